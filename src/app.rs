@@ -0,0 +1,359 @@
+use crate::config::Config;
+use crate::health_check::{
+    circuit_breaker::CircuitBreakerHealthChecker,
+    history::StatusHistory,
+    maintenance::{
+        CachedMaintenanceStore, LocalMaintenanceStore, MaintenanceHealthChecker, MaintenanceStore,
+    },
+    polling::PollingHealthChecker,
+    registry::{DependencyRegistry, RegistryError},
+    single_flight::SingleFlightHealthChecker,
+    uptime::{self, UptimeStore},
+    version::{Environment, VersionFromFile, Versioned},
+    webhook::WebhookNotifier,
+    DependencyHealthChecker, HealthChecker, RusticSketchHealthChecker,
+};
+use crate::routes::{admin, error::with_json_errors, health_status};
+use crate::shutdown::{Shutdown, ShutdownHandle, ShutdownSummary};
+use crate::store::postgres::{PostgresStore, PostgresStoreError};
+use crate::store::Store;
+use derive_more::Display;
+use derive_more::Error;
+use log::info;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use warp::reject::Rejection;
+use warp::reply::Reply;
+use warp::Filter;
+
+/// How the service is known to others, e.g. in the `/status` of services depending on it.
+pub const SERVICE_NAME: &str = "rustic-sketch";
+
+/// The composed service: its routes plus everything needed to run and stop them.
+pub struct App {
+    config: Config,
+    health_checker: Arc<dyn HealthChecker + Send + Sync>,
+    history: StatusHistory,
+    uptime_store: Option<Arc<dyn UptimeStore + Send + Sync>>,
+    maintenance_store: Arc<dyn MaintenanceStore + Send + Sync>,
+    store: Option<Arc<dyn Store + Send + Sync>>,
+    shutdown: Shutdown,
+}
+impl App {
+    pub fn builder(config: Config) -> AppBuilder {
+        AppBuilder::new(config)
+    }
+
+    /// The service as configured, connected to its database, see `AppBuilder::database`.
+    pub async fn from_config(config: Config) -> Result<AppBuilder, AppError> {
+        let store = PostgresStore::new(config.database().clone())
+            .await
+            .map_err(AppError::Store)?;
+        App::builder(config).database(store).await
+    }
+
+    pub fn routes(&self) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let hello_route = warp::path!("hello" / String).map(|name| format!("Hello, {}!", name));
+
+        with_json_errors(
+            health_status::routes(
+                self.health_checker.clone(),
+                self.history.clone(),
+                self.uptime_store.clone(),
+                self.shutdown.readiness(),
+                self.shutdown.handle(),
+            )
+            .or(admin::routes(
+                self.config
+                    .admin()
+                    .as_ref()
+                    .map(|admin| admin.token().clone()),
+                self.maintenance_store.clone(),
+            ))
+            .or(hello_route),
+        )
+        // TODO any origins for now
+        .with(warp::cors().allow_any_origin())
+    }
+
+    /// Binds to the configured address and serves requests in the background until shut down.
+    pub fn start(self) -> Result<ServerHandle, warp::Error> {
+        let (address, server) = warp::serve(self.routes()).try_bind_with_graceful_shutdown(
+            self.config.server().address(),
+            self.shutdown.signal(),
+        )?;
+        info!("Listening on {address}");
+
+        let shutdown = self.shutdown.handle();
+        let stopped = tokio::spawn(self.shutdown.drain(server, self.store));
+        Ok(ServerHandle {
+            address,
+            shutdown,
+            stopped,
+        })
+    }
+}
+
+pub struct AppBuilder {
+    config: Config,
+    versioned: Option<Box<dyn Versioned + Send + Sync>>,
+    dependency_health_checkers: Vec<Box<dyn DependencyHealthChecker + Send + Sync>>,
+    uptime_store: Option<Arc<dyn UptimeStore + Send + Sync>>,
+    maintenance_store: Option<Arc<dyn MaintenanceStore + Send + Sync>>,
+    store: Option<Arc<dyn Store + Send + Sync>>,
+}
+impl AppBuilder {
+    pub fn new(config: Config) -> Self {
+        AppBuilder {
+            config,
+            versioned: None,
+            dependency_health_checkers: Vec::new(),
+            uptime_store: None,
+            maintenance_store: None,
+            store: None,
+        }
+    }
+
+    /// Defaults to the version file and environment set in the config.
+    pub fn versioned(mut self, versioned: impl Versioned + Send + Sync + 'static) -> Self {
+        self.versioned = Some(Box::new(versioned));
+        self
+    }
+
+    pub fn dependency_health_checker(
+        mut self,
+        checker: impl DependencyHealthChecker + Send + Sync + 'static,
+    ) -> Self {
+        self.dependency_health_checkers.push(Box::new(checker));
+        self
+    }
+
+    /// Appends checkers, e.g. the ones built from config by `DependencyRegistry`.
+    pub fn dependency_health_checkers(
+        mut self,
+        checkers: Vec<Box<dyn DependencyHealthChecker + Send + Sync>>,
+    ) -> Self {
+        self.dependency_health_checkers.extend(checkers);
+        self
+    }
+
+    /// Samples the status of the service into the store, reported at `/status/uptime`.
+    /// Only given when `health.uptime.enabled` is set, see `database`.
+    pub fn uptime_store(mut self, uptime_store: impl UptimeStore + Send + Sync + 'static) -> Self {
+        self.uptime_store = Some(Arc::new(uptime_store));
+        self
+    }
+
+    /// Defaults to keeping maintenance in memory, so it only applies to this instance.
+    pub fn maintenance_store(
+        mut self,
+        maintenance_store: impl MaintenanceStore + Send + Sync + 'static,
+    ) -> Self {
+        self.maintenance_store = Some(Arc::new(maintenance_store));
+        self
+    }
+
+    /// The store is closed once the server shuts down.
+    pub fn store(mut self, store: impl Store + Send + Sync + 'static) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Checks the dependencies declared in config, `postgres` ones against the database,
+    /// which also keeps uptime samples and maintenance when enabled, once their tables are prepared.
+    pub async fn database(self, store: PostgresStore) -> Result<Self, AppError> {
+        let health = self.config.health();
+        let store = match health.database_deep_check() {
+            Some(deep_check) => store.with_deep_check(deep_check.clone()),
+            None => store,
+        };
+        let uptime_enabled = *health.uptime().enabled();
+        if uptime_enabled {
+            store.prepare_uptime().await.map_err(AppError::Store)?;
+        }
+        let persist_maintenance = self
+            .config
+            .admin()
+            .as_ref()
+            .is_some_and(|admin| *admin.persist_maintenance());
+        if persist_maintenance {
+            store.prepare_maintenance().await.map_err(AppError::Store)?;
+        }
+        let dependency_health_checkers = DependencyRegistry::new(SERVICE_NAME)
+            .with_database(store.clone())
+            .build(health.dependencies())
+            .map_err(AppError::Registry)?;
+
+        let builder = self.dependency_health_checkers(dependency_health_checkers);
+        let builder = if uptime_enabled {
+            builder.uptime_store(store.clone())
+        } else {
+            builder
+        };
+        let builder = if persist_maintenance {
+            builder.maintenance_store(store.clone())
+        } else {
+            builder
+        };
+        Ok(builder.store(store))
+    }
+
+    /// Starts polling dependencies, so it must be called within a Tokio runtime.
+    pub fn build(self) -> App {
+        let versioned = self.versioned.unwrap_or_else(|| {
+            Box::new(VersionFromFile::new(
+                Environment::new(self.config.env().clone()),
+                self.config.version_file().clone(),
+            ))
+        });
+        let health = self.config.health();
+        let timeouts = health.timeouts();
+        let mut dependency_health_checkers = self.dependency_health_checkers;
+        dependency_health_checkers.extend(
+            health
+                .self_checks()
+                .checkers()
+                .expect("Self-checks are validated with the config"),
+        );
+        let dependency_health_checkers = match health.circuit_breaker() {
+            Some(policy) => dependency_health_checkers
+                .into_iter()
+                .map(|checker| {
+                    let timeout = timeouts.for_dependency(checker.dependency().name());
+                    Box::new(
+                        CircuitBreakerHealthChecker::new(checker, policy.clone())
+                            .with_timeout(timeout),
+                    ) as Box<dyn DependencyHealthChecker + Send + Sync>
+                })
+                .collect(),
+            None => dependency_health_checkers,
+        };
+        let health_checker = health.criticalities().into_iter().fold(
+            RusticSketchHealthChecker::new(versioned, dependency_health_checkers)
+                .with_timeouts(timeouts)
+                .with_aggregation(health.aggregation().clone()),
+            |health_checker, (name, criticality)| {
+                health_checker.with_criticality(&name, criticality)
+            },
+        );
+        let polling = PollingHealthChecker::start(health_checker, &health.intervals());
+        let changes = polling.changes();
+        let maintenance_store = self
+            .maintenance_store
+            .unwrap_or_else(|| Arc::new(LocalMaintenanceStore::default()));
+        // so a persisted maintenance isn't read from the database on every request
+        let maintenance_store = Arc::new(CachedMaintenanceStore::new(
+            maintenance_store,
+            *health.poll_interval(),
+        ));
+        maintenance_store.start_refreshing();
+        // applied before history follows, so entering and leaving maintenance shows in history,
+        // the status stream, webhooks and uptime
+        let health_checker = Arc::new(MaintenanceHealthChecker::new(
+            polling,
+            maintenance_store.clone(),
+        ));
+        let history = StatusHistory::new(*health.history_size());
+        history.follow(
+            Arc::downgrade(&health_checker),
+            either(changes, maintenance_store.changes()),
+        );
+        for webhook in health.webhooks() {
+            WebhookNotifier::new(webhook)
+                .expect("Webhooks are validated with the config")
+                .start(history.subscribe());
+        }
+        if let Some(uptime_store) = &self.uptime_store {
+            uptime::start_sampling(
+                uptime_store.clone(),
+                Arc::downgrade(&health_checker),
+                health.uptime().clone(),
+            );
+        }
+        let health_checker = SingleFlightHealthChecker::new(health_checker);
+        let health_checker = match health.reuse_window() {
+            Some(window) => health_checker.with_reuse_window(*window),
+            None => health_checker,
+        };
+        let shutdown = Shutdown::new(*self.config.server().shutdown_deadline());
+
+        App {
+            config: self.config,
+            health_checker: Arc::new(health_checker),
+            history,
+            uptime_store: self.uptime_store,
+            maintenance_store,
+            store: self.store,
+            shutdown,
+        }
+    }
+}
+
+// Signals whenever either does, until either is closed
+fn either(mut a: watch::Receiver<()>, mut b: watch::Receiver<()>) -> watch::Receiver<()> {
+    let (sender, receiver) = watch::channel(());
+    tokio::spawn(async move {
+        loop {
+            let changed = tokio::select! {
+                changed = a.changed() => changed,
+                changed = b.changed() => changed,
+            };
+            if changed.is_err() || sender.send(()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+#[derive(Debug, Display, Error)]
+pub enum AppError {
+    #[display("{_0}")]
+    Store(#[error(source)] PostgresStoreError),
+
+    #[display("{_0}")]
+    Registry(#[error(source)] RegistryError),
+}
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Store(e) => e.code(),
+            AppError::Registry(e) => e.code(),
+        }
+    }
+}
+
+/// A running server.
+pub struct ServerHandle {
+    address: SocketAddr,
+    shutdown: ShutdownHandle,
+    stopped: JoinHandle<ShutdownSummary>,
+}
+impl ServerHandle {
+    /// The address the server is bound to, handy when the config asks for an ephemeral port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Shuts the server down on SIGTERM or SIGINT.
+    pub fn listen_for_signals(&self) {
+        self.shutdown.listen_for_signals()
+    }
+
+    /// Triggers shutdown and waits for it to complete.
+    pub async fn shutdown(self) -> ShutdownSummary {
+        self.shutdown.trigger();
+        self.stopped().await
+    }
+
+    /// Waits for the server to be shut down by other means, e.g. a signal.
+    pub async fn stopped(self) -> ShutdownSummary {
+        self.stopped.await.expect("Server shutdown task panicked")
+    }
+}
@@ -0,0 +1,1134 @@
+use crate::app::SERVICE_NAME;
+use crate::health_check::{
+    aggregation::Aggregation,
+    circuit_breaker::BreakerPolicy,
+    polling::Intervals,
+    registry::{CheckConfig, DependencyConfig, DependencyRegistry},
+    resources::SelfChecksConfig,
+    service_status::Criticality,
+    timeouts::Timeouts,
+    uptime::UptimeConfig,
+    webhook::{WebhookConfig, WebhookNotifier},
+};
+use crate::store::postgres::{DatabaseConfig, DeepCheckConfig};
+use clap::Parser;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const ENV_PREFIX: &str = "RUSTIC";
+
+/// The runtime configuration of the service.
+///
+/// Layers are merged in the following order, each one overriding the previous:
+///  1. defaults (the values used by the `dev` stack);
+///  2. a TOML config file, if one is given with `--config` (or `RUSTIC_CONFIG`);
+///  3. `RUSTIC_*` environment variables, using `__` to separate nested keys, e.g. `RUSTIC_SERVER__PORT`;
+///  4. command line flags.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct Config {
+    env: String,
+    version_file: String,
+    server: ServerConfig,
+    health: HealthConfig,
+    database: DatabaseConfig,
+    // serves the admin routes when set, e.g. `RUSTIC_ADMIN__TOKEN`
+    #[serde(default)]
+    admin: Option<AdminConfig>,
+}
+impl Config {
+    pub fn load(args: &CliArgs) -> Result<Config, ConfigError> {
+        Config::load_from(args, std::env::vars().collect())
+    }
+
+    // Environment variables are passed in explicitly so tests don't need to mutate the process environment
+    fn load_from(args: &CliArgs, env_vars: HashMap<String, String>) -> Result<Config, ConfigError> {
+        let mut builder = config::Config::builder()
+            .set_default("env", "dev")?
+            .set_default("version_file", "rustic.version")?
+            .set_default("server.host", "0.0.0.0")?
+            .set_default("server.port", 3030)?
+            // below docker's default stop timeout (10s), after which the process gets killed
+            .set_default("server.shutdown_deadline", "8s")?
+            .set_default("health.timeout", "5s")?
+            .set_default("health.dependency_timeout", "2s")?
+            .set_default("health.poll_interval", "10s")?
+            .set_default("database.host", "rustic-database")?
+            .set_default("database.port", 5432)?
+            .set_default("database.name", "rustic-sketch")?
+            .set_default("database.user", "rustic-sketch.dev")?
+            .set_default("database.password", "rustic-sketch.pw")?
+            .set_default("database.db_pool_threads", 5)?;
+
+        if let Some(path) = &args.config {
+            builder = builder.add_source(config::File::from(path.as_path()).required(true));
+        }
+
+        let config = builder
+            .add_source(
+                config::Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("_")
+                    .separator("__")
+                    .try_parsing(true)
+                    .source(Some(env_vars)),
+            )
+            .set_override_option("env", args.env.clone())?
+            .set_override_option("version_file", args.version_file.clone())?
+            .set_override_option("server.host", args.host.map(|h| h.to_string()))?
+            .set_override_option("server.port", args.port)?
+            .set_override_option("server.shutdown_deadline", args.shutdown_deadline.clone())?
+            .set_override_option("database.host", args.db_host.clone())?
+            .set_override_option("database.port", args.db_port)?
+            .set_override_option("database.name", args.db_name.clone())?
+            .set_override_option("database.user", args.db_user.clone())?
+            .set_override_option("database.db_pool_threads", args.db_pool_threads)?
+            .build()?
+            .try_deserialize::<Config>()?;
+
+        config.validate()
+    }
+
+    fn validate(self) -> Result<Config, ConfigError> {
+        let non_empty = [
+            ("env", self.env.as_str()),
+            ("version_file", self.version_file.as_str()),
+            ("database.host", self.database.host().as_str()),
+            ("database.name", self.database.name().as_str()),
+            ("database.user", self.database.user().as_str()),
+        ];
+        if let Some((key, _)) = non_empty.iter().find(|(_, value)| value.trim().is_empty()) {
+            return Err(ConfigError::invalid(key, "must not be empty"));
+        }
+        if self.health.poll_interval.is_zero()
+            || self.health.poll_intervals.values().any(|i| i.is_zero())
+        {
+            return Err(ConfigError::invalid(
+                "health.poll_interval",
+                "must be greater than 0",
+            ));
+        }
+        // a zero timeout times every check out
+        if self.health.timeout.is_zero() {
+            return Err(ConfigError::invalid(
+                "health.timeout",
+                "must be greater than 0",
+            ));
+        }
+        if self.health.dependency_timeout.is_zero()
+            || self
+                .health
+                .dependency_timeouts
+                .values()
+                .any(|t| t.is_zero())
+        {
+            return Err(ConfigError::invalid(
+                "health.dependency_timeout",
+                "must be greater than 0",
+            ));
+        }
+        if let Some(deep_check) = &self.health.database_deep_check {
+            let saturation = *deep_check.max_pool_saturation();
+            if !(saturation > 0.0 && saturation <= 1.0) {
+                return Err(ConfigError::invalid(
+                    "health.database_deep_check.max_pool_saturation",
+                    "must be greater than 0 and at most 1",
+                ));
+            }
+        }
+        if self.health.history_size == 0 {
+            return Err(ConfigError::invalid(
+                "health.history_size",
+                "must be at least 1",
+            ));
+        }
+        let uptime = &self.health.uptime;
+        if [
+            uptime.sample_interval(),
+            uptime.downsample_after(),
+            uptime.compact_interval(),
+        ]
+        .iter()
+        .any(|d| d.is_zero())
+            || uptime.retention() < uptime.downsample_after()
+        {
+            return Err(ConfigError::invalid(
+                "health.uptime",
+                "intervals must be greater than 0 and retention at least downsample_after",
+            ));
+        }
+        if let Some(policy) = &self.health.circuit_breaker {
+            if *policy.failure_threshold() == 0 || *policy.success_threshold() == 0 {
+                return Err(ConfigError::invalid(
+                    "health.circuit_breaker",
+                    "thresholds must be at least 1",
+                ));
+            }
+            if policy.open_for().is_zero() || policy.max_open_for() < policy.open_for() {
+                return Err(ConfigError::invalid(
+                    "health.circuit_breaker.open_for",
+                    "must be greater than 0 and at most max_open_for",
+                ));
+            }
+        }
+        if let Err(e) = self.health.aggregation.validate() {
+            return Err(ConfigError::invalid("health.aggregation", &e.to_string()));
+        }
+        for webhook in &self.health.webhooks {
+            if let Err(e) = WebhookNotifier::new(webhook) {
+                return Err(ConfigError::invalid("health.webhooks", &e.to_string()));
+            }
+        }
+        if let Err(e) = self.health.self_checks.checkers() {
+            return Err(ConfigError::invalid("health.self_checks", &e.to_string()));
+        }
+        if let Err(e) = DependencyRegistry::new(SERVICE_NAME).validate(&self.health.dependencies) {
+            return Err(ConfigError::invalid("health.dependencies", &e.to_string()));
+        }
+        // self-checks are reported next to declared dependencies, so their names must not clash
+        let self_checks = self.health.self_checks.checkers().unwrap_or_default();
+        let clashing = self_checks
+            .iter()
+            .map(|checker| checker.dependency().name().to_string())
+            .find(|name| self.health.dependencies.iter().any(|d| d.name() == name));
+        if let Some(name) = clashing {
+            return Err(ConfigError::invalid(
+                "health.dependencies",
+                &format!("Dependency `{name}` is declared more than once"),
+            ));
+        }
+        // settings by dependency name must refer to a dependency, so typos don't go unnoticed
+        let known: HashSet<String> = self
+            .health
+            .dependencies
+            .iter()
+            .map(|d| d.name().to_string())
+            .chain(
+                self_checks
+                    .iter()
+                    .map(|checker| checker.dependency().name().to_string()),
+            )
+            .collect();
+        let by_name = [
+            ("health.criticality", sorted(self.health.criticality.keys())),
+            (
+                "health.poll_intervals",
+                sorted(self.health.poll_intervals.keys()),
+            ),
+            (
+                "health.dependency_timeouts",
+                sorted(self.health.dependency_timeouts.keys()),
+            ),
+        ];
+        let grouped = self
+            .health
+            .aggregation
+            .groups()
+            .iter()
+            .enumerate()
+            .map(|(i, group)| {
+                (
+                    format!("health.aggregation.groups[{i}].dependencies"),
+                    group.dependencies().iter().collect(),
+                )
+            });
+        let referencing = by_name
+            .into_iter()
+            .map(|(key, names)| (key.to_string(), names))
+            .chain(grouped);
+        for (key, names) in referencing {
+            if let Some(name) = names.into_iter().find(|name| !known.contains(*name)) {
+                return Err(ConfigError::invalid(
+                    &key,
+                    &format!("Unknown dependency `{name}`: must be declared in health.dependencies or be a self-check"),
+                ));
+            }
+        }
+        if let Some(admin) = &self.admin {
+            if admin.token.trim().is_empty() {
+                return Err(ConfigError::invalid("admin.token", "must not be empty"));
+            }
+        }
+        if *self.database.port() == 0 {
+            return Err(ConfigError::invalid("database.port", "must not be 0"));
+        }
+        if *self.database.db_pool_threads() == 0 {
+            return Err(ConfigError::invalid(
+                "database.db_pool_threads",
+                "must be at least 1",
+            ));
+        }
+        Ok(self)
+    }
+}
+
+fn sorted<'a>(names: impl Iterator<Item = &'a String>) -> Vec<&'a String> {
+    let mut names: Vec<_> = names.collect();
+    names.sort();
+    names
+}
+
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct ServerConfig {
+    host: IpAddr,
+    // 0 binds to an ephemeral port
+    port: u16,
+    // how long in-flight requests have to complete once shutdown is triggered, e.g. "8s"
+    #[serde(with = "humantime_serde")]
+    shutdown_deadline: Duration,
+}
+impl ServerConfig {
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+}
+
+#[derive(Clone, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct AdminConfig {
+    // expected as `Authorization: Bearer <token>`
+    token: String,
+    // keeps maintenance in the database, so it applies to every instance rather than this one only
+    #[serde(default)]
+    persist_maintenance: bool,
+}
+// Not derived, so the token doesn't end up in logs
+impl std::fmt::Debug for AdminConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdminConfig")
+            .field("token", &"***")
+            .field("persist_maintenance", &self.persist_maintenance)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct HealthConfig {
+    // bounds how long `/status` waits for all dependencies
+    #[serde(with = "humantime_serde")]
+    timeout: Duration,
+    // bounds each dependency check, unless overridden below
+    #[serde(with = "humantime_serde")]
+    dependency_timeout: Duration,
+    // by dependency name, e.g. `RUSTIC_HEALTH__DEPENDENCY_TIMEOUTS__DATABASE=1s`
+    #[serde(default)]
+    dependency_timeouts: HashMap<String, humantime_serde::Serde<Duration>>,
+    // how often dependencies are checked in the background, unless overridden below
+    #[serde(with = "humantime_serde")]
+    poll_interval: Duration,
+    // by dependency name, e.g. `RUSTIC_HEALTH__POLL_INTERVALS__DATABASE=5s`
+    #[serde(default)]
+    poll_intervals: HashMap<String, humantime_serde::Serde<Duration>>,
+    // by dependency name, `critical` unless set to `optional`, e.g. `RUSTIC_HEALTH__CRITICALITY__SNITCH=optional`
+    #[serde(default)]
+    criticality: HashMap<String, Criticality>,
+    // looks into replication, pool saturation, migrations and long-running transactions when set,
+    // e.g. `[health.database_deep_check]`
+    #[serde(default)]
+    database_deep_check: Option<DeepCheckConfig>,
+    // how long the outcome of a live check is shared with subsequent requests, e.g. "1s"
+    #[serde(default, with = "humantime_serde")]
+    reuse_window: Option<Duration>,
+    // suppresses flapping of every dependency when set, e.g. `[health.circuit_breaker]`
+    #[serde(default)]
+    circuit_breaker: Option<BreakerPolicy>,
+    // how many status transitions `/status/history` keeps
+    #[serde(default = "default_history_size")]
+    history_size: usize,
+    // whether uptime samples are taken, how often and how long they are kept
+    #[serde(default)]
+    uptime: UptimeConfig,
+    // how the statuses of dependencies add up to the overall status, all of them must be Ok by default,
+    // e.g. `[[health.aggregation.groups]]`
+    #[serde(default)]
+    aggregation: Aggregation,
+    // notified of every status change, e.g. `[[health.webhooks]]`
+    #[serde(default)]
+    webhooks: Vec<WebhookConfig>,
+    // the service's own resources, reported next to its dependencies, e.g. `[health.self_checks.disk]`
+    #[serde(default)]
+    self_checks: SelfChecksConfig,
+    // the dependencies to check, in the order they are reported, e.g. `[[health.dependencies]]`;
+    // only the service's own database when not set
+    #[serde(default = "default_dependencies")]
+    dependencies: Vec<DependencyConfig>,
+}
+
+fn default_history_size() -> usize {
+    100
+}
+
+fn default_dependencies() -> Vec<DependencyConfig> {
+    vec![DependencyConfig::new("database", CheckConfig::Postgres)]
+}
+impl HealthConfig {
+    /// Intervals declared with dependencies take precedence over `poll_intervals`.
+    pub fn intervals(&self) -> Intervals {
+        let declared = self
+            .dependencies
+            .iter()
+            .filter_map(|dependency| Some((dependency.name(), (*dependency.interval())?)));
+        self.poll_intervals
+            .iter()
+            .map(|(name, interval)| (name, **interval))
+            .chain(declared)
+            .fold(
+                Intervals::new(self.poll_interval),
+                |intervals, (name, interval)| intervals.dependency_override(name, interval),
+            )
+    }
+
+    /// Timeouts declared with dependencies take precedence over `dependency_timeouts`.
+    pub fn timeouts(&self) -> Timeouts {
+        let declared = self
+            .dependencies
+            .iter()
+            .filter_map(|dependency| Some((dependency.name(), (*dependency.timeout())?)));
+        self.dependency_timeouts
+            .iter()
+            .map(|(name, timeout)| (name, **timeout))
+            .chain(declared)
+            .fold(
+                Timeouts::default()
+                    .overall(self.timeout)
+                    .dependency(self.dependency_timeout),
+                |timeouts, (name, timeout)| timeouts.dependency_override(name, timeout),
+            )
+    }
+
+    /// By dependency name, the criticality declared with dependencies taking precedence over `criticality`.
+    pub fn criticalities(&self) -> HashMap<String, Criticality> {
+        let declared = self.dependencies.iter().filter_map(|dependency| {
+            Some((dependency.name().clone(), (*dependency.criticality())?))
+        });
+        self.criticality
+            .clone()
+            .into_iter()
+            .chain(declared)
+            .collect()
+    }
+}
+
+/// Command line flags, the layer with the highest precedence.
+#[derive(Debug, Default, Parser)]
+#[command(version, about)]
+pub struct CliArgs {
+    /// Path to a TOML config file
+    #[arg(long, env = "RUSTIC_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Environment the service runs in, e.g. `dev` or `prd`
+    #[arg(long)]
+    pub env: Option<String>,
+
+    /// Path to the file containing the build number and commit hash
+    #[arg(long)]
+    pub version_file: Option<String>,
+
+    #[arg(long)]
+    pub host: Option<IpAddr>,
+
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// How long in-flight requests have to complete on shutdown, e.g. `8s`
+    #[arg(long)]
+    pub shutdown_deadline: Option<String>,
+
+    #[arg(long)]
+    pub db_host: Option<String>,
+
+    #[arg(long)]
+    pub db_port: Option<u16>,
+
+    #[arg(long)]
+    pub db_name: Option<String>,
+
+    #[arg(long)]
+    pub db_user: Option<String>,
+
+    // No flag for the password on purpose: it would be visible in the process list.
+    #[arg(long)]
+    pub db_pool_threads: Option<u32>,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum ConfigError {
+    #[display("Failed to load config: {_0}")]
+    Load(config::ConfigError),
+
+    #[display("Invalid config `{key}`: {reason}")]
+    Invalid { key: String, reason: String },
+}
+impl ConfigError {
+    fn invalid(key: &str, reason: &str) -> Self {
+        ConfigError::Invalid {
+            key: key.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+impl From<config::ConfigError> for ConfigError {
+    fn from(value: config::ConfigError) -> Self {
+        ConfigError::Load(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::aggregation::{AggregationPolicy, DependencyGroup};
+    use claims::{assert_err, assert_matches, assert_ok};
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn defaults_to_dev_stack() {
+        let config = Config::load_from(&CliArgs::default(), HashMap::new()).unwrap();
+
+        assert_eq!(config.env(), "dev");
+        assert_eq!(config.version_file(), "rustic.version");
+        assert_eq!(
+            config.server().address(),
+            SocketAddr::from(([0, 0, 0, 0], 3030))
+        );
+        assert_eq!(*config.server().shutdown_deadline(), Duration::from_secs(8));
+        assert_eq!(config.database().host(), "rustic-database");
+        assert_eq!(*config.database().db_pool_threads(), 5);
+        assert_eq!(
+            config.health().timeouts().for_dependency("database"),
+            Some(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn layers_override_each_other_in_order() {
+        let file = config_file_with(
+            "layers.toml",
+            r#"
+            env = "prd"
+            [server]
+            port = 8080
+            [health.dependency_timeouts]
+            database = "1s"
+            [health.poll_intervals]
+            database = "3s"
+            [health.criticality]
+            snitch = "optional"
+            [health.database_deep_check]
+            allow_replica = true
+            max_replication_lag = "1m"
+            [[health.dependencies]]
+            name = "database"
+            kind = "postgres"
+            [[health.dependencies]]
+            name = "snitch"
+            kind = "downstream-status"
+            url = "http://snitch:3030/status"
+            [database]
+            host = "file-host"
+            port = 6543
+            "#,
+        );
+        let env_vars = HashMap::from([
+            ("RUSTIC_SERVER__PORT".to_string(), "9090".to_string()),
+            (
+                "RUSTIC_SERVER__SHUTDOWN_DEADLINE".to_string(),
+                "500ms".to_string(),
+            ),
+            ("RUSTIC_DATABASE__HOST".to_string(), "env-host".to_string()),
+        ]);
+        let args = CliArgs {
+            config: Some(file.path()),
+            db_host: Some("cli-host".to_string()),
+            ..CliArgs::default()
+        };
+
+        let config = Config::load_from(&args, env_vars).unwrap();
+
+        assert_eq!(config.env(), "prd"); // file
+        assert_eq!(
+            config.health().timeouts().for_dependency("database"),
+            Some(Duration::from_secs(1))
+        ); // file
+        assert_eq!(
+            config.health().intervals().for_dependency("database"),
+            Duration::from_secs(3)
+        ); // file
+        assert_eq!(
+            config.health().criticality().get("snitch"),
+            Some(&Criticality::Optional)
+        ); // file
+        assert_eq!(
+            config
+                .health()
+                .database_deep_check()
+                .as_ref()
+                .map(|c| *c.max_replication_lag()),
+            Some(Duration::from_secs(60))
+        ); // file
+        assert_eq!(*config.database().port(), 6543); // file
+        assert_eq!(*config.server().port(), 9090); // env var
+        assert_eq!(
+            *config.server().shutdown_deadline(),
+            Duration::from_millis(500)
+        ); // env var
+        assert_eq!(config.database().host(), "cli-host"); // cli flag
+    }
+
+    #[test]
+    fn fails_when_config_file_is_missing() {
+        let args = CliArgs {
+            config: Some(PathBuf::from("unknown.config.toml")),
+            ..CliArgs::default()
+        };
+
+        let result = Config::load_from(&args, HashMap::new());
+
+        assert_matches!(result, Err(ConfigError::Load(_)));
+    }
+
+    #[test]
+    fn fails_when_value_has_wrong_type() {
+        let env_vars = HashMap::from([("RUSTIC_SERVER__PORT".to_string(), "http".to_string())]);
+
+        let result = Config::load_from(&CliArgs::default(), env_vars);
+
+        assert_err!(result);
+    }
+
+    #[test]
+    fn samples_uptime_only_once_enabled() {
+        let env_vars = HashMap::from([(
+            "RUSTIC_HEALTH__UPTIME__ENABLED".to_string(),
+            "true".to_string(),
+        )]);
+
+        let default = Config::load_from(&CliArgs::default(), HashMap::new()).unwrap();
+        let enabled = Config::load_from(&CliArgs::default(), env_vars).unwrap();
+
+        assert!(!default.health().uptime().enabled());
+        assert!(enabled.health().uptime().enabled());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let args = CliArgs {
+            db_pool_threads: Some(0),
+            ..CliArgs::default()
+        };
+
+        let result = Config::load_from(&args, HashMap::new());
+
+        assert_matches!(
+            result,
+            Err(ConfigError::Invalid { key, .. }) if key == "database.db_pool_threads"
+        );
+    }
+
+    #[test]
+    fn rejects_circuit_breaker_without_thresholds() {
+        let env_vars = HashMap::from([(
+            "RUSTIC_HEALTH__CIRCUIT_BREAKER__FAILURE_THRESHOLD".to_string(),
+            "0".to_string(),
+        )]);
+
+        let result = Config::load_from(&CliArgs::default(), env_vars);
+
+        assert_matches!(
+            result,
+            Err(ConfigError::Invalid { key, .. }) if key == "health.circuit_breaker"
+        );
+    }
+
+    #[test]
+    fn rejects_zero_timeouts() {
+        let test_cases = [
+            ("RUSTIC_HEALTH__TIMEOUT", "health.timeout"),
+            (
+                "RUSTIC_HEALTH__DEPENDENCY_TIMEOUT",
+                "health.dependency_timeout",
+            ),
+            (
+                "RUSTIC_HEALTH__DEPENDENCY_TIMEOUTS__DATABASE",
+                "health.dependency_timeout",
+            ),
+        ];
+
+        for (env_var, expected_key) in test_cases {
+            let env_vars = HashMap::from([(env_var.to_string(), "0s".to_string())]);
+
+            let result = Config::load_from(&CliArgs::default(), env_vars);
+
+            assert_matches!(
+                result,
+                Err(ConfigError::Invalid { key, .. }) if key == expected_key,
+                "{env_var}"
+            );
+        }
+    }
+
+    #[test]
+    fn debug_redacts_admin_token() {
+        let env_vars = HashMap::from([("RUSTIC_ADMIN__TOKEN".to_string(), "s3cr3t".to_string())]);
+
+        let config = Config::load_from(&CliArgs::default(), env_vars).unwrap();
+
+        assert!(config.admin().is_some());
+        assert!(!format!("{config:?}").contains("s3cr3t"));
+    }
+
+    #[test]
+    fn rejects_empty_admin_token() {
+        let env_vars = HashMap::from([("RUSTIC_ADMIN__TOKEN".to_string(), " ".to_string())]);
+
+        let result = Config::load_from(&CliArgs::default(), env_vars);
+
+        assert_matches!(
+            result,
+            Err(ConfigError::Invalid { key, .. }) if key == "admin.token"
+        );
+    }
+
+    #[test]
+    fn loads_aggregation_groups() {
+        let file = config_file_with(
+            "aggregation.toml",
+            r#"
+            [health.aggregation.policy]
+            kind = "critical-only"
+
+            [[health.aggregation.groups]]
+            name = "replicas"
+            dependencies = ["replica-1", "replica-2", "replica-3"]
+            policy = { kind = "quorum", min_ok = 2 }
+
+            [[health.dependencies]]
+            name = "replica-1"
+            kind = "tcp"
+            address = "replica-1:5432"
+
+            [[health.dependencies]]
+            name = "replica-2"
+            kind = "tcp"
+            address = "replica-2:5432"
+
+            [[health.dependencies]]
+            name = "replica-3"
+            kind = "tcp"
+            address = "replica-3:5432"
+            "#,
+        );
+        let args = CliArgs {
+            config: Some(file.path()),
+            ..CliArgs::default()
+        };
+
+        let config = Config::load_from(&args, HashMap::new()).unwrap();
+
+        assert_eq!(
+            *config.health().aggregation(),
+            Aggregation::new(AggregationPolicy::CriticalOnly).with_group(DependencyGroup::new(
+                "replicas",
+                &["replica-1", "replica-2", "replica-3"],
+                AggregationPolicy::Quorum { min_ok: 2 },
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_unreachable_quorum() {
+        let file = config_file_with(
+            "unreachable-quorum.toml",
+            r#"
+            [[health.aggregation.groups]]
+            name = "replicas"
+            dependencies = ["replica-1", "replica-2"]
+            policy = { kind = "quorum", min_ok = 3 }
+            "#,
+        );
+        let args = CliArgs {
+            config: Some(file.path()),
+            ..CliArgs::default()
+        };
+
+        let result = Config::load_from(&args, HashMap::new());
+
+        assert_matches!(
+            result,
+            Err(ConfigError::Invalid { key, .. }) if key == "health.aggregation"
+        );
+    }
+
+    #[test]
+    fn rejects_webhook_with_invalid_url() {
+        let file = config_file_with(
+            "invalid-webhook.toml",
+            r#"
+            [[health.webhooks]]
+            url = "hooks.example.com/status"
+            secret = "whsec_c0ffee"
+            "#,
+        );
+        let args = CliArgs {
+            config: Some(file.path()),
+            ..CliArgs::default()
+        };
+
+        let result = Config::load_from(&args, HashMap::new());
+
+        assert_matches!(
+            result,
+            Err(ConfigError::Invalid { key, .. }) if key == "health.webhooks"
+        );
+    }
+
+    #[test]
+    fn rejects_webhook_without_timeout() {
+        let file = config_file_with(
+            "webhook-without-timeout.toml",
+            r#"
+            [[health.webhooks]]
+            url = "https://hooks.example.com/status"
+            secret = "whsec_c0ffee"
+            timeout = "0s"
+            "#,
+        );
+        let args = CliArgs {
+            config: Some(file.path()),
+            ..CliArgs::default()
+        };
+
+        let result = Config::load_from(&args, HashMap::new());
+
+        assert_matches!(
+            result,
+            Err(ConfigError::Invalid { key, reason }) if key == "health.webhooks" && reason.contains("timeout")
+        );
+    }
+
+    #[test]
+    fn loads_self_checks() {
+        let file = config_file_with(
+            "self-checks.toml",
+            r#"
+            [health.self_checks.disk]
+            path = "/var/lib/rustic"
+            degraded_at = 0.8
+
+            [health.self_checks.memory]
+            budget_mib = 512
+
+            [health.self_checks.runtime]
+            "#,
+        );
+        let args = CliArgs {
+            config: Some(file.path()),
+            ..CliArgs::default()
+        };
+
+        let config = Config::load_from(&args, HashMap::new()).unwrap();
+
+        let self_checks = config.health().self_checks();
+        let disk = self_checks.disk().as_ref().unwrap();
+        assert_eq!(*disk.degraded_at(), 0.8);
+        assert_eq!(*disk.down_at(), 0.98);
+        assert_eq!(*self_checks.memory().as_ref().unwrap().budget_mib(), 512);
+        assert!(self_checks.file_descriptors().is_none());
+        assert_eq!(self_checks.checkers().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn rejects_self_check_thresholds_out_of_order() {
+        let env_vars = HashMap::from([
+            (
+                "RUSTIC_HEALTH__SELF_CHECKS__FILE_DESCRIPTORS__DEGRADED_AT".to_string(),
+                "0.9".to_string(),
+            ),
+            (
+                "RUSTIC_HEALTH__SELF_CHECKS__FILE_DESCRIPTORS__DOWN_AT".to_string(),
+                "0.5".to_string(),
+            ),
+        ]);
+
+        let result = Config::load_from(&CliArgs::default(), env_vars);
+
+        assert_matches!(
+            result,
+            Err(ConfigError::Invalid { key, .. }) if key == "health.self_checks"
+        );
+    }
+
+    #[test]
+    fn checks_the_database_unless_dependencies_are_declared() {
+        let config = Config::load_from(&CliArgs::default(), HashMap::new()).unwrap();
+
+        let dependencies = config.health().dependencies();
+        assert_eq!(dependencies.len(), 1);
+        assert_eq!(dependencies[0].name(), "database");
+        assert_eq!(dependencies[0].check().kind(), "postgres");
+    }
+
+    #[test]
+    fn loads_declared_dependencies() {
+        let file = config_file_with(
+            "dependencies.toml",
+            r#"
+            [health.criticality]
+            snitch = "critical"
+
+            [health.poll_intervals]
+            broker = "1m"
+
+            [[health.dependencies]]
+            name = "database"
+            kind = "postgres"
+            timeout = "1s"
+
+            [[health.dependencies]]
+            name = "snitch"
+            kind = "downstream-status"
+            url = "http://snitch:3030/status"
+            criticality = "optional"
+            interval = "30s"
+
+            [[health.dependencies]]
+            name = "broker"
+            kind = "tcp"
+            address = "broker:5671"
+            interval = "5s"
+            tls = { expiry_warning = "30days" }
+
+            [[health.dependencies]]
+            name = "backups"
+            kind = "command"
+            command = ["/usr/lib/nagios/plugins/check_backups", "-w", "1d"]
+            "#,
+        );
+        let args = CliArgs {
+            config: Some(file.path()),
+            ..CliArgs::default()
+        };
+
+        let config = Config::load_from(&args, HashMap::new()).unwrap();
+
+        let health = config.health();
+        let kinds = health
+            .dependencies()
+            .iter()
+            .map(|dependency| dependency.check().kind())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec!["postgres", "downstream-status", "tcp", "command"]
+        );
+        assert_eq!(
+            health.timeouts().for_dependency("database"),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            health.intervals().for_dependency("snitch"),
+            Duration::from_secs(30)
+        );
+        // declared with the dependency, overriding `health.poll_intervals`
+        assert_eq!(
+            health.intervals().for_dependency("broker"),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            health.criticalities().get("snitch"),
+            Some(&Criticality::Optional)
+        );
+    }
+
+    #[test]
+    fn rejects_settings_of_unknown_dependencies() {
+        struct TestCase {
+            filename: &'static str,
+            settings: &'static str,
+            expected_key: &'static str,
+        }
+        let test_cases = [
+            TestCase {
+                filename: "unknown-criticality.toml",
+                settings: "[health.criticality]\nsnitch = \"optional\"",
+                expected_key: "health.criticality",
+            },
+            TestCase {
+                filename: "unknown-poll-interval.toml",
+                settings: "[health.poll_intervals]\ndatabse = \"5s\"",
+                expected_key: "health.poll_intervals",
+            },
+            TestCase {
+                filename: "unknown-timeout.toml",
+                settings: "[health.dependency_timeouts]\nbroker = \"1s\"",
+                expected_key: "health.dependency_timeouts",
+            },
+            TestCase {
+                filename: "unknown-grouped.toml",
+                settings: "[[health.aggregation.groups]]\nname = \"storage\"\ndependencies = [\"database\", \"replica-1\"]",
+                expected_key: "health.aggregation.groups[0].dependencies",
+            },
+        ];
+
+        for case in test_cases {
+            let file = config_file_with(case.filename, case.settings);
+            let args = CliArgs {
+                config: Some(file.path()),
+                ..CliArgs::default()
+            };
+
+            let result = Config::load_from(&args, HashMap::new());
+
+            assert_matches!(
+                result,
+                Err(ConfigError::Invalid { key, reason })
+                    if key == case.expected_key && reason.starts_with("Unknown dependency"),
+                "{}", case.filename
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_settings_of_self_checks() {
+        let file = config_file_with(
+            "self-check-settings.toml",
+            r#"
+            [health.criticality]
+            memory = "optional"
+            [health.self_checks.memory]
+            budget_mib = 512
+            "#,
+        );
+        let args = CliArgs {
+            config: Some(file.path()),
+            ..CliArgs::default()
+        };
+
+        let result = Config::load_from(&args, HashMap::new());
+
+        assert_ok!(result);
+    }
+
+    #[test]
+    fn rejects_invalid_dependencies() {
+        struct TestCase {
+            filename: &'static str,
+            dependencies: &'static str,
+            expected_reason: &'static str,
+        }
+        let test_cases = [
+            TestCase {
+                filename: "duplicate-dependency.toml",
+                dependencies: r#"
+                [[health.dependencies]]
+                name = "broker"
+                kind = "tcp"
+                address = "broker:5672"
+
+                [[health.dependencies]]
+                name = "broker"
+                kind = "dns"
+                host = "broker"
+                "#,
+                expected_reason: "Dependency `broker` is declared more than once",
+            },
+            TestCase {
+                filename: "invalid-command.toml",
+                dependencies: r#"
+                [[health.dependencies]]
+                name = "backups"
+                kind = "command"
+                command = []
+                "#,
+                expected_reason: "Invalid command dependency `backups`: \
+                                  Missing program: the command must start with the program to run",
+            },
+            TestCase {
+                filename: "self-check-dependency.toml",
+                dependencies: r#"
+                [health.self_checks.runtime]
+
+                [[health.dependencies]]
+                name = "runtime"
+                kind = "tcp"
+                address = "runtime:8125"
+                "#,
+                expected_reason: "Dependency `runtime` is declared more than once",
+            },
+        ];
+
+        for case in test_cases {
+            let file = config_file_with(case.filename, case.dependencies);
+            let args = CliArgs {
+                config: Some(file.path()),
+                ..CliArgs::default()
+            };
+
+            let result = Config::load_from(&args, HashMap::new());
+
+            assert_matches!(
+                result,
+                Err(ConfigError::Invalid { key, reason })
+                    if key == "health.dependencies" && reason == case.expected_reason,
+                "{}",
+                case.filename
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_dependency_kind() {
+        let file = config_file_with(
+            "unknown-dependency-kind.toml",
+            r#"
+            [[health.dependencies]]
+            name = "cache"
+            kind = "redis"
+            "#,
+        );
+        let args = CliArgs {
+            config: Some(file.path()),
+            ..CliArgs::default()
+        };
+
+        let result = Config::load_from(&args, HashMap::new());
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("unknown variant `redis`"));
+    }
+
+    // tests run in parallel, possibly in several processes, so each one needs its own file
+    fn config_file_with(filename: &str, content: &str) -> ConfigFile {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "rustic-sketch.{}-{}.{filename}",
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::write(&path, content).unwrap();
+        ConfigFile(path)
+    }
+
+    // Removed once dropped
+    struct ConfigFile(PathBuf);
+    impl ConfigFile {
+        fn path(&self) -> PathBuf {
+            self.0.clone()
+        }
+    }
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+}
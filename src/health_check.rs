@@ -1,68 +1,264 @@
 use self::{
-    service_status::{DependencyStatus, ServiceStatus},
-    version::Versioned,
+    aggregation::Aggregation,
+    service_status::{Criticality, Dependency, DependencyStatus, ServiceStatus, Status},
+    timeouts::{check_within, Timeouts},
+    version::{Version, VersionLoadError, Versioned},
 };
 use async_trait::async_trait;
-use derive_more::Constructor;
+use chrono::Utc;
 use derive_more::Display;
 use derive_more::Error;
 use futures::future::join_all;
-use getset::Getters;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
+pub mod aggregation;
+pub mod circuit_breaker;
+pub mod command;
+pub mod dns;
+pub mod downstream;
+pub mod history;
+pub mod http;
+pub mod maintenance;
+pub mod oidc;
+pub mod polling;
+pub mod registry;
+pub mod resources;
 pub mod service_status;
+pub mod single_flight;
+pub mod tcp;
+pub mod timeouts;
+pub mod uptime;
 pub mod version;
+pub mod webhook;
 
 #[async_trait]
 pub trait HealthChecker {
     async fn check(&self) -> Result<ServiceStatus, HealthCheckError>;
+
+    /// Checks health bypassing any cached results.
+    async fn check_fresh(&self) -> Result<ServiceStatus, HealthCheckError> {
+        self.check().await
+    }
+
+    /// Whether the initial checks have completed, so results reflect the actual state of dependencies.
+    fn started(&self) -> bool {
+        true
+    }
+}
+
+// So a health checker can be shared, e.g. with `StatusHistory::follow`, while being wrapped
+#[async_trait]
+impl<H: HealthChecker + Send + Sync + ?Sized> HealthChecker for Arc<H> {
+    async fn check(&self) -> Result<ServiceStatus, HealthCheckError> {
+        self.as_ref().check().await
+    }
+
+    async fn check_fresh(&self) -> Result<ServiceStatus, HealthCheckError> {
+        self.as_ref().check_fresh().await
+    }
+
+    fn started(&self) -> bool {
+        self.as_ref().started()
+    }
 }
 
 #[async_trait]
 pub trait DependencyHealthChecker {
+    /// The dependency being checked, also used to report checks that didn't complete.
+    fn dependency(&self) -> Dependency;
+
     async fn check(&self) -> DependencyStatus;
 }
 
-#[derive(Constructor)]
 pub struct RusticSketchHealthChecker {
     versioned: Box<dyn Versioned + Send + Sync>,
-    dependency_health_checkers: Vec<Box<dyn DependencyHealthChecker + Sync + Send>>,
+    // shared with the background tasks polling them, see `polling`
+    dependency_health_checkers: Vec<Arc<dyn DependencyHealthChecker + Sync + Send>>,
+    timeouts: Timeouts,
+    // by dependency name, critical unless set otherwise
+    criticality: HashMap<String, Criticality>,
+    // by dependency name, reset once a check is Ok
+    consecutive_failures: Mutex<HashMap<String, u32>>,
+    aggregation: Aggregation,
+}
+impl RusticSketchHealthChecker {
+    pub fn new(
+        versioned: Box<dyn Versioned + Send + Sync>,
+        dependency_health_checkers: Vec<Box<dyn DependencyHealthChecker + Sync + Send>>,
+    ) -> Self {
+        RusticSketchHealthChecker {
+            versioned,
+            dependency_health_checkers: dependency_health_checkers
+                .into_iter()
+                .map(Arc::from)
+                .collect(),
+            timeouts: Timeouts::default(),
+            criticality: HashMap::new(),
+            consecutive_failures: Mutex::new(HashMap::new()),
+            aggregation: Aggregation::default(),
+        }
+    }
+
+    pub fn with_timeouts(self, timeouts: Timeouts) -> Self {
+        RusticSketchHealthChecker { timeouts, ..self }
+    }
+
+    pub fn with_criticality(mut self, name: &str, criticality: Criticality) -> Self {
+        self.criticality.insert(name.to_string(), criticality);
+        self
+    }
+
+    /// How the statuses of dependencies add up to the overall status, all of them must be Ok by default.
+    pub fn with_aggregation(self, aggregation: Aggregation) -> Self {
+        RusticSketchHealthChecker {
+            aggregation,
+            ..self
+        }
+    }
+
+    pub fn aggregation(&self) -> &Aggregation {
+        &self.aggregation
+    }
+
+    /// Critical unless set otherwise with `with_criticality`.
+    pub fn criticality_of(&self, name: &str) -> Criticality {
+        self.criticality.get(name).copied().unwrap_or_default()
+    }
+
+    pub fn dependency_health_checkers(&self) -> &[Arc<dyn DependencyHealthChecker + Sync + Send>] {
+        &self.dependency_health_checkers
+    }
+
+    pub async fn version(&self) -> Result<Version, HealthCheckError> {
+        self.versioned
+            .version()
+            .await
+            .map_err(HealthCheckError::Version)
+    }
+
+    /// Checks a single dependency, within its timeout, recording diagnostics along the way.
+    pub async fn check_dependency(
+        &self,
+        checker: &(dyn DependencyHealthChecker + Sync + Send),
+    ) -> DependencyStatus {
+        self.check_dependency_until(checker, None).await
+    }
+
+    // Also gives up at the deadline of the health check, if any, so the dependency is reported as timed out
+    async fn check_dependency_until(
+        &self,
+        checker: &(dyn DependencyHealthChecker + Sync + Send),
+        deadline: Option<tokio::time::Instant>,
+    ) -> DependencyStatus {
+        let dependency = checker.dependency();
+        let timeout = self.timeouts.for_dependency(dependency.name());
+        let timeout = match (timeout, deadline) {
+            (timeout, Some(deadline)) => {
+                let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+                Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)))
+            }
+            (timeout, None) => timeout,
+        };
+        let criticality = self.criticality_of(dependency.name());
+        let (checked_at, started) = (Utc::now(), Instant::now());
+        let status = check_within(checker, timeout).await;
+        let latency = started.elapsed();
+        let consecutive_failures = match status.diagnostics().consecutive_failures() {
+            0 => self.record_outcome(dependency.name(), status.status()),
+            // counted by the checker itself, e.g. failures tolerated by a circuit breaker
+            counted => self.record_failures(dependency.name(), counted),
+        };
+        // checkers reporting an earlier outcome, e.g. an open circuit breaker, tell when it was checked
+        let status = match status.diagnostics().checked_at() {
+            Some(_) => status,
+            None => status.with_timing(checked_at, latency),
+        };
+        status
+            .with_criticality(criticality)
+            .with_consecutive_failures(consecutive_failures)
+    }
+
+    fn failures(&self) -> std::sync::MutexGuard<'_, HashMap<String, u32>> {
+        self.consecutive_failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record_outcome(&self, name: &str, status: &Status) -> u32 {
+        let mut failures = self.failures();
+        let count = failures.entry(name.to_string()).or_default();
+        *count = match status {
+            Status::Ok => 0,
+            _ => *count + 1,
+        };
+        *count
+    }
+
+    fn record_failures(&self, name: &str, count: u32) -> u32 {
+        self.failures().insert(name.to_string(), count);
+        count
+    }
 }
 
 #[async_trait]
 impl HealthChecker for RusticSketchHealthChecker {
     async fn check(&self) -> Result<ServiceStatus, HealthCheckError> {
-        let version = self
-            .versioned
-            .version()
-            .await
-            .map_err(|err| HealthCheckError {
-                message: format!("Failed to load version: {}", err.message()),
-            })?;
+        let overall = self.timeouts.for_health_check();
+        let deadline = overall.map(|timeout| tokio::time::Instant::now() + timeout);
+        // dependencies are then given whatever time is left, so only the version can time the check out
+        let version = match (overall, deadline) {
+            (Some(timeout), Some(deadline)) => tokio::time::timeout_at(deadline, self.version())
+                .await
+                .map_err(|_| HealthCheckError::TimedOut(timeout))?,
+            _ => self.version().await,
+        }?;
 
         let futures: Vec<_> = self
             .dependency_health_checkers
             .iter()
-            .map(|checker| checker.check())
+            .map(|checker| self.check_dependency_until(checker.as_ref(), deadline))
             .collect();
         let dependencies = join_all(futures).await;
 
-        Ok(ServiceStatus::new(version, dependencies))
+        Ok(ServiceStatus::aggregated(
+            version,
+            dependencies,
+            &self.aggregation,
+        ))
     }
 }
 
-#[derive(Clone, Constructor, Debug, Display, Error, Getters)]
-pub struct HealthCheckError {
-    #[getset(get)]
-    message: String,
+#[derive(Clone, Debug, Display, Error)]
+pub enum HealthCheckError {
+    #[display("Failed to load version: {_0}")]
+    Version(#[error(source)] VersionLoadError),
+
+    #[display("Health check timed out after {_0:?}")]
+    TimedOut(#[error(not(source))] Duration),
+}
+impl HealthCheckError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            HealthCheckError::Version(_) => "version_unavailable",
+            HealthCheckError::TimedOut(_) => "health_check_timed_out",
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::version::test_kit::StubVersion;
     use super::*;
-    use crate::health_check::service_status::{Dependency, Status};
-    use crate::health_check::test_kit::StubDependencyHealthChecker;
+    use crate::health_check::circuit_breaker::{BreakerPolicy, CircuitBreakerHealthChecker};
+    use crate::health_check::service_status::test_kit::{database, snitch};
+    use crate::health_check::service_status::Status;
+    use crate::health_check::test_kit::{
+        HangingDependencyHealthChecker, StubDependencyHealthChecker,
+    };
     use crate::health_check::version::{Build, Commit, Environment};
+    use claims::assert_matches;
 
     #[tokio::test]
     async fn returns_service_status() {
@@ -71,18 +267,16 @@ mod tests {
             Build::new("feat.branch.108".to_string()),
             Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
         );
-        let database_health_checker =
-            StubDependencyHealthChecker::new(Dependency::Database, Status::Ok);
-        let snitch_health_checker =
-            StubDependencyHealthChecker::new(Dependency::Snitch, Status::Ok);
-
-        let health_checker = RusticSketchHealthChecker {
-            versioned: Box::new(versioned),
-            dependency_health_checkers: vec![
+        let database_health_checker = StubDependencyHealthChecker::new(database(), Status::Ok);
+        let snitch_health_checker = StubDependencyHealthChecker::new(snitch(), Status::Ok);
+
+        let health_checker = RusticSketchHealthChecker::new(
+            Box::new(versioned),
+            vec![
                 Box::new(database_health_checker),
                 Box::new(snitch_health_checker),
             ],
-        };
+        );
         let result = health_checker.check().await.unwrap();
 
         assert_eq!(*result.status(), Status::Ok);
@@ -94,13 +288,12 @@ mod tests {
         let build = Build::new("feat.branch.108".to_string());
         let commit = Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string());
         let versioned = StubVersion::new(env.clone(), build.clone(), commit.clone());
-        let database_health_checker =
-            StubDependencyHealthChecker::new(Dependency::Database, Status::Ok);
+        let database_health_checker = StubDependencyHealthChecker::new(database(), Status::Ok);
 
-        let health_checker = RusticSketchHealthChecker {
-            versioned: Box::new(versioned),
-            dependency_health_checkers: vec![Box::new(database_health_checker)],
-        };
+        let health_checker = RusticSketchHealthChecker::new(
+            Box::new(versioned),
+            vec![Box::new(database_health_checker)],
+        );
         let service_status = health_checker.check().await.unwrap();
         let result = service_status.version();
 
@@ -108,14 +301,211 @@ mod tests {
         assert_eq!(*result.build(), build);
         assert_eq!(*result.commit(), commit);
     }
+
+    #[tokio::test]
+    async fn reports_hanging_dependency_as_timed_out() {
+        let health_checker = RusticSketchHealthChecker::new(
+            Box::new(stub_version()),
+            vec![
+                Box::new(HangingDependencyHealthChecker::new(database())),
+                Box::new(StubDependencyHealthChecker::new(snitch(), Status::Ok)),
+            ],
+        )
+        .with_timeouts(
+            Timeouts::default()
+                .dependency(Duration::from_secs(10))
+                .dependency_override("database", Duration::from_millis(50)),
+        );
+
+        let result = health_checker.check().await.unwrap();
+
+        assert_eq!(*result.status(), Status::Degraded);
+        let statuses: Vec<_> = result
+            .dependencies()
+            .iter()
+            .map(|d| (d.status().clone(), d.diagnostics().reason()))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (Status::Degraded, Some("Timed out after 50ms")),
+                (Status::Ok, None)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_dependency_criticality() {
+        let health_checker = RusticSketchHealthChecker::new(
+            Box::new(stub_version()),
+            vec![
+                Box::new(StubDependencyHealthChecker::new(database(), Status::Ok)),
+                Box::new(StubDependencyHealthChecker::new(snitch(), Status::Degraded)),
+            ],
+        )
+        .with_criticality("snitch", Criticality::Optional);
+
+        let result = health_checker.check().await.unwrap();
+
+        let criticality: Vec<_> = result
+            .dependencies()
+            .iter()
+            .map(|d| *d.criticality())
+            .collect();
+        assert_eq!(
+            criticality,
+            vec![Criticality::Critical, Criticality::Optional]
+        );
+        assert!(result.is_ready());
+    }
+
+    #[tokio::test]
+    async fn overall_timeout_bounds_dependency_checks() {
+        let health_checker = RusticSketchHealthChecker::new(
+            Box::new(stub_version()),
+            vec![Box::new(HangingDependencyHealthChecker::new(database()))],
+        )
+        .with_timeouts(Timeouts::default().overall(Duration::from_millis(50)));
+
+        let result = health_checker.check().await.unwrap();
+
+        assert_eq!(*result.dependencies()[0].status(), Status::Degraded);
+        assert!(result.dependencies()[0]
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("Timed out after"));
+    }
+
+    #[tokio::test]
+    async fn dependencies_outlasting_the_overall_timeout_time_out_on_their_own() {
+        struct SlowVersion;
+        #[async_trait]
+        impl Versioned for SlowVersion {
+            async fn version(&self) -> Result<Version, VersionLoadError> {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                stub_version().version().await
+            }
+        }
+        let health_checker = RusticSketchHealthChecker::new(
+            Box::new(SlowVersion),
+            vec![Box::new(HangingDependencyHealthChecker::new(database()))],
+        )
+        .with_timeouts(
+            Timeouts::default()
+                .overall(Duration::from_millis(100))
+                .dependency_override("database", Duration::from_secs(10)),
+        );
+
+        let result = tokio::time::timeout(Duration::from_secs(1), health_checker.check())
+            .await
+            .expect("the overall timeout should apply")
+            .unwrap();
+
+        let database = &result.dependencies()[0];
+        assert_eq!(*database.status(), Status::Degraded);
+        assert!(database
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("Timed out after"));
+    }
+
+    #[tokio::test]
+    async fn times_out_overall_including_version() {
+        struct HangingVersion;
+        #[async_trait]
+        impl Versioned for HangingVersion {
+            async fn version(&self) -> Result<Version, VersionLoadError> {
+                std::future::pending().await
+            }
+        }
+        let health_checker = RusticSketchHealthChecker::new(
+            Box::new(HangingVersion),
+            vec![Box::new(StubDependencyHealthChecker::new(
+                database(),
+                Status::Ok,
+            ))],
+        )
+        .with_timeouts(Timeouts::default().overall(Duration::from_millis(50)));
+
+        let result = tokio::time::timeout(Duration::from_secs(1), health_checker.check())
+            .await
+            .expect("the overall timeout should apply");
+
+        assert_matches!(result, Err(HealthCheckError::TimedOut(timeout)) if timeout == Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn records_check_diagnostics() {
+        let database = StubDependencyHealthChecker::new(database(), Status::Degraded);
+        let health_checker =
+            RusticSketchHealthChecker::new(Box::new(stub_version()), vec![Box::new(database)]);
+        let before = Utc::now();
+
+        health_checker.check().await.unwrap();
+        let result = health_checker.check().await.unwrap();
+
+        let diagnostics = result.dependencies()[0].diagnostics();
+        assert!(*diagnostics.checked_at().unwrap() >= before);
+        assert!(diagnostics.latency().is_some());
+        assert_eq!(diagnostics.consecutive_failures(), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_failures_counted_by_checkers() {
+        let breaker = CircuitBreakerHealthChecker::new(
+            Box::new(StubDependencyHealthChecker::new(
+                database(),
+                Status::Degraded,
+            )),
+            BreakerPolicy::new(3, 1),
+        );
+        let health_checker =
+            RusticSketchHealthChecker::new(Box::new(stub_version()), vec![Box::new(breaker)]);
+
+        let mut failures = Vec::new();
+        for _ in 0..3 {
+            let result = health_checker.check().await.unwrap();
+            failures.push(
+                result.dependencies()[0]
+                    .diagnostics()
+                    .consecutive_failures(),
+            );
+        }
+
+        // tolerated by the breaker, then reported
+        assert_eq!(failures, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn resets_consecutive_failures_once_dependency_recovers() {
+        let health_checker = RusticSketchHealthChecker::new(Box::new(stub_version()), Vec::new());
+
+        health_checker.record_outcome("database", &Status::Degraded);
+        health_checker.record_outcome("database", &Status::Degraded);
+
+        assert_eq!(health_checker.record_outcome("database", &Status::Ok), 0);
+        assert_eq!(
+            health_checker.record_outcome("database", &Status::Degraded),
+            1
+        );
+    }
+
+    fn stub_version() -> StubVersion {
+        StubVersion::new(
+            Environment::new("dev".to_string()),
+            Build::new("feat.branch.108".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        )
+    }
 }
 
 #[cfg(test)]
 pub(crate) mod test_kit {
-    use super::{
-        service_status::{Dependency, Status},
-        *,
-    };
+    use super::{service_status::Status, *};
+    use derive_more::Constructor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     /* Stubs */
 
@@ -137,8 +527,60 @@ pub(crate) mod test_kit {
     }
     #[async_trait]
     impl DependencyHealthChecker for StubDependencyHealthChecker {
+        fn dependency(&self) -> Dependency {
+            self.dependency.clone()
+        }
+
+        async fn check(&self) -> DependencyStatus {
+            DependencyStatus::new(self.dependency.clone(), self.status.clone())
+        }
+    }
+
+    /// Counts how many times the dependency has been checked.
+    #[derive(Clone)]
+    pub struct CountingDependencyHealthChecker {
+        dependency: Dependency,
+        status: Status,
+        checks: Arc<AtomicUsize>,
+    }
+    impl CountingDependencyHealthChecker {
+        pub fn new(dependency: Dependency, status: Status) -> Self {
+            CountingDependencyHealthChecker {
+                dependency,
+                status,
+                checks: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        pub fn checks(&self) -> usize {
+            self.checks.load(Ordering::SeqCst)
+        }
+    }
+    #[async_trait]
+    impl DependencyHealthChecker for CountingDependencyHealthChecker {
+        fn dependency(&self) -> Dependency {
+            self.dependency.clone()
+        }
+
         async fn check(&self) -> DependencyStatus {
+            self.checks.fetch_add(1, Ordering::SeqCst);
             DependencyStatus::new(self.dependency.clone(), self.status.clone())
         }
     }
+
+    /// Never completes its check, like a probe against a network-partitioned dependency.
+    #[derive(Constructor)]
+    pub struct HangingDependencyHealthChecker {
+        dependency: Dependency,
+    }
+    #[async_trait]
+    impl DependencyHealthChecker for HangingDependencyHealthChecker {
+        fn dependency(&self) -> Dependency {
+            self.dependency.clone()
+        }
+
+        async fn check(&self) -> DependencyStatus {
+            std::future::pending().await
+        }
+    }
 }
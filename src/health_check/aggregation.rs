@@ -0,0 +1,554 @@
+use super::service_status::{Criticality, DependencyStatus, Status};
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+// Ok when there are no dependencies at all
+fn worst<'a>(dependencies: impl Iterator<Item = &'a DependencyStatus>) -> Status {
+    dependencies
+        .map(DependencyStatus::status)
+        .max()
+        .cloned()
+        .unwrap_or(Status::Ok)
+}
+
+/// How the statuses of a group of dependencies add up to the status of the group.
+///
+/// A group is never better off than the dependencies it needs: e.g. when a quorum can't be met
+/// because replicas are Down, the group is Down too.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum AggregationPolicy {
+    /// As bad as the worst dependency.
+    #[default]
+    AllMustPass,
+    /// As bad as the worst critical dependency.
+    CriticalOnly,
+    /// As bad as the `min_ok`-th best dependency, e.g. Ok when 2 of 3 replicas are.
+    Quorum { min_ok: usize },
+    /// As bad as needed for the dependencies at least that good to add up to `threshold` of the total weight,
+    /// between 0 and 1. Dependencies without a weight weigh 1.
+    Weighted {
+        #[serde(default)]
+        weights: HashMap<String, f64>,
+        threshold: f64,
+    },
+}
+impl AggregationPolicy {
+    pub fn aggregate<'a>(
+        &self,
+        dependencies: impl IntoIterator<Item = &'a DependencyStatus>,
+    ) -> Status {
+        let dependencies = dependencies.into_iter();
+        match self {
+            AggregationPolicy::AllMustPass => worst(dependencies),
+            AggregationPolicy::CriticalOnly => {
+                worst(dependencies.filter(|d| *d.criticality() == Criticality::Critical))
+            }
+            AggregationPolicy::Quorum { min_ok } => {
+                let mut statuses: Vec<_> = dependencies.map(DependencyStatus::status).collect();
+                statuses.sort();
+                match statuses.get(min_ok.saturating_sub(1)) {
+                    Some(status) => (*status).clone(),
+                    None => Status::Down,
+                }
+            }
+            AggregationPolicy::Weighted { weights, threshold } => {
+                let mut statuses: Vec<_> = dependencies
+                    .map(|d| {
+                        let weight = weights.get(d.dependency().name()).copied().unwrap_or(1.0);
+                        (d.status(), weight)
+                    })
+                    .collect();
+                statuses.sort_by_key(|(status, _)| *status);
+                let total: f64 = statuses.iter().map(|(_, weight)| weight).sum();
+                let mut cumulated = 0.0;
+                statuses
+                    .into_iter()
+                    .find(|(_, weight)| {
+                        cumulated += weight;
+                        cumulated / total >= *threshold
+                    })
+                    .map_or(Status::Ok, |(status, _)| status.clone())
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            AggregationPolicy::Quorum { min_ok: 0 } => Err("min_ok must be at least 1".to_string()),
+            AggregationPolicy::Weighted { weights, threshold } => {
+                if !(*threshold > 0.0 && *threshold <= 1.0) {
+                    return Err("threshold must be greater than 0 and at most 1".to_string());
+                }
+                if let Some((name, _)) = weights.iter().find(|(_, w)| !w.is_finite()) {
+                    return Err(format!("weight of `{name}` must be a finite number"));
+                }
+                match weights.iter().find(|(_, w)| **w < 0.0) {
+                    Some((name, _)) => Err(format!("weight of `{name}` must not be negative")),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Dependencies whose statuses add up according to their own policy, e.g. replicas of a database.
+#[derive(Clone, Debug, Deserialize, Getters, PartialEq)]
+#[getset(get = "pub")]
+pub struct DependencyGroup {
+    name: String,
+    // by dependency name
+    dependencies: Vec<String>,
+    #[serde(default)]
+    policy: AggregationPolicy,
+}
+impl DependencyGroup {
+    pub fn new(name: &str, dependencies: &[&str], policy: AggregationPolicy) -> Self {
+        DependencyGroup {
+            name: name.to_string(),
+            dependencies: dependencies.iter().map(ToString::to_string).collect(),
+            policy,
+        }
+    }
+}
+
+type Split<'a> = (
+    Vec<(&'a DependencyGroup, Vec<&'a DependencyStatus>)>,
+    Vec<&'a DependencyStatus>,
+);
+
+/// How the statuses of dependencies add up to the overall status of the service.
+///
+/// Each group is aggregated with its own policy, the dependencies outside any group with the default one.
+/// The service is as bad as the worst group, or the worst of the rest of its dependencies.
+#[derive(Clone, Debug, Default, Deserialize, Getters, PartialEq)]
+#[getset(get = "pub")]
+pub struct Aggregation {
+    // for the dependencies outside any group, e.g. `RUSTIC_HEALTH__AGGREGATION__POLICY__KIND=critical-only`
+    #[serde(default)]
+    policy: AggregationPolicy,
+    #[serde(default)]
+    groups: Vec<DependencyGroup>,
+}
+impl Aggregation {
+    pub fn new(policy: AggregationPolicy) -> Self {
+        Aggregation {
+            policy,
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn with_group(mut self, group: DependencyGroup) -> Self {
+        self.groups.push(group);
+        self
+    }
+
+    pub fn aggregate(&self, dependencies: &[DependencyStatus]) -> Status {
+        let (groups, ungrouped) = self.split(dependencies);
+        groups
+            .into_iter()
+            .map(|(group, members)| group.policy.aggregate(members))
+            .chain([self.policy.aggregate(ungrouped)])
+            .fold(Status::Ok, Ord::max)
+    }
+
+    /// Whether the service can take traffic as far as its dependencies go: every group with a critical dependency
+    /// adds up to Ok according to its policy, e.g. a quorum of replicas is met, and every other critical dependency is Ok.
+    pub fn is_ready(&self, dependencies: &[DependencyStatus]) -> bool {
+        let critical = |d: &DependencyStatus| *d.criticality() == Criticality::Critical;
+        let (groups, ungrouped) = self.split(dependencies);
+        groups
+            .into_iter()
+            .filter(|(_, members)| members.iter().any(|d| critical(d)))
+            .all(|(group, members)| group.policy.aggregate(members) == Status::Ok)
+            && ungrouped
+                .into_iter()
+                .filter(|d| critical(d))
+                .all(|d| *d.status() == Status::Ok)
+    }
+
+    // The dependencies of each group, then the ones outside any group
+    fn split<'a>(&'a self, dependencies: &'a [DependencyStatus]) -> Split<'a> {
+        let member_of = |group: &DependencyGroup, d: &DependencyStatus| {
+            group
+                .dependencies
+                .iter()
+                .any(|n| n == d.dependency().name())
+        };
+        let groups = self
+            .groups
+            .iter()
+            .map(|group| {
+                let members = dependencies
+                    .iter()
+                    .filter(|d| member_of(group, d))
+                    .collect();
+                (group, members)
+            })
+            .collect();
+        let ungrouped = dependencies
+            .iter()
+            .filter(|d| !self.groups.iter().any(|group| member_of(group, d)))
+            .collect();
+        (groups, ungrouped)
+    }
+
+    /// Rejects policies that can't be met and dependencies that belong to more than one group.
+    pub fn validate(&self) -> Result<(), AggregationError> {
+        self.policy
+            .validate()
+            .map_err(AggregationError::InvalidPolicy)?;
+        let mut names = HashSet::new();
+        let mut grouped = HashSet::new();
+        for group in &self.groups {
+            if group.name.is_empty() || !names.insert(group.name.as_str()) {
+                return Err(AggregationError::InvalidGroupName {
+                    name: group.name.clone(),
+                });
+            }
+            if group.dependencies.is_empty() {
+                return Err(AggregationError::EmptyGroup {
+                    group: group.name.clone(),
+                });
+            }
+            if let Some(dependency) = group
+                .dependencies
+                .iter()
+                .find(|d| !grouped.insert(d.as_str()))
+            {
+                return Err(AggregationError::GroupedTwice {
+                    dependency: dependency.clone(),
+                });
+            }
+            let invalid = |reason| AggregationError::InvalidGroupPolicy {
+                group: group.name.clone(),
+                reason,
+            };
+            group.policy.validate().map_err(invalid)?;
+            if let AggregationPolicy::Quorum { min_ok } = group.policy {
+                if min_ok > group.dependencies.len() {
+                    return Err(invalid(format!(
+                        "min_ok must be at most the {} dependencies of the group",
+                        group.dependencies.len()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum AggregationError {
+    #[display("Invalid aggregation policy: {_0}")]
+    InvalidPolicy(#[error(not(source))] String),
+
+    #[display("Invalid aggregation policy of group `{group}`: {reason}")]
+    InvalidGroupPolicy {
+        #[error(not(source))]
+        group: String,
+        #[error(not(source))]
+        reason: String,
+    },
+
+    #[display("Invalid group name `{name}`: must be unique and not empty")]
+    InvalidGroupName {
+        #[error(not(source))]
+        name: String,
+    },
+
+    #[display("Group `{group}` has no dependencies")]
+    EmptyGroup {
+        #[error(not(source))]
+        group: String,
+    },
+
+    #[display("Dependency `{dependency}` belongs to more than one group")]
+    GroupedTwice {
+        #[error(not(source))]
+        dependency: String,
+    },
+}
+impl AggregationError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AggregationError::InvalidPolicy(_) | AggregationError::InvalidGroupPolicy { .. } => {
+                "aggregation_invalid_policy"
+            }
+            AggregationError::InvalidGroupName { .. } => "aggregation_invalid_group_name",
+            AggregationError::EmptyGroup { .. } => "aggregation_empty_group",
+            AggregationError::GroupedTwice { .. } => "aggregation_grouped_twice",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::service_status::test_kit::*;
+    use crate::health_check::service_status::{Dependency, DependencyKind};
+    use claims::assert_matches;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn all_must_pass_is_ok_only_when_every_dependency_is(
+            dependencies in vec(arb_service_dependency(), 0..4),
+        ) {
+            let expected = dependencies.iter().all(|d| *d.status() == Status::Ok);
+
+            let result = AggregationPolicy::AllMustPass.aggregate(&dependencies);
+
+            prop_assert_eq!(result == Status::Ok, expected);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn critical_only_ignores_optional_dependencies(
+            critical in vec(arb_healthy_dependency(), 0..4),
+            optional in vec(arb_service_dependency(), 0..4),
+        ) {
+            let dependencies: Vec<_> = critical
+                .into_iter()
+                .map(|d| d.with_criticality(Criticality::Critical))
+                .chain(optional.into_iter().map(|d| d.with_criticality(Criticality::Optional)))
+                .collect();
+
+            let result = AggregationPolicy::CriticalOnly.aggregate(&dependencies);
+
+            prop_assert_eq!(result, Status::Ok);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn critical_only_is_degraded_by_critical_dependencies(
+            dependencies in arb_unhealthy_dependencies(),
+        ) {
+            let dependencies: Vec<_> = dependencies
+                .into_iter()
+                .map(|d| d.with_criticality(Criticality::Critical))
+                .collect();
+
+            let result = AggregationPolicy::CriticalOnly.aggregate(&dependencies);
+
+            prop_assert_eq!(result, Status::Degraded);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn quorum_needs_enough_ok_dependencies(
+            dependencies in vec(arb_service_dependency(), 0..6),
+            min_ok in 1..4usize,
+        ) {
+            let ok = dependencies.iter().filter(|d| *d.status() == Status::Ok).count();
+
+            let result = AggregationPolicy::Quorum { min_ok }.aggregate(&dependencies);
+
+            prop_assert_eq!(result == Status::Ok, ok >= min_ok);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn weighted_with_full_threshold_is_all_must_pass(
+            dependencies in vec(arb_service_dependency(), 0..4),
+        ) {
+            let weighted = AggregationPolicy::Weighted {
+                weights: HashMap::from([("database".to_string(), 3.0)]),
+                threshold: 1.0,
+            };
+
+            prop_assert_eq!(
+                weighted.aggregate(&dependencies),
+                AggregationPolicy::AllMustPass.aggregate(&dependencies)
+            );
+        }
+    }
+
+    #[test]
+    fn weighted_tolerates_light_dependencies() {
+        let weighted = AggregationPolicy::Weighted {
+            weights: HashMap::from([("database".to_string(), 3.0)]),
+            threshold: 0.75,
+        };
+        let snitch_down = [
+            DependencyStatus::new(database(), Status::Ok),
+            DependencyStatus::new(snitch(), Status::Degraded),
+        ];
+        let database_down = [
+            DependencyStatus::new(database(), Status::Degraded),
+            DependencyStatus::new(snitch(), Status::Ok),
+        ];
+
+        assert_eq!(weighted.aggregate(&snitch_down), Status::Ok);
+        assert_eq!(weighted.aggregate(&database_down), Status::Degraded);
+    }
+
+    #[test]
+    fn quorum_is_as_bad_as_the_dependencies_it_needs() {
+        let replicas = [
+            DependencyStatus::new(replica(0), Status::Down),
+            DependencyStatus::new(replica(1), Status::Ok),
+            DependencyStatus::new(replica(2), Status::Degraded),
+        ];
+
+        let quorum = |min_ok| AggregationPolicy::Quorum { min_ok }.aggregate(&replicas);
+
+        assert_eq!(quorum(1), Status::Ok);
+        assert_eq!(quorum(2), Status::Degraded);
+        assert_eq!(quorum(3), Status::Down);
+        assert_eq!(quorum(4), Status::Down);
+    }
+
+    #[test]
+    fn unknown_dependencies_leave_the_status_unknown_unless_worse() {
+        let starting = [
+            DependencyStatus::new(database(), Status::Ok),
+            DependencyStatus::new(snitch(), Status::Unknown),
+        ];
+        let failing = [
+            DependencyStatus::new(database(), Status::Down),
+            DependencyStatus::new(snitch(), Status::Unknown),
+        ];
+
+        assert_eq!(
+            AggregationPolicy::AllMustPass.aggregate(&starting),
+            Status::Unknown
+        );
+        assert_eq!(
+            AggregationPolicy::AllMustPass.aggregate(&failing),
+            Status::Down
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn groups_are_aggregated_with_their_own_policy(
+            healthy in vec(arb_healthy_dependency(), 0..4),
+            down in 0..3usize,
+        ) {
+            let replicas: Vec<_> = (0..3)
+                .map(|i| {
+                    let status = if i < down { Status::Degraded } else { Status::Ok };
+                    DependencyStatus::new(replica(i), status)
+                })
+                .collect();
+            let aggregation = Aggregation::default().with_group(DependencyGroup::new(
+                "replicas",
+                &["replica-0", "replica-1", "replica-2"],
+                AggregationPolicy::Quorum { min_ok: 2 },
+            ));
+            let dependencies: Vec<_> = healthy.into_iter().chain(replicas).collect();
+
+            let result = aggregation.aggregate(&dependencies);
+
+            prop_assert_eq!(result == Status::Ok, down <= 1);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn ungrouped_dependencies_follow_the_default_policy(
+            dependencies in arb_unhealthy_dependencies(),
+        ) {
+            let replica = DependencyStatus::new(replica(0), Status::Ok);
+            let aggregation = Aggregation::default().with_group(DependencyGroup::new(
+                "replicas",
+                &["replica-0"],
+                AggregationPolicy::AllMustPass,
+            ));
+            let dependencies: Vec<_> = dependencies.into_iter().chain([replica]).collect();
+
+            let result = aggregation.aggregate(&dependencies);
+
+            prop_assert_eq!(result, Status::Degraded);
+        }
+    }
+
+    #[test]
+    fn rejects_unreachable_quorum() {
+        let aggregation = Aggregation::default().with_group(DependencyGroup::new(
+            "replicas",
+            &["replica-0", "replica-1"],
+            AggregationPolicy::Quorum { min_ok: 3 },
+        ));
+
+        assert_matches!(
+            aggregation.validate(),
+            Err(AggregationError::InvalidGroupPolicy { group, .. }) if group == "replicas"
+        );
+    }
+
+    #[test]
+    fn rejects_dependencies_grouped_twice() {
+        let aggregation = Aggregation::default()
+            .with_group(DependencyGroup::new(
+                "primary",
+                &["database"],
+                AggregationPolicy::AllMustPass,
+            ))
+            .with_group(DependencyGroup::new(
+                "storage",
+                &["database"],
+                AggregationPolicy::AllMustPass,
+            ));
+
+        assert_eq!(
+            aggregation.validate(),
+            Err(AggregationError::GroupedTwice {
+                dependency: "database".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_weighted_threshold_out_of_range() {
+        let aggregation = Aggregation::new(AggregationPolicy::Weighted {
+            weights: HashMap::new(),
+            threshold: 1.5,
+        });
+
+        assert_matches!(
+            aggregation.validate(),
+            Err(AggregationError::InvalidPolicy(_))
+        );
+    }
+
+    #[test]
+    fn rejects_weights_that_are_negative_or_not_a_number() {
+        let weighted = |weight| {
+            Aggregation::new(AggregationPolicy::Weighted {
+                weights: HashMap::from([("database".to_string(), weight)]),
+                threshold: 0.5,
+            })
+            .validate()
+        };
+
+        assert_eq!(
+            weighted(-1.0),
+            Err(AggregationError::InvalidPolicy(
+                "weight of `database` must not be negative".to_string()
+            ))
+        );
+        for weight in [f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                weighted(weight),
+                Err(AggregationError::InvalidPolicy(
+                    "weight of `database` must be a finite number".to_string()
+                ))
+            );
+        }
+    }
+
+    fn replica(i: usize) -> Dependency {
+        Dependency::new(format!("replica-{i}"), DependencyKind::Postgres)
+    }
+}
@@ -0,0 +1,448 @@
+use super::{
+    service_status::{Dependency, DependencyStatus, Status},
+    timeouts::check_within,
+    DependencyHealthChecker,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use derive_more::Display;
+use getset::Getters;
+use serde::Deserialize;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// When a dependency is considered unhealthy, or healthy again, and how often it's probed in between.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct BreakerPolicy {
+    // consecutive failed checks before the dependency is reported as Degraded
+    #[serde(default = "default_failure_threshold")]
+    failure_threshold: u32,
+    // consecutive successful checks before the dependency is reported as Ok again
+    #[serde(default = "default_success_threshold")]
+    success_threshold: u32,
+    // how long to wait before probing once the circuit opens, doubling after each failed probe
+    #[serde(default = "default_open_for", with = "humantime_serde")]
+    open_for: Duration,
+    #[serde(default = "default_max_open_for", with = "humantime_serde")]
+    max_open_for: Duration,
+}
+impl BreakerPolicy {
+    pub fn new(failure_threshold: u32, success_threshold: u32) -> Self {
+        BreakerPolicy {
+            failure_threshold,
+            success_threshold,
+            ..BreakerPolicy::default()
+        }
+    }
+
+    pub fn with_backoff(self, open_for: Duration, max_open_for: Duration) -> Self {
+        BreakerPolicy {
+            open_for,
+            max_open_for,
+            ..self
+        }
+    }
+
+    fn backoff(&self, open_for: Duration) -> Duration {
+        (open_for * 2).min(self.max_open_for)
+    }
+}
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        BreakerPolicy {
+            failure_threshold: default_failure_threshold(),
+            success_threshold: default_success_threshold(),
+            open_for: default_open_for(),
+            max_open_for: default_max_open_for(),
+        }
+    }
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_success_threshold() -> u32 {
+    2
+}
+
+fn default_open_for() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_max_open_for() -> Duration {
+    Duration::from_secs(60)
+}
+
+#[derive(Clone, Debug, Display)]
+enum BreakerState {
+    // reporting Ok, tolerating a few failures
+    #[display("closed")]
+    Closed { failures: u32 },
+    // reporting Degraded, probing once `until` is reached
+    #[display("open")]
+    Open {
+        until: Instant,
+        open_for: Duration,
+        // consecutive failed probes, reported along with the last one until probing again
+        failures: u32,
+        last: Box<DependencyStatus>,
+    },
+    // still reporting Degraded while the dependency proves it has recovered
+    #[display("half-open")]
+    HalfOpen { successes: u32, open_for: Duration },
+}
+
+/// Suppresses flapping of a dependency: it's only reported as Degraded after a number of consecutive
+/// failures and as Ok again after a number of consecutive successes.
+///
+/// While Degraded (the circuit is open), the dependency is probed less and less often,
+/// backing off up to `max_open_for`, and the outcome of the last probe is reported as `cached` in the meantime.
+/// The state of the breaker is reported in the diagnostics, along with the failures it tolerates.
+pub struct CircuitBreakerHealthChecker {
+    checker: Box<dyn DependencyHealthChecker + Send + Sync>,
+    policy: BreakerPolicy,
+    // so checks that hang are counted as failures too
+    timeout: Option<Duration>,
+    state: Mutex<BreakerState>,
+}
+impl CircuitBreakerHealthChecker {
+    pub fn new(
+        checker: Box<dyn DependencyHealthChecker + Send + Sync>,
+        policy: BreakerPolicy,
+    ) -> Self {
+        CircuitBreakerHealthChecker {
+            checker,
+            policy,
+            timeout: None,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        CircuitBreakerHealthChecker { timeout, ..self }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record(&self, status: DependencyStatus, now: Instant) -> DependencyStatus {
+        let mut state = self.state();
+        let ok = *status.status() == Status::Ok;
+        let policy = &self.policy;
+        let open = |open_for: Duration, failures: u32, status: DependencyStatus| {
+            let status = status.with_consecutive_failures(failures);
+            let state = BreakerState::Open {
+                until: now + open_for,
+                open_for,
+                failures,
+                last: Box::new(status.clone()),
+            };
+            (state, status)
+        };
+
+        let (next, reported) = match (&*state, ok) {
+            (BreakerState::Closed { .. }, true) => (BreakerState::Closed { failures: 0 }, status),
+            (BreakerState::Closed { failures }, false)
+                if failures + 1 >= policy.failure_threshold =>
+            {
+                open(policy.open_for, failures + 1, status)
+            }
+            (BreakerState::Closed { failures }, false) => (
+                BreakerState::Closed {
+                    failures: failures + 1,
+                },
+                // the failure still counts, and its reason is kept
+                status
+                    .with_status(Status::Ok)
+                    .with_consecutive_failures(failures + 1)
+                    .with_detail("breaker_failures", failures + 1),
+            ),
+            (BreakerState::Open { open_for, .. }, true)
+            | (BreakerState::HalfOpen { open_for, .. }, true) => {
+                let successes = match &*state {
+                    BreakerState::HalfOpen { successes, .. } => successes + 1,
+                    _ => 1,
+                };
+                if successes >= policy.success_threshold {
+                    (BreakerState::Closed { failures: 0 }, status)
+                } else {
+                    (
+                        BreakerState::HalfOpen {
+                            successes,
+                            open_for: *open_for,
+                        },
+                        status
+                            .with_status(Status::Degraded)
+                            .with_reason(format!(
+                                "Recovering: {successes} of {} successful checks",
+                                policy.success_threshold
+                            ))
+                            .with_detail("breaker_successes", successes),
+                    )
+                }
+            }
+            (
+                BreakerState::Open {
+                    open_for, failures, ..
+                },
+                false,
+            ) => open(policy.backoff(*open_for), failures + 1, status),
+            // the probe before succeeded
+            (BreakerState::HalfOpen { open_for, .. }, false) => {
+                open(policy.backoff(*open_for), 1, status)
+            }
+        };
+        let reported = with_breaker_details(reported, &next, now);
+        *state = next;
+        reported
+    }
+}
+
+fn with_breaker_details(
+    status: DependencyStatus,
+    state: &BreakerState,
+    now: Instant,
+) -> DependencyStatus {
+    let status = status.with_detail("breaker", state);
+    match state {
+        BreakerState::Open { until, .. } => status.with_detail(
+            "next_probe_in_ms",
+            until.saturating_duration_since(now).as_millis(),
+        ),
+        _ => status,
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for CircuitBreakerHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.checker.dependency()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        let now = Instant::now();
+        // no probing until the backoff elapses
+        {
+            let state = self.state();
+            if let BreakerState::Open { until, last, .. } = &*state {
+                if now < *until {
+                    // keeping the timing and failures of the check it comes from
+                    let cached = last.as_ref().clone().with_detail("cached", true);
+                    return with_breaker_details(cached, &state, now);
+                }
+            }
+        }
+
+        let (checked_at, started) = (Utc::now(), Instant::now());
+        let status = check_within(self.checker.as_ref(), self.timeout)
+            .await
+            .with_timing(checked_at, started.elapsed());
+        self.record(status, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::service_status::test_kit::database;
+    use crate::health_check::test_kit::HangingDependencyHealthChecker;
+    use crate::health_check::version::test_kit::StubVersion;
+    use crate::health_check::version::{Build, Commit, Environment};
+    use crate::health_check::RusticSketchHealthChecker;
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn tolerates_failures_below_threshold() {
+        let (breaker, _) = breaker_over(&[Status::Degraded, Status::Degraded, Status::Ok]);
+
+        let statuses = check_times(&breaker, 3).await;
+
+        assert_eq!(statuses, vec![Status::Ok, Status::Ok, Status::Ok]);
+    }
+
+    #[tokio::test]
+    async fn counts_tolerated_failures() {
+        let (breaker, _) = breaker_over(&[Status::Degraded, Status::Degraded]);
+
+        let failures: Vec<_> = check_all(&breaker, 2)
+            .await
+            .iter()
+            .map(|status| status.diagnostics().consecutive_failures())
+            .collect();
+
+        assert_eq!(failures, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn reports_last_probe_as_cached_while_open() {
+        let (breaker, _) = breaker_over(&vec![Status::Degraded; 3]);
+        let probes = check_all(&breaker, 3).await;
+
+        let cached = breaker.check().await;
+
+        assert_eq!(cached.diagnostics().details()["cached"], "true");
+        assert_eq!(
+            cached.diagnostics().checked_at(),
+            probes[2].diagnostics().checked_at()
+        );
+        assert_eq!(
+            cached.diagnostics().latency(),
+            probes[2].diagnostics().latency()
+        );
+    }
+
+    #[tokio::test]
+    async fn failures_stay_put_while_open() {
+        let (breaker, _) = breaker_over(&vec![Status::Degraded; 3]);
+        let health_checker = RusticSketchHealthChecker::new(
+            Box::new(StubVersion::new(
+                Environment::new("dev".to_string()),
+                Build::new("feat.branch.108".to_string()),
+                Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+            )),
+            Vec::new(),
+        );
+
+        let mut failures = Vec::new();
+        for _ in 0..6 {
+            let status = health_checker.check_dependency(&breaker).await;
+            failures.push(status.diagnostics().consecutive_failures());
+        }
+
+        assert_eq!(failures, vec![1, 2, 3, 3, 3, 3]);
+    }
+
+    #[tokio::test]
+    async fn opens_after_consecutive_failures_and_backs_off() {
+        let (breaker, probes) = breaker_over(&vec![Status::Degraded; 3]);
+
+        let statuses = check_times(&breaker, 5).await;
+
+        assert_eq!(
+            statuses,
+            vec![
+                Status::Ok,
+                Status::Ok,
+                Status::Degraded,
+                Status::Degraded,
+                Status::Degraded
+            ]
+        );
+        assert_eq!(probes.load(Ordering::SeqCst), 3);
+        let status = breaker.check().await;
+        assert_eq!(status.diagnostics().details()["breaker"], "open");
+        assert!(status
+            .diagnostics()
+            .details()
+            .contains_key("next_probe_in_ms"));
+    }
+
+    #[tokio::test]
+    async fn closes_after_consecutive_successes() {
+        let (breaker, _) = breaker_over(&[
+            Status::Degraded,
+            Status::Degraded,
+            Status::Degraded,
+            Status::Ok,
+            Status::Ok,
+        ]);
+        check_times(&breaker, 3).await;
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let recovering = breaker.check().await;
+        let recovered = breaker.check().await;
+
+        assert_eq!(*recovering.status(), Status::Degraded);
+        assert_eq!(recovering.diagnostics().details()["breaker"], "half-open");
+        assert_eq!(
+            recovering.diagnostics().reason(),
+            Some("Recovering: 1 of 2 successful checks")
+        );
+        assert_eq!(*recovered.status(), Status::Ok);
+        assert_eq!(recovered.diagnostics().details()["breaker"], "closed");
+    }
+
+    #[tokio::test]
+    async fn backs_off_further_after_each_failed_probe() {
+        let (breaker, _) = breaker_over(&vec![Status::Degraded; 4]);
+        check_times(&breaker, 3).await;
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let status = breaker.check().await;
+
+        let next_probe_in: u64 = status.diagnostics().details()["next_probe_in_ms"]
+            .parse()
+            .unwrap();
+        assert!(next_probe_in > 20);
+    }
+
+    #[tokio::test]
+    async fn counts_hanging_checks_as_failures() {
+        let breaker = CircuitBreakerHealthChecker::new(
+            Box::new(HangingDependencyHealthChecker::new(database())),
+            BreakerPolicy::new(1, 1),
+        )
+        .with_timeout(Some(Duration::from_millis(10)));
+
+        let status = breaker.check().await;
+
+        assert_eq!(*status.status(), Status::Degraded);
+        assert_eq!(status.diagnostics().details()["breaker"], "open");
+    }
+
+    async fn check_times(breaker: &CircuitBreakerHealthChecker, times: usize) -> Vec<Status> {
+        check_all(breaker, times)
+            .await
+            .iter()
+            .map(|status| status.status().clone())
+            .collect()
+    }
+
+    async fn check_all(
+        breaker: &CircuitBreakerHealthChecker,
+        times: usize,
+    ) -> Vec<DependencyStatus> {
+        let mut statuses = Vec::new();
+        for _ in 0..times {
+            statuses.push(breaker.check().await);
+        }
+        statuses
+    }
+
+    fn breaker_over(outcomes: &[Status]) -> (CircuitBreakerHealthChecker, Arc<AtomicUsize>) {
+        let checker = ScriptedDependencyHealthChecker {
+            outcomes: Mutex::new(outcomes.iter().cloned().collect()),
+            probes: Arc::new(AtomicUsize::new(0)),
+        };
+        let probes = checker.probes.clone();
+        let policy = BreakerPolicy::new(3, 2)
+            .with_backoff(Duration::from_millis(20), Duration::from_millis(200));
+        (
+            CircuitBreakerHealthChecker::new(Box::new(checker), policy),
+            probes,
+        )
+    }
+
+    // Reports the given outcomes in order, then Ok
+    struct ScriptedDependencyHealthChecker {
+        outcomes: Mutex<VecDeque<Status>>,
+        probes: Arc<AtomicUsize>,
+    }
+    #[async_trait]
+    impl DependencyHealthChecker for ScriptedDependencyHealthChecker {
+        fn dependency(&self) -> Dependency {
+            database()
+        }
+
+        async fn check(&self) -> DependencyStatus {
+            self.probes.fetch_add(1, Ordering::SeqCst);
+            let status = self.outcomes.lock().unwrap().pop_front();
+            DependencyStatus::new(database(), status.unwrap_or(Status::Ok))
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use super::{
+    service_status::{Dependency, DependencyKind, DependencyStatus, Status},
+    DependencyHealthChecker,
+};
+use async_trait::async_trait;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use serde::Deserialize;
+use std::io;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+
+// Plugins print a line or two, so anything beyond is discarded rather than kept in memory
+const MAX_OUTPUT_BYTES: u64 = 64 * 1024;
+
+/// Which command to run, e.g. a Nagios plugin, and how long to let it run.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct CommandCheckConfig {
+    // the program then its arguments, run without a shell,
+    // e.g. `["/usr/lib/nagios/plugins/check_disk", "-w", "20%", "-p", "/"]`
+    command: Vec<String>,
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    timeout: Duration,
+}
+impl CommandCheckConfig {
+    pub fn new(command: &[&str]) -> Self {
+        CommandCheckConfig {
+            command: command.iter().map(|arg| arg.to_string()).collect(),
+            timeout: default_timeout(),
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        CommandCheckConfig { timeout, ..self }
+    }
+}
+
+// as Nagios does for its plugins
+fn default_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Checks a dependency by running a command following the Nagios plugin conventions,
+/// so existing check scripts can be reported without writing a checker.
+///
+/// Exit codes 0, 1 and 2 (OK, WARNING and CRITICAL) make the dependency Ok, Degraded and Down respectively.
+/// It is Unknown when the command exits with 3 (UNKNOWN) or any other code, can't be run, or doesn't exit in time,
+/// in which case it is killed along with the processes it started, as it is when the check is cancelled,
+/// e.g. by a dependency timeout shorter than the command's. The first line of its output,
+/// without performance data, is the reason it isn't Ok. Only the first 64 KiB of each output are read.
+pub struct CommandHealthChecker {
+    dependency: Dependency,
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+impl CommandHealthChecker {
+    pub fn new(name: &str, config: &CommandCheckConfig) -> Result<Self, CommandCheckError> {
+        let (program, args) = config
+            .command
+            .split_first()
+            .filter(|(program, _)| !program.trim().is_empty())
+            .ok_or(CommandCheckError::MissingProgram)?;
+        if config.timeout.is_zero() {
+            return Err(CommandCheckError::InvalidTimeout);
+        }
+
+        Ok(CommandHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Command),
+            program: program.clone(),
+            args: args.to_vec(),
+            timeout: config.timeout,
+        })
+    }
+
+    async fn run(&self) -> Result<Output, String> {
+        let failed = |e: io::Error| format!("Failed to run `{}`: {e}", self.program);
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // so a command timing out doesn't outlive its check
+            .kill_on_drop(true);
+        // in a group of its own, so the processes it starts, e.g. scripts running other commands, can be killed too
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = command.spawn().map_err(failed)?;
+        // killed on timeout, including when the check is cancelled from outside, e.g. by `check_within`
+        let group = ProcessGroup(child.id());
+
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let output = async {
+            let (stdout, stderr, status) =
+                tokio::try_join!(read_capped(stdout), read_capped(stderr), child.wait())?;
+            Ok(Output {
+                status,
+                stdout,
+                stderr,
+            })
+        };
+        match tokio::time::timeout(self.timeout, output).await {
+            Ok(output) => {
+                group.disarm();
+                output.map_err(failed)
+            }
+            Err(_) => Err(format!(
+                "`{}` timed out after {:?}",
+                self.program, self.timeout
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for CommandHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        let output = match self.run().await {
+            Ok(output) => output,
+            Err(reason) => {
+                return DependencyStatus::new(self.dependency(), Status::Unknown)
+                    .with_reason(reason)
+            }
+        };
+        let status = match output.status.code() {
+            Some(0) => Status::Ok,
+            Some(1) => Status::Degraded,
+            Some(2) => Status::Down,
+            _ => Status::Unknown,
+        };
+        let exit_code = output
+            .status
+            .code()
+            .map_or_else(|| "none".to_string(), |code| code.to_string());
+        let checked =
+            DependencyStatus::new(self.dependency(), status).with_detail("exit_code", exit_code);
+
+        // plugins print a single line, e.g. `DISK WARNING - 15% free | /=85%;80;90`,
+        // but some of them report errors on stderr only
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let Some(line) = [stdout, stderr]
+            .iter()
+            .filter_map(|output| output.lines().map(str::trim).find(|line| !line.is_empty()))
+            .next()
+            .map(str::to_string)
+        else {
+            return checked;
+        };
+        let (summary, performance_data) = match line.split_once('|') {
+            Some((summary, performance_data)) => (summary.trim(), Some(performance_data.trim())),
+            None => (line.as_str(), None),
+        };
+
+        let checked = checked.with_detail("output", summary);
+        let checked = match performance_data {
+            Some(performance_data) if !performance_data.is_empty() => {
+                checked.with_detail("performance_data", performance_data)
+            }
+            _ => checked,
+        };
+        if *checked.status() == Status::Ok {
+            checked
+        } else {
+            checked.with_reason(summary.to_string())
+        }
+    }
+}
+
+// Reads up to `MAX_OUTPUT_BYTES`, then discards the rest so the command doesn't block writing it
+async fn read_capped(pipe: Option<impl AsyncRead + Unpin>) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    if let Some(mut pipe) = pipe {
+        (&mut pipe)
+            .take(MAX_OUTPUT_BYTES)
+            .read_to_end(&mut output)
+            .await?;
+        tokio::io::copy(&mut pipe, &mut tokio::io::sink()).await?;
+    }
+    Ok(output)
+}
+
+// The process group of a running command, killed when dropped unless the command exited
+struct ProcessGroup(Option<u32>);
+impl ProcessGroup {
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        kill_group(self.0.take());
+    }
+}
+
+#[cfg(unix)]
+fn kill_group(group: Option<u32>) {
+    use rustix::process::{kill_process_group, Pid, Signal};
+
+    // the group may be gone already, nothing left to kill then
+    if let Some(group) = group.and_then(|id| Pid::from_raw(id as i32)) {
+        let _ = kill_process_group(group, Signal::KILL);
+    }
+}
+
+// killing the command itself on drop is all there is
+#[cfg(not(unix))]
+fn kill_group(_group: Option<u32>) {}
+
+#[derive(Debug, Display, Error)]
+pub enum CommandCheckError {
+    #[display("Missing program: the command must start with the program to run")]
+    MissingProgram,
+
+    #[display("Invalid timeout: must be greater than zero")]
+    InvalidTimeout,
+}
+impl CommandCheckError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CommandCheckError::MissingProgram => "command_check_missing_program",
+            CommandCheckError::InvalidTimeout => "command_check_invalid_timeout",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_matches;
+    use std::time::Instant;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn maps_nagios_exit_codes() {
+        struct TestCase {
+            exit_code: u8,
+            expected: Status,
+        }
+        let test_cases = [
+            TestCase {
+                exit_code: 0,
+                expected: Status::Ok,
+            },
+            TestCase {
+                exit_code: 1,
+                expected: Status::Degraded,
+            },
+            TestCase {
+                exit_code: 2,
+                expected: Status::Down,
+            },
+            TestCase {
+                exit_code: 3,
+                expected: Status::Unknown,
+            },
+            TestCase {
+                exit_code: 127,
+                expected: Status::Unknown,
+            },
+        ];
+
+        for case in test_cases {
+            let result = check(&format!("exit {}", case.exit_code)).await;
+
+            assert_eq!(*result.status(), case.expected, "exit {}", case.exit_code);
+            assert_eq!(
+                result.diagnostics().details()["exit_code"],
+                case.exit_code.to_string()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_first_output_line_without_performance_data() {
+        let result =
+            check("echo 'DISK WARNING - 15% free | /=85%;80;90'; echo 'more details'; exit 1")
+                .await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("DISK WARNING - 15% free")
+        );
+        assert_eq!(
+            result.diagnostics().details()["performance_data"],
+            "/=85%;80;90"
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_output_of_passing_commands_in_details() {
+        let result = check("echo 'PING OK - 0.1ms'").await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        assert_eq!(result.diagnostics().reason(), None);
+        assert_eq!(result.diagnostics().details()["output"], "PING OK - 0.1ms");
+    }
+
+    #[tokio::test]
+    async fn falls_back_on_error_output() {
+        let result = check("echo 'check_snitch: connection refused' >&2; exit 2").await;
+
+        assert_eq!(*result.status(), Status::Down);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("check_snitch: connection refused")
+        );
+    }
+
+    #[tokio::test]
+    async fn is_unknown_and_killed_when_timing_out() {
+        let checker = CommandHealthChecker::new(
+            "snitch",
+            &CommandCheckConfig::new(&["sleep", "5"]).with_timeout(Duration::from_millis(100)),
+        )
+        .unwrap();
+        let started = Instant::now();
+
+        let result = checker.check().await;
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(*result.status(), Status::Unknown);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("`sleep` timed out after 100ms")
+        );
+    }
+
+    // looks the processes up in /proc
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn kills_processes_started_by_the_command_when_timing_out() {
+        let (script, pid_file) = spawning_sleep("timing-out");
+        let checker = CommandHealthChecker::new(
+            "snitch",
+            &CommandCheckConfig::new(&["sh", "-c", &script])
+                .with_timeout(Duration::from_millis(200)),
+        )
+        .unwrap();
+
+        let result = checker.check().await;
+
+        assert_eq!(*result.status(), Status::Unknown);
+        assert_killed(&pid_file).await;
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn kills_processes_started_by_the_command_when_cancelled() {
+        use crate::health_check::timeouts::Timeouts;
+        use crate::health_check::version::test_kit::StubVersion;
+        use crate::health_check::version::{Build, Commit, Environment};
+        use crate::health_check::RusticSketchHealthChecker;
+
+        let (script, pid_file) = spawning_sleep("cancelled");
+        // the command may run for 10s by default, more than the dependency is given
+        let checker =
+            CommandHealthChecker::new("snitch", &CommandCheckConfig::new(&["sh", "-c", &script]))
+                .unwrap();
+        let version = StubVersion::new(
+            Environment::new("dev".to_string()),
+            Build::new("feat.branch.108".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        );
+        let health_checker = RusticSketchHealthChecker::new(Box::new(version), Vec::new())
+            .with_timeouts(Timeouts::default().dependency(Duration::from_millis(200)));
+
+        let result = health_checker.check_dependency(&checker).await;
+
+        assert!(result.diagnostics().reason().unwrap().contains("Timed out"));
+        assert_killed(&pid_file).await;
+    }
+
+    // A script starting a long sleep in the background, writing its pid to the returned file
+    #[cfg(target_os = "linux")]
+    fn spawning_sleep(test: &str) -> (String, std::path::PathBuf) {
+        let pid_file = std::env::temp_dir().join(format!(
+            "rustic-sketch.command-check-{test}-{}.pid",
+            std::process::id()
+        ));
+        let script = format!("sleep 5 & echo $! > {}; wait", pid_file.display());
+        (script, pid_file)
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn assert_killed(pid_file: &std::path::Path) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let sleeping = std::fs::read_to_string(pid_file).unwrap();
+        let _ = std::fs::remove_file(pid_file);
+        let state = std::fs::read_to_string(format!("/proc/{}/stat", sleeping.trim()));
+        // gone, or a zombie waiting for init to reap it
+        assert!(state.is_err() || state.unwrap().contains(") Z "));
+    }
+
+    #[tokio::test]
+    async fn reads_output_up_to_a_limit() {
+        let result = check("echo 'DISK OK'; head -c 1000000 /dev/zero; exit 0").await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        assert_eq!(result.diagnostics().details()["output"], "DISK OK");
+    }
+
+    #[tokio::test]
+    async fn output_is_capped_without_blocking_the_command() {
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        let writing = tokio::spawn(async move { writer.write_all(&vec![b'x'; 200 * 1024]).await });
+
+        let output = read_capped(Some(reader)).await.unwrap();
+
+        writing.await.unwrap().unwrap();
+        assert_eq!(output.len() as u64, MAX_OUTPUT_BYTES);
+    }
+
+    #[tokio::test]
+    async fn is_unknown_when_command_cannot_run() {
+        let checker = CommandHealthChecker::new(
+            "snitch",
+            &CommandCheckConfig::new(&["/usr/lib/nagios/plugins/check_nothing"]),
+        )
+        .unwrap();
+
+        let result = checker.check().await;
+
+        assert_eq!(*result.status(), Status::Unknown);
+        assert!(result
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("Failed to run"));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let empty = CommandHealthChecker::new("snitch", &CommandCheckConfig::new(&[]));
+        let blank = CommandHealthChecker::new("snitch", &CommandCheckConfig::new(&[" ", "-v"]));
+        let zero_timeout = CommandHealthChecker::new(
+            "snitch",
+            &CommandCheckConfig::new(&["true"]).with_timeout(Duration::ZERO),
+        );
+
+        assert_matches!(empty.err(), Some(CommandCheckError::MissingProgram));
+        assert_matches!(blank.err(), Some(CommandCheckError::MissingProgram));
+        assert_matches!(zero_timeout.err(), Some(CommandCheckError::InvalidTimeout));
+    }
+
+    async fn check(script: &str) -> DependencyStatus {
+        CommandHealthChecker::new("snitch", &CommandCheckConfig::new(&["sh", "-c", script]))
+            .unwrap()
+            .check()
+            .await
+    }
+}
@@ -0,0 +1,215 @@
+use super::{
+    service_status::{Dependency, DependencyKind, DependencyStatus, Status},
+    DependencyHealthChecker,
+};
+use async_trait::async_trait;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Which name to resolve and, optionally, which addresses it may resolve to.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct DnsCheckConfig {
+    // e.g. `db.internal`
+    host: String,
+    // any address when empty
+    #[serde(default)]
+    expected: Vec<IpAddr>,
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+}
+impl DnsCheckConfig {
+    pub fn new(host: &str) -> Self {
+        DnsCheckConfig {
+            host: host.to_string(),
+            expected: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    pub fn with_expected(self, expected: Vec<IpAddr>) -> Self {
+        DnsCheckConfig { expected, ..self }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        DnsCheckConfig {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+}
+
+/// Checks that a name resolves, through the system resolver so `/etc/hosts` and the like are honored.
+///
+/// The name is Down if it doesn't resolve in time, and Degraded if it resolves to any address outside the expected ones.
+/// Resolving to only some of the expected addresses is fine, as resolvers commonly rotate or trim them.
+/// The addresses and how long resolving took are reported in the details.
+pub struct DnsHealthChecker {
+    dependency: Dependency,
+    host: String,
+    expected: BTreeSet<IpAddr>,
+    timeout: Option<Duration>,
+}
+impl DnsHealthChecker {
+    pub fn new(name: &str, config: &DnsCheckConfig) -> Result<Self, DnsCheckError> {
+        // the resolver expects a name, not a url nor an address with a port
+        let host = &config.host;
+        if host.is_empty()
+            || host.contains(|c: char| c == '/' || c.is_whitespace())
+            || (host.contains(':') && host.parse::<IpAddr>().is_err())
+        {
+            return Err(DnsCheckError::InvalidHost {
+                host: config.host.clone(),
+            });
+        }
+
+        Ok(DnsHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Dns),
+            host: config.host.clone(),
+            expected: config.expected.iter().copied().collect(),
+            timeout: config.timeout,
+        })
+    }
+
+    async fn resolve(&self) -> Result<BTreeSet<IpAddr>, String> {
+        let lookup = tokio::net::lookup_host((self.host.as_str(), 0));
+        let addresses = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, lookup)
+                .await
+                .map_err(|_| format!("Timed out after {timeout:?}"))?,
+            None => lookup.await,
+        }
+        .map_err(|e| format!("Failed to resolve `{}`: {e}", self.host))?;
+        Ok(addresses.map(|address| address.ip()).collect())
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for DnsHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        let started = Instant::now();
+        let addresses = match self.resolve().await {
+            Ok(addresses) if addresses.is_empty() => {
+                return DependencyStatus::new(self.dependency(), Status::Down)
+                    .with_reason(format!("`{}` resolves to no address", self.host))
+            }
+            Ok(addresses) => addresses,
+            Err(reason) => {
+                return DependencyStatus::new(self.dependency(), Status::Down).with_reason(reason)
+            }
+        };
+        let resolved = DependencyStatus::new(self.dependency(), Status::Ok)
+            .with_detail("resolve_ms", started.elapsed().as_millis())
+            .with_detail("addresses", join(&addresses));
+
+        if self.expected.is_empty() || addresses.is_subset(&self.expected) {
+            return resolved;
+        }
+        let unexpected = addresses.difference(&self.expected).copied().collect();
+        resolved.with_status(Status::Degraded).with_reason(format!(
+            "`{}` resolves to unexpected addresses: {}",
+            self.host,
+            join(&unexpected)
+        ))
+    }
+}
+
+fn join(addresses: &BTreeSet<IpAddr>) -> String {
+    addresses
+        .iter()
+        .map(IpAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Display, Error)]
+pub enum DnsCheckError {
+    #[display("Invalid host `{host}`: expected a name to resolve, without a port")]
+    InvalidHost { host: String },
+}
+impl DnsCheckError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DnsCheckError::InvalidHost { .. } => "dns_check_invalid_host",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_matches;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[tokio::test]
+    async fn resolves_through_the_local_resolver_config() {
+        let result = check(DnsCheckConfig::new("localhost")).await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        let details = result.diagnostics().details();
+        assert!(details["addresses"].contains("127.0.0.1"));
+        assert!(details.contains_key("resolve_ms"));
+    }
+
+    #[tokio::test]
+    async fn is_ok_when_resolving_to_expected_addresses() {
+        // whether `localhost` resolves to IPv6 as well depends on the host
+        let result = check(DnsCheckConfig::new("localhost").with_expected(vec![
+            localhost(),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        ]))
+        .await;
+
+        assert_eq!(*result.status(), Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn is_degraded_when_resolving_to_unexpected_addresses() {
+        let result = check(
+            DnsCheckConfig::new("localhost")
+                .with_expected(vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]),
+        )
+        .await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        let reason = result.diagnostics().reason().unwrap();
+        assert!(reason.starts_with("`localhost` resolves to unexpected addresses: "));
+        assert!(reason.contains("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn is_down_when_not_resolving() {
+        // the `.invalid` top-level domain is reserved so it never resolves
+        let result = check(
+            DnsCheckConfig::new("rustic-sketch.invalid").with_timeout(Duration::from_secs(5)),
+        )
+        .await;
+
+        assert_eq!(*result.status(), Status::Down);
+    }
+
+    #[test]
+    fn rejects_invalid_host() {
+        let result = DnsHealthChecker::new("dns", &DnsCheckConfig::new("localhost:53"));
+
+        assert_matches!(result.err(), Some(DnsCheckError::InvalidHost { .. }));
+    }
+
+    async fn check(config: DnsCheckConfig) -> DependencyStatus {
+        DnsHealthChecker::new("dns", &config).unwrap().check().await
+    }
+
+    fn localhost() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    }
+}
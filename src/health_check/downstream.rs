@@ -0,0 +1,376 @@
+use super::{
+    service_status::{Dependency, DependencyKind, DependencyStatus, DownstreamStatus, Status},
+    DependencyHealthChecker,
+};
+use crate::routes::health_status::model::ServiceStatusPayload;
+use async_trait::async_trait;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use log::warn;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Where to find a downstream service's `/status` and how much of its dependency tree to keep.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct DownstreamCheckConfig {
+    // e.g. `http://snitch:3030/status`
+    url: String,
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+    // how many levels of services to nest, counting the downstream service itself
+    #[serde(default = "default_max_depth")]
+    max_depth: usize,
+}
+impl DownstreamCheckConfig {
+    pub fn new(url: &str) -> Self {
+        DownstreamCheckConfig {
+            url: url.to_string(),
+            timeout: None,
+            max_depth: default_max_depth(),
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        DownstreamCheckConfig {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        DownstreamCheckConfig { max_depth, ..self }
+    }
+}
+
+fn default_max_depth() -> usize {
+    3
+}
+
+/// Checks a service exposing the same `/status` contract, nesting what it reports about itself,
+/// and transitively about its own downstream services, in our status.
+///
+/// The downstream service is as healthy as it reports to be, or Degraded while in maintenance,
+/// and Down when it can't be connected to. Its statuses are requested as of version 2 of the contract.
+/// Services that appear again further down the tree, e.g. because two services depend on each other,
+/// are left out, as are services nested deeper than `max_depth`.
+pub struct DownstreamStatusHealthChecker {
+    dependency: Dependency,
+    // the name of this service, so cycles leading back to it are detected
+    origin: String,
+    client: Client,
+    url: Url,
+    max_depth: usize,
+}
+impl DownstreamStatusHealthChecker {
+    pub fn new(
+        name: &str,
+        origin: &str,
+        config: &DownstreamCheckConfig,
+    ) -> Result<Self, DownstreamCheckError> {
+        let mut url =
+            Url::parse(&config.url).map_err(|source| DownstreamCheckError::InvalidUrl {
+                url: config.url.clone(),
+                source,
+            })?;
+        // so Down and Unknown dependencies can be told apart; services ignore it before that version
+        if !url.query_pairs().any(|(key, _)| key == "contract") {
+            url.query_pairs_mut().append_pair("contract", "2");
+        }
+        if config.max_depth == 0 {
+            return Err(DownstreamCheckError::InvalidMaxDepth);
+        }
+        let mut client = Client::builder();
+        if let Some(timeout) = config.timeout {
+            client = client.timeout(timeout);
+        }
+        let client = client.build().map_err(DownstreamCheckError::Client)?;
+
+        Ok(DownstreamStatusHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::DownstreamStatus),
+            origin: origin.to_string(),
+            client,
+            url,
+            max_depth: config.max_depth,
+        })
+    }
+
+    async fn fetch(&self) -> Result<ServiceStatusPayload, (Status, String)> {
+        let response = self
+            .client
+            .get(self.url.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                let status = if e.is_connect() {
+                    Status::Down
+                } else {
+                    Status::Degraded
+                };
+                (status, e.without_url().to_string())
+            })?;
+        response.json::<ServiceStatusPayload>().await.map_err(|e| {
+            (
+                Status::Degraded,
+                format!("Invalid status payload: {}", e.without_url()),
+            )
+        })
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for DownstreamStatusHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        let payload = match self.fetch().await {
+            Ok(payload) => payload,
+            Err((status, reason)) => {
+                return DependencyStatus::new(self.dependency(), status).with_reason(reason)
+            }
+        };
+
+        let mut path = vec![self.origin.clone(), self.dependency.name().to_string()];
+        let mut cycles = Vec::new();
+        let downstream = prune(payload.into(), &mut path, self.max_depth - 1, &mut cycles);
+        if !cycles.is_empty() {
+            warn!(
+                "Left out services depending on each other: {}",
+                cycles.join(", ")
+            );
+        }
+
+        match downstream.status() {
+            Status::Ok => DependencyStatus::new(self.dependency(), Status::Ok),
+            reported => {
+                // a downstream service in maintenance can't be relied upon either
+                let status = match reported {
+                    Status::Maintenance => Status::Degraded,
+                    reported => reported.clone(),
+                };
+                DependencyStatus::new(self.dependency(), status).with_reason(format!(
+                    "`{}` reports its status as {reported}",
+                    self.dependency.name()
+                ))
+            }
+        }
+        .with_downstream(downstream)
+    }
+}
+
+// Keeps `depth` levels of downstream services, leaving out the ones already in `path`
+fn prune(
+    status: DownstreamStatus,
+    path: &mut Vec<String>,
+    depth: usize,
+    cycles: &mut Vec<String>,
+) -> DownstreamStatus {
+    if depth == 0 {
+        return status.with_downstream(BTreeMap::new());
+    }
+    let downstream = status
+        .downstream()
+        .iter()
+        .filter_map(|(name, nested)| {
+            if path.contains(name) {
+                cycles.push(format!("{} -> {name}", path.join(" -> ")));
+                return None;
+            }
+            path.push(name.clone());
+            let nested = prune(nested.clone(), path, depth - 1, cycles);
+            path.pop();
+            Some((name.clone(), nested))
+        })
+        .collect();
+    status.with_downstream(downstream)
+}
+
+#[derive(Debug, Display, Error)]
+pub enum DownstreamCheckError {
+    #[display("Invalid url `{url}`: {source}")]
+    InvalidUrl {
+        #[error(not(source))]
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[display("Invalid max depth: must be at least 1")]
+    InvalidMaxDepth,
+
+    #[display("Failed to build the http client: {_0}")]
+    Client(#[error(source)] reqwest::Error),
+}
+impl DownstreamCheckError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DownstreamCheckError::InvalidUrl { .. } => "downstream_check_invalid_url",
+            DownstreamCheckError::InvalidMaxDepth => "downstream_check_invalid_max_depth",
+            DownstreamCheckError::Client(_) => "downstream_check_client_failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_matches;
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn nests_downstream_version_and_dependencies() {
+        let address = stub_downstream(json!({
+            "env": "dev",
+            "build": "snitch.108",
+            "commit": "66bf883e145315e5a304f6a1b69c4aaa22ae9305",
+            "status": "Ok",
+            "dependencies": [{ "database": "Ok" }]
+        }));
+
+        let result = check(DownstreamCheckConfig::new(&status_url(&address))).await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        let downstream = result.downstream().unwrap();
+        assert_eq!(downstream.version().build().to_string(), "snitch.108");
+        assert_eq!(
+            downstream.dependencies(),
+            &[("database".to_string(), Status::Ok)]
+        );
+    }
+
+    #[tokio::test]
+    async fn is_degraded_when_downstream_reports_so() {
+        let address = stub_downstream(status_of("Degraded", json!({})));
+
+        let result = check(DownstreamCheckConfig::new(&status_url(&address))).await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("`snitch` reports its status as Degraded")
+        );
+    }
+
+    #[tokio::test]
+    async fn mirrors_downstream_being_down() {
+        let address = stub_downstream(status_of("Down", json!({})));
+
+        let result = check(DownstreamCheckConfig::new(&status_url(&address))).await;
+
+        assert_eq!(*result.status(), Status::Down);
+    }
+
+    #[tokio::test]
+    async fn is_down_when_unreachable() {
+        // nothing listens on port 1 of the loopback interface
+        let result = check(DownstreamCheckConfig::new("http://127.0.0.1:1/status")).await;
+
+        assert_eq!(*result.status(), Status::Down);
+        assert!(result.downstream().is_none());
+    }
+
+    #[tokio::test]
+    async fn is_degraded_when_payload_is_invalid() {
+        let address = stub_downstream(json!({ "status": "Fine" }));
+
+        let result = check(DownstreamCheckConfig::new(&status_url(&address))).await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert!(result
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("Invalid status payload"));
+        assert!(result.downstream().is_none());
+    }
+
+    #[tokio::test]
+    async fn leaves_out_services_leading_back_up_the_tree() {
+        let address = stub_downstream(status_of(
+            "Ok",
+            json!({
+                "rustic-sketch": status_of("Ok", json!({})),
+                "auth": status_of("Ok", json!({ "snitch": status_of("Ok", json!({})) })),
+            }),
+        ));
+
+        let result = check(DownstreamCheckConfig::new(&status_url(&address))).await;
+
+        let downstream = result.downstream().unwrap().downstream();
+        assert_eq!(downstream.keys().collect::<Vec<_>>(), vec!["auth"]);
+        assert!(downstream["auth"].downstream().is_empty());
+    }
+
+    #[tokio::test]
+    async fn limits_nesting_depth() {
+        let address = stub_downstream(status_of(
+            "Ok",
+            json!({ "auth": status_of("Ok", json!({ "vault": status_of("Ok", json!({})) })) }),
+        ));
+
+        let result =
+            check(DownstreamCheckConfig::new(&status_url(&address)).with_max_depth(2)).await;
+
+        let downstream = result.downstream().unwrap().downstream();
+        assert!(downstream["auth"].downstream().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let invalid_url = DownstreamStatusHealthChecker::new(
+            "snitch",
+            "rustic-sketch",
+            &DownstreamCheckConfig::new("snitch"),
+        );
+        let invalid_depth = DownstreamStatusHealthChecker::new(
+            "snitch",
+            "rustic-sketch",
+            &DownstreamCheckConfig::new("http://snitch/status").with_max_depth(0),
+        );
+
+        assert_matches!(
+            invalid_url.err(),
+            Some(DownstreamCheckError::InvalidUrl { .. })
+        );
+        assert_matches!(
+            invalid_depth.err(),
+            Some(DownstreamCheckError::InvalidMaxDepth)
+        );
+    }
+
+    async fn check(config: DownstreamCheckConfig) -> DependencyStatus {
+        DownstreamStatusHealthChecker::new("snitch", "rustic-sketch", &config)
+            .unwrap()
+            .check()
+            .await
+    }
+
+    fn status_url(address: &SocketAddr) -> String {
+        format!("http://{address}/status")
+    }
+
+    fn status_of(status: &str, downstream: Value) -> Value {
+        json!({
+            "env": "dev",
+            "build": "snapshot",
+            "commit": "66bf883e145315e5a304f6a1b69c4aaa22ae9305",
+            "status": status,
+            "dependencies": [],
+            "downstream": downstream
+        })
+    }
+
+    fn stub_downstream(payload: Value) -> SocketAddr {
+        let status = warp::path("status").map(move || warp::reply::json(&payload));
+        let (address, server) = warp::serve(status).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        address
+    }
+}
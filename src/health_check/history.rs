@@ -0,0 +1,294 @@
+use super::{
+    service_status::{ServiceStatus, Status},
+    HealthChecker,
+};
+use chrono::{DateTime, Utc};
+use getset::Getters;
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+
+// how many status updates a slow subscriber may fall behind before missing some
+const SUBSCRIBER_BACKLOG: usize = 16;
+
+/// A change of the overall status of the service, or of one of its dependencies.
+#[derive(Clone, Debug, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct Transition {
+    at: DateTime<Utc>,
+    // none for the overall status of the service
+    dependency: Option<String>,
+    // none when first observed
+    from: Option<Status>,
+    to: Status,
+    reason: Option<String>,
+}
+
+/// A recorded status of the service, along with how it differs from the previous one.
+#[derive(Clone, Debug, Getters)]
+#[getset(get = "pub")]
+pub struct StatusChange {
+    service_status: ServiceStatus,
+    transitions: Vec<Transition>,
+}
+
+/// The latest status transitions, oldest first, plus the subscribers to be told about new ones.
+///
+/// Only the last `capacity` transitions are kept, in memory, so the history starts over with each instance.
+#[derive(Clone)]
+pub struct StatusHistory {
+    state: Arc<Mutex<State>>,
+    updates: broadcast::Sender<StatusChange>,
+}
+
+struct State {
+    capacity: usize,
+    latest: Option<ServiceStatus>,
+    transitions: VecDeque<Transition>,
+}
+
+impl StatusHistory {
+    pub fn new(capacity: usize) -> Self {
+        let (updates, _) = broadcast::channel(SUBSCRIBER_BACKLOG);
+        StatusHistory {
+            state: Arc::new(Mutex::new(State {
+                capacity,
+                latest: None,
+                transitions: VecDeque::with_capacity(capacity),
+            })),
+            updates,
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records how `service_status` differs from the previously recorded one,
+    /// notifying subscribers when the overall status or the status of any dependency changed.
+    pub fn record(&self, service_status: &ServiceStatus) -> Vec<Transition> {
+        let mut state = self.state();
+        let at = Utc::now();
+        let previous: HashMap<&str, &Status> = state
+            .latest
+            .iter()
+            .flat_map(|latest| latest.dependencies())
+            .map(|d| (d.dependency().name(), d.status()))
+            .collect();
+
+        let mut transitions: Vec<Transition> = service_status
+            .dependencies()
+            .iter()
+            .filter(|d| previous.get(d.dependency().name()) != Some(&d.status()))
+            .map(|d| Transition {
+                at,
+                dependency: Some(d.dependency().name().to_string()),
+                from: previous.get(d.dependency().name()).cloned().cloned(),
+                to: d.status().clone(),
+                reason: d.diagnostics().reason().map(str::to_string),
+            })
+            .collect();
+        let overall = state.latest.as_ref().map(|latest| latest.status().clone());
+        if overall.as_ref() != Some(service_status.status()) {
+            transitions.push(Transition {
+                at,
+                dependency: None,
+                from: overall,
+                to: service_status.status().clone(),
+                reason: None,
+            });
+        }
+
+        state.latest = Some(service_status.clone());
+        if transitions.is_empty() {
+            return transitions;
+        }
+        for transition in &transitions {
+            if state.transitions.len() == state.capacity {
+                state.transitions.pop_front();
+            }
+            state.transitions.push_back(transition.clone());
+        }
+        // fails only when nobody is subscribed
+        let _ = self.updates.send(StatusChange {
+            service_status: service_status.clone(),
+            transitions: transitions.clone(),
+        });
+        transitions
+    }
+
+    pub fn transitions(&self) -> Vec<Transition> {
+        self.state().transitions.iter().cloned().collect()
+    }
+
+    /// The most recently recorded status, if any.
+    pub fn latest(&self) -> Option<ServiceStatus> {
+        self.state().latest.clone()
+    }
+
+    /// Receives every recorded status that differs from the previous one.
+    pub fn subscribe(&self) -> broadcast::Receiver<StatusChange> {
+        self.updates.subscribe()
+    }
+
+    /// Records the status of the service each time `changes` signals one of its dependencies changed,
+    /// until either the health checker is dropped or `changes` is closed.
+    ///
+    /// Nothing is recorded until the health checker has started, so dependencies not checked yet
+    /// don't show up as transitions from Unknown.
+    ///
+    /// Holds on to the health checker weakly, so following it doesn't keep it alive.
+    pub fn follow<H>(
+        &self,
+        health_checker: Weak<H>,
+        mut changes: watch::Receiver<()>,
+    ) -> JoinHandle<()>
+    where
+        H: HealthChecker + Send + Sync + 'static,
+    {
+        let history = self.clone();
+        tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                let Some(health_checker) = health_checker.upgrade() else {
+                    break;
+                };
+                if !health_checker.started() {
+                    continue;
+                }
+                match health_checker.check().await {
+                    Ok(service_status) => {
+                        history.record(&service_status);
+                    }
+                    Err(e) => warn!("Failed to record the status of the service: {e}"),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::service_status::test_kit::{database, snitch};
+    use crate::health_check::service_status::DependencyStatus;
+    use crate::health_check::test_kit::StubHealthChecker;
+    use crate::health_check::version::test_kit::StubVersion;
+    use crate::health_check::version::{Build, Commit, Environment};
+    use std::time::Duration;
+
+    #[test]
+    fn records_overall_and_dependency_transitions() {
+        let history = StatusHistory::new(10);
+        history.record(&status_of(Status::Ok, Status::Ok));
+
+        let transitions = history.record(&status_of(Status::Degraded, Status::Ok));
+
+        assert_eq!(
+            transitions
+                .iter()
+                .map(|t| (t.dependency().clone(), t.from().clone(), t.to().clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    Some("database".to_string()),
+                    Some(Status::Ok),
+                    Status::Degraded
+                ),
+                (None, Some(Status::Ok), Status::Degraded),
+            ]
+        );
+        assert_eq!(
+            transitions[0].reason().as_deref(),
+            Some("Connection refused")
+        );
+        assert_eq!(history.transitions().len(), 5);
+    }
+
+    #[test]
+    fn ignores_unchanged_statuses() {
+        let history = StatusHistory::new(10);
+        history.record(&status_of(Status::Ok, Status::Ok));
+
+        let transitions = history.record(&status_of(Status::Ok, Status::Ok));
+
+        assert!(transitions.is_empty());
+        assert_eq!(history.transitions().len(), 3);
+    }
+
+    #[test]
+    fn keeps_latest_transitions_only() {
+        let history = StatusHistory::new(2);
+
+        history.record(&status_of(Status::Ok, Status::Ok));
+        history.record(&status_of(Status::Ok, Status::Degraded));
+
+        let transitions = history.transitions();
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(transitions[0].dependency().as_deref(), Some("snitch"));
+        assert_eq!(transitions[1].dependency(), &None);
+    }
+
+    #[tokio::test]
+    async fn notifies_subscribers_of_changes_only() {
+        let history = StatusHistory::new(10);
+        let mut updates = history.subscribe();
+
+        history.record(&status_of(Status::Ok, Status::Ok));
+        history.record(&status_of(Status::Ok, Status::Ok));
+        history.record(&status_of(Status::Ok, Status::Degraded));
+
+        let first = updates.recv().await.unwrap();
+        let second = updates.recv().await.unwrap();
+        assert_eq!(*first.service_status().status(), Status::Ok);
+        assert_eq!(*second.service_status().status(), Status::Degraded);
+        assert_eq!(second.transitions().len(), 2);
+        assert!(updates.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn follows_changes_until_health_checker_is_dropped() {
+        let history = StatusHistory::new(10);
+        let health_checker = Arc::new(StubHealthChecker::new(Ok(status_of(
+            Status::Ok,
+            Status::Degraded,
+        ))));
+        let (changes, receiver) = watch::channel(());
+        let follower = history.follow(Arc::downgrade(&health_checker), receiver);
+
+        changes.send_replace(());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            history.latest().map(|latest| latest.status().clone()),
+            Some(Status::Degraded)
+        );
+
+        drop(health_checker);
+        changes.send_replace(());
+        tokio::time::timeout(Duration::from_secs(1), follower)
+            .await
+            .expect("follower should stop once the health checker is dropped")
+            .unwrap();
+    }
+
+    fn status_of(database_status: Status, snitch_status: Status) -> ServiceStatus {
+        let database_status = match database_status {
+            Status::Ok => DependencyStatus::new(database(), Status::Ok),
+            other => DependencyStatus::new(database(), other)
+                .with_reason("Connection refused".to_string()),
+        };
+        let version = StubVersion::new(
+            Environment::new("dev".to_string()),
+            Build::new("feat.branch.108".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        );
+        ServiceStatus::new(
+            version.into(),
+            vec![
+                database_status,
+                DependencyStatus::new(snitch(), snitch_status),
+            ],
+        )
+    }
+}
@@ -0,0 +1,416 @@
+use super::{
+    service_status::{Dependency, DependencyKind, DependencyStatus, Status},
+    DependencyHealthChecker,
+};
+use async_trait::async_trait;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Method, Request, Url};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How to probe an HTTP upstream, e.g. an identity provider or another service of the stack.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct HttpCheckConfig {
+    url: String,
+    #[serde(default = "default_method")]
+    method: String,
+    // any 2xx when empty
+    #[serde(default)]
+    expected_statuses: Vec<u16>,
+    // the response body must contain it, when set
+    #[serde(default)]
+    body_contains: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    // bounds the whole request, from connecting until the body is read
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    connect_timeout: Option<Duration>,
+}
+impl HttpCheckConfig {
+    /// A `GET` expecting any 2xx response.
+    pub fn new(url: &str) -> Self {
+        HttpCheckConfig {
+            url: url.to_string(),
+            method: default_method(),
+            expected_statuses: Vec::new(),
+            body_contains: None,
+            headers: HashMap::new(),
+            timeout: None,
+            connect_timeout: None,
+        }
+    }
+
+    pub fn with_method(self, method: &str) -> Self {
+        HttpCheckConfig {
+            method: method.to_string(),
+            ..self
+        }
+    }
+
+    pub fn with_expected_statuses(self, expected_statuses: Vec<u16>) -> Self {
+        HttpCheckConfig {
+            expected_statuses,
+            ..self
+        }
+    }
+
+    pub fn with_body_contains(self, body_contains: &str) -> Self {
+        HttpCheckConfig {
+            body_contains: Some(body_contains.to_string()),
+            ..self
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        HttpCheckConfig {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    pub fn with_connect_timeout(self, connect_timeout: Duration) -> Self {
+        HttpCheckConfig {
+            connect_timeout: Some(connect_timeout),
+            ..self
+        }
+    }
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+/// Checks an HTTP upstream by sending a request and inspecting the response.
+///
+/// The upstream is `Down` if it can't be connected to, and `Degraded` if the request otherwise fails,
+/// the response status isn't expected or the body doesn't contain the expected content.
+pub struct HttpHealthChecker {
+    dependency: Dependency,
+    client: Client,
+    method: Method,
+    url: Url,
+    headers: HeaderMap,
+    expected_statuses: Vec<u16>,
+    body_contains: Option<String>,
+}
+impl HttpHealthChecker {
+    pub fn new(name: &str, config: &HttpCheckConfig) -> Result<Self, HttpCheckError> {
+        let url = Url::parse(&config.url).map_err(|source| HttpCheckError::InvalidUrl {
+            url: config.url.clone(),
+            source,
+        })?;
+        let method = Method::from_bytes(config.method.to_uppercase().as_bytes()).map_err(|_| {
+            HttpCheckError::InvalidMethod {
+                method: config.method.clone(),
+            }
+        })?;
+        if let Some(status) = config
+            .expected_statuses
+            .iter()
+            .find(|status| !(100..600).contains(*status))
+        {
+            return Err(HttpCheckError::InvalidStatus { status: *status });
+        }
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let invalid = || HttpCheckError::InvalidHeader { name: name.clone() };
+                Ok((
+                    HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?,
+                    HeaderValue::from_str(value).map_err(|_| invalid())?,
+                ))
+            })
+            .collect::<Result<HeaderMap, HttpCheckError>>()?;
+
+        let mut client = Client::builder();
+        if let Some(timeout) = config.timeout {
+            client = client.timeout(timeout);
+        }
+        if let Some(connect_timeout) = config.connect_timeout {
+            client = client.connect_timeout(connect_timeout);
+        }
+        let client = client.build().map_err(HttpCheckError::Client)?;
+
+        Ok(HttpHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Http),
+            client,
+            method,
+            url,
+            headers,
+            expected_statuses: config.expected_statuses.clone(),
+            body_contains: config.body_contains.clone(),
+        })
+    }
+
+    fn request(&self) -> Request {
+        let mut request = Request::new(self.method.clone(), self.url.clone());
+        *request.headers_mut() = self.headers.clone();
+        request
+    }
+
+    fn is_expected(&self, status: u16) -> bool {
+        if self.expected_statuses.is_empty() {
+            (200..300).contains(&status)
+        } else {
+            self.expected_statuses.contains(&status)
+        }
+    }
+
+    async fn probe(&self) -> Result<(), (Status, String)> {
+        let response = self.client.execute(self.request()).await.map_err(|e| {
+            let status = if e.is_connect() {
+                Status::Down
+            } else {
+                Status::Degraded
+            };
+            (status, e.without_url().to_string())
+        })?;
+
+        let degraded = |reason| (Status::Degraded, reason);
+        let status = response.status();
+        if !self.is_expected(status.as_u16()) {
+            return Err(degraded(format!("Unexpected status {status}")));
+        }
+        if let Some(expected) = &self.body_contains {
+            let body = response
+                .text()
+                .await
+                .map_err(|e| degraded(format!("Failed to read body: {}", e.without_url())))?;
+            if !body.contains(expected.as_str()) {
+                return Err(degraded(format!(
+                    "Response body doesn't contain `{expected}`"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for HttpHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        match self.probe().await {
+            Ok(()) => DependencyStatus::new(self.dependency(), Status::Ok),
+            Err((status, reason)) => {
+                DependencyStatus::new(self.dependency(), status).with_reason(reason)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Display, Error)]
+pub enum HttpCheckError {
+    #[display("Invalid url `{url}`: {source}")]
+    InvalidUrl {
+        #[error(not(source))]
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[display("Invalid method `{method}`")]
+    InvalidMethod {
+        #[error(not(source))]
+        method: String,
+    },
+
+    #[display("Invalid expected status `{status}`")]
+    InvalidStatus {
+        #[error(not(source))]
+        status: u16,
+    },
+
+    #[display("Invalid header `{name}`")]
+    InvalidHeader {
+        #[error(not(source))]
+        name: String,
+    },
+
+    #[display("Failed to build the http client: {_0}")]
+    Client(#[error(source)] reqwest::Error),
+}
+impl HttpCheckError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            HttpCheckError::InvalidUrl { .. } => "http_check_invalid_url",
+            HttpCheckError::InvalidMethod { .. } => "http_check_invalid_method",
+            HttpCheckError::InvalidStatus { .. } => "http_check_invalid_status",
+            HttpCheckError::InvalidHeader { .. } => "http_check_invalid_header",
+            HttpCheckError::Client(_) => "http_check_client_failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_matches;
+    use std::net::SocketAddr;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn upstream_is_ok_when_it_responds_with_success() {
+        let address = stub_upstream();
+
+        let result = check(HttpCheckConfig::new(&format!("http://{address}/ok"))).await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        assert_eq!(result.dependency().kind(), &DependencyKind::Http);
+    }
+
+    #[tokio::test]
+    async fn upstream_is_degraded_on_unexpected_status() {
+        let address = stub_upstream();
+
+        let result = check(HttpCheckConfig::new(&format!(
+            "http://{address}/unavailable"
+        )))
+        .await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("Unexpected status 503 Service Unavailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_configured_statuses() {
+        let address = stub_upstream();
+
+        let result = check(
+            HttpCheckConfig::new(&format!("http://{address}/unavailable"))
+                .with_expected_statuses(vec![503]),
+        )
+        .await;
+
+        assert_eq!(*result.status(), Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn matches_response_body() {
+        let address = stub_upstream();
+        let config = HttpCheckConfig::new(&format!("http://{address}/ok"));
+
+        let matching = check(config.clone().with_body_contains("pong")).await;
+        let not_matching = check(config.with_body_contains("ping")).await;
+
+        assert_eq!(*matching.status(), Status::Ok);
+        assert_eq!(*not_matching.status(), Status::Degraded);
+        assert_eq!(
+            not_matching.diagnostics().reason(),
+            Some("Response body doesn't contain `ping`")
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_configured_method_and_headers() {
+        let address = stub_upstream();
+        let config = HttpCheckConfig::new(&format!("http://{address}/authorised"));
+
+        let with_header = check(
+            config
+                .clone()
+                .with_method("post")
+                .with_header("authorization", "Bearer token"),
+        )
+        .await;
+        let without_header = check(config.with_method("post")).await;
+
+        assert_eq!(*with_header.status(), Status::Ok);
+        assert_eq!(*without_header.status(), Status::Degraded);
+    }
+
+    #[tokio::test]
+    async fn upstream_is_degraded_when_it_times_out() {
+        let address = stub_upstream();
+
+        let result = check(
+            HttpCheckConfig::new(&format!("http://{address}/slow"))
+                .with_timeout(Duration::from_millis(50)),
+        )
+        .await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert!(result.diagnostics().reason().is_some());
+    }
+
+    #[tokio::test]
+    async fn upstream_is_down_when_unreachable() {
+        // nothing listens on port 1 of the loopback interface
+        let result = check(HttpCheckConfig::new("http://127.0.0.1:1/ok")).await;
+
+        assert_eq!(*result.status(), Status::Down);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let invalid_url = HttpHealthChecker::new("snitch", &HttpCheckConfig::new("snitch"));
+        let invalid_method = HttpHealthChecker::new(
+            "snitch",
+            &HttpCheckConfig::new("http://snitch/status").with_method("GET /"),
+        );
+        let invalid_status = HttpHealthChecker::new(
+            "snitch",
+            &HttpCheckConfig::new("http://snitch/status").with_expected_statuses(vec![1000]),
+        );
+        let invalid_header = HttpHealthChecker::new(
+            "snitch",
+            &HttpCheckConfig::new("http://snitch/status").with_header("x auth", "token"),
+        );
+
+        assert_matches!(invalid_url.err(), Some(HttpCheckError::InvalidUrl { .. }));
+        assert_matches!(
+            invalid_method.err(),
+            Some(HttpCheckError::InvalidMethod { .. })
+        );
+        assert_matches!(
+            invalid_status.err(),
+            Some(HttpCheckError::InvalidStatus { status: 1000 })
+        );
+        assert_matches!(invalid_header.err(), Some(HttpCheckError::InvalidHeader { name }) if name == "x auth");
+    }
+
+    async fn check(config: HttpCheckConfig) -> DependencyStatus {
+        HttpHealthChecker::new("snitch", &config)
+            .unwrap()
+            .check()
+            .await
+    }
+
+    fn stub_upstream() -> SocketAddr {
+        let ok = warp::path("ok").map(|| "pong");
+        let unavailable = warp::path("unavailable")
+            .map(|| warp::reply::with_status("down", StatusCode::SERVICE_UNAVAILABLE));
+        let authorised = warp::path("authorised")
+            .and(warp::post())
+            .and(warp::header::exact("authorization", "Bearer token"))
+            .map(|| "welcome");
+        let slow = warp::path("slow").then(|| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            "finally"
+        });
+
+        let (address, server) = warp::serve(ok.or(unavailable).or(authorised).or(slow))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        address
+    }
+}
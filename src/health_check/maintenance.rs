@@ -0,0 +1,484 @@
+use super::{service_status::ServiceStatus, HealthCheckError, HealthChecker};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use log::warn;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Why and until when an operator took the service out of rotation.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct Maintenance {
+    reason: String,
+    since: DateTime<Utc>,
+    // maintenance ends on its own, so a forgotten one doesn't keep the service out of rotation
+    until: DateTime<Utc>,
+}
+impl Maintenance {
+    pub fn new(
+        reason: &str,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Self, MaintenanceError> {
+        let reason = reason.trim();
+        if reason.is_empty() {
+            return Err(MaintenanceError::Invalid(
+                "reason must not be empty".to_string(),
+            ));
+        }
+        if until <= since {
+            return Err(MaintenanceError::Invalid(format!(
+                "until must be after {}",
+                since.to_rfc3339()
+            )));
+        }
+        Ok(Maintenance {
+            reason: reason.to_string(),
+            since,
+            until,
+        })
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        now < self.until
+    }
+}
+
+/// Where maintenance is kept: in memory for a single instance, or e.g. in `PostgresStore` for all of them.
+#[async_trait]
+pub trait MaintenanceStore {
+    /// The latest maintenance started, whether it has expired or not.
+    async fn current(&self) -> Result<Option<Maintenance>, MaintenanceError>;
+
+    /// Replaces the current maintenance, if any.
+    async fn start(&self, maintenance: &Maintenance) -> Result<(), MaintenanceError>;
+
+    async fn end(&self) -> Result<(), MaintenanceError>;
+}
+
+/// Keeps maintenance in memory, so it only applies to this instance.
+#[derive(Default)]
+pub struct LocalMaintenanceStore {
+    current: Mutex<Option<Maintenance>>,
+}
+impl LocalMaintenanceStore {
+    fn state(&self) -> std::sync::MutexGuard<'_, Option<Maintenance>> {
+        self.current.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+#[async_trait]
+impl MaintenanceStore for LocalMaintenanceStore {
+    async fn current(&self) -> Result<Option<Maintenance>, MaintenanceError> {
+        Ok(self.state().clone())
+    }
+
+    async fn start(&self, maintenance: &Maintenance) -> Result<(), MaintenanceError> {
+        *self.state() = Some(maintenance.clone());
+        Ok(())
+    }
+
+    async fn end(&self) -> Result<(), MaintenanceError> {
+        *self.state() = None;
+        Ok(())
+    }
+}
+
+// The current maintenance, or why it couldn't be read
+type Lookup = Result<Option<Maintenance>, String>;
+
+/// Keeps the maintenance of another store for a while, so checking health doesn't query it on every request.
+///
+/// Maintenance started or ended through this store applies right away, while maintenance started or ended
+/// by other instances sharing the store is picked up once the cached one is older than `refresh_interval`.
+/// Failing to access the store is cached as well.
+///
+/// Whenever the maintenance in effect changes, e.g. started, ended or expired, it is signalled through `changes`,
+/// so that the status history, and everything following it, records the service entering and leaving maintenance.
+pub struct CachedMaintenanceStore {
+    store: Arc<dyn MaintenanceStore + Send + Sync>,
+    refresh_interval: Duration,
+    cached: Mutex<Option<(Instant, Lookup)>>,
+    // the maintenance in effect when last signalled
+    in_effect: Mutex<Option<Maintenance>>,
+    changes: watch::Sender<()>,
+}
+impl CachedMaintenanceStore {
+    pub fn new(store: Arc<dyn MaintenanceStore + Send + Sync>, refresh_interval: Duration) -> Self {
+        CachedMaintenanceStore {
+            store,
+            refresh_interval,
+            cached: Mutex::new(None),
+            in_effect: Mutex::new(None),
+            changes: watch::channel(()).0,
+        }
+    }
+
+    /// Signals whenever the maintenance in effect changes.
+    pub fn changes(&self) -> watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    /// Refreshes the cached maintenance every `refresh_interval` until the store is dropped,
+    /// so maintenance expiring, or started or ended by other instances, is signalled without waiting for a check.
+    pub fn start_refreshing(self: &Arc<Self>) -> JoinHandle<()> {
+        let store = Arc::downgrade(self);
+        let mut interval = tokio::time::interval(self.refresh_interval);
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                // failures are cached, then reported by the checks reading them
+                let _ = store.current().await;
+            }
+        })
+    }
+
+    fn cache(&self, current: Lookup) {
+        let in_effect = match &current {
+            Ok(Some(maintenance)) if maintenance.is_active(Utc::now()) => Some(maintenance.clone()),
+            // as checks do when the store can't be reached
+            _ => None,
+        };
+        *self.cached.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some((Instant::now(), current));
+
+        let mut signalled = self
+            .in_effect
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if *signalled != in_effect {
+            *signalled = in_effect;
+            self.changes.send_replace(());
+        }
+    }
+
+    fn cached(&self) -> Option<Lookup> {
+        let cached = self.cached.lock().unwrap_or_else(PoisonError::into_inner);
+        cached
+            .as_ref()
+            .filter(|(cached_at, _)| cached_at.elapsed() < self.refresh_interval)
+            .map(|(_, current)| current.clone())
+    }
+}
+#[async_trait]
+impl MaintenanceStore for CachedMaintenanceStore {
+    async fn current(&self) -> Result<Option<Maintenance>, MaintenanceError> {
+        let current = match self.cached() {
+            Some(current) => current,
+            None => {
+                let current = self.store.current().await.map_err(|e| e.to_string());
+                self.cache(current.clone());
+                current
+            }
+        };
+        current.map_err(MaintenanceError::Store)
+    }
+
+    async fn start(&self, maintenance: &Maintenance) -> Result<(), MaintenanceError> {
+        self.store.start(maintenance).await?;
+        self.cache(Ok(Some(maintenance.clone())));
+        Ok(())
+    }
+
+    async fn end(&self) -> Result<(), MaintenanceError> {
+        self.store.end().await?;
+        self.cache(Ok(None));
+        Ok(())
+    }
+}
+
+/// Reports the service as in maintenance, and so not ready, while an operator says so.
+///
+/// Dependencies keep being checked and reported all along, so the service can be seen to be healthy
+/// before maintenance ends. When the store can't be reached, the service is reported as if not in maintenance.
+pub struct MaintenanceHealthChecker<H> {
+    health_checker: H,
+    store: Arc<dyn MaintenanceStore + Send + Sync>,
+}
+impl<H> MaintenanceHealthChecker<H> {
+    pub fn new(health_checker: H, store: Arc<dyn MaintenanceStore + Send + Sync>) -> Self {
+        MaintenanceHealthChecker {
+            health_checker,
+            store,
+        }
+    }
+
+    async fn apply(&self, service_status: ServiceStatus) -> ServiceStatus {
+        match self.store.current().await {
+            Ok(Some(maintenance)) if maintenance.is_active(Utc::now()) => {
+                service_status.with_maintenance(maintenance)
+            }
+            Ok(_) => service_status,
+            Err(e) => {
+                warn!("{e}");
+                service_status
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<H: HealthChecker + Send + Sync> HealthChecker for MaintenanceHealthChecker<H> {
+    async fn check(&self) -> Result<ServiceStatus, HealthCheckError> {
+        let service_status = self.health_checker.check().await?;
+        Ok(self.apply(service_status).await)
+    }
+
+    async fn check_fresh(&self) -> Result<ServiceStatus, HealthCheckError> {
+        let service_status = self.health_checker.check_fresh().await?;
+        Ok(self.apply(service_status).await)
+    }
+
+    fn started(&self) -> bool {
+        self.health_checker.started()
+    }
+}
+
+#[derive(Debug, Display, Error)]
+pub enum MaintenanceError {
+    #[display("Invalid maintenance: {_0}")]
+    Invalid(#[error(not(source))] String),
+
+    #[display("Failed to access maintenance: {_0}")]
+    Store(#[error(not(source))] String),
+}
+impl MaintenanceError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            MaintenanceError::Invalid(_) => "maintenance_invalid",
+            MaintenanceError::Store(_) => "maintenance_unavailable",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::history::StatusHistory;
+    use crate::health_check::service_status::test_kit::database;
+    use crate::health_check::service_status::{DependencyStatus, Status};
+    use crate::health_check::test_kit::StubHealthChecker;
+    use crate::health_check::version::test_kit::StubVersion;
+    use crate::health_check::version::{Build, Commit, Environment};
+    use chrono::Duration;
+    use claims::assert_matches;
+
+    #[tokio::test]
+    async fn reports_maintenance_along_with_dependencies() {
+        let store = Arc::new(LocalMaintenanceStore::default());
+        let health_checker = MaintenanceHealthChecker::new(
+            StubHealthChecker::new(Ok(healthy_status())),
+            store.clone(),
+        );
+        let now = Utc::now();
+        let maintenance =
+            Maintenance::new("Upgrading the database", now, now + Duration::hours(1)).unwrap();
+
+        store.start(&maintenance).await.unwrap();
+        let result = health_checker.check().await.unwrap();
+
+        assert_eq!(*result.status(), Status::Maintenance);
+        assert_eq!(result.maintenance(), Some(&maintenance));
+        assert_eq!(*result.dependencies()[0].status(), Status::Ok);
+        assert!(!result.is_ready());
+    }
+
+    #[tokio::test]
+    async fn ignores_expired_maintenance() {
+        let store = Arc::new(LocalMaintenanceStore::default());
+        let health_checker = MaintenanceHealthChecker::new(
+            StubHealthChecker::new(Ok(healthy_status())),
+            store.clone(),
+        );
+        let now = Utc::now();
+        let maintenance = Maintenance::new(
+            "Upgrading the database",
+            now - Duration::hours(2),
+            now - Duration::hours(1),
+        )
+        .unwrap();
+
+        store.start(&maintenance).await.unwrap();
+        let result = health_checker.check().await.unwrap();
+
+        assert_eq!(*result.status(), Status::Ok);
+        assert!(result.is_ready());
+    }
+
+    #[tokio::test]
+    async fn caches_maintenance_until_refresh_interval() {
+        let store = Arc::new(CountingMaintenanceStore::default());
+        let cached =
+            CachedMaintenanceStore::new(store.clone(), std::time::Duration::from_secs(3600));
+
+        for _ in 0..3 {
+            assert_eq!(cached.current().await.unwrap(), None);
+        }
+
+        assert_eq!(store.reads(), 1);
+    }
+
+    #[tokio::test]
+    async fn applies_maintenance_started_or_ended_through_the_cache_right_away() {
+        let store = Arc::new(CountingMaintenanceStore::default());
+        let cached =
+            CachedMaintenanceStore::new(store.clone(), std::time::Duration::from_secs(3600));
+        let now = Utc::now();
+        let maintenance =
+            Maintenance::new("Upgrading the database", now, now + Duration::hours(1)).unwrap();
+        cached.current().await.unwrap();
+
+        cached.start(&maintenance).await.unwrap();
+        assert_eq!(cached.current().await.unwrap(), Some(maintenance));
+        cached.end().await.unwrap();
+        assert_eq!(cached.current().await.unwrap(), None);
+
+        assert_eq!(store.reads(), 1);
+    }
+
+    #[tokio::test]
+    async fn picks_up_maintenance_started_elsewhere_once_refreshed() {
+        let store = Arc::new(CountingMaintenanceStore::default());
+        let cached =
+            CachedMaintenanceStore::new(store.clone(), std::time::Duration::from_millis(20));
+        let now = Utc::now();
+        let maintenance =
+            Maintenance::new("Upgrading the database", now, now + Duration::hours(1)).unwrap();
+        cached.current().await.unwrap();
+
+        store.start(&maintenance).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        assert_eq!(cached.current().await.unwrap(), Some(maintenance));
+        assert_eq!(store.reads(), 2);
+    }
+
+    #[tokio::test]
+    async fn signals_maintenance_started_ended_or_expired() {
+        let store = Arc::new(LocalMaintenanceStore::default());
+        let cached = Arc::new(CachedMaintenanceStore::new(
+            store.clone(),
+            std::time::Duration::from_millis(20),
+        ));
+        let mut changes = cached.changes();
+        let now = Utc::now();
+        let maintenance =
+            Maintenance::new("Upgrading the database", now, now + Duration::hours(1)).unwrap();
+        let expiring = Maintenance::new(
+            "Upgrading the database",
+            now,
+            now + Duration::milliseconds(50),
+        )
+        .unwrap();
+        let _refreshing = cached.start_refreshing();
+
+        cached.start(&maintenance).await.unwrap();
+        assert!(changes.has_changed().unwrap());
+        changes.mark_unchanged();
+        cached.start(&maintenance).await.unwrap();
+        assert!(!changes.has_changed().unwrap());
+
+        cached.end().await.unwrap();
+        assert!(changes.has_changed().unwrap());
+        changes.mark_unchanged();
+
+        cached.start(&expiring).await.unwrap();
+        changes.mark_unchanged();
+        tokio::time::timeout(std::time::Duration::from_secs(1), changes.changed())
+            .await
+            .expect("expired maintenance should be signalled")
+            .unwrap();
+        assert_eq!(cached.current().await.unwrap(), Some(expiring));
+    }
+
+    #[tokio::test]
+    async fn history_records_service_entering_and_leaving_maintenance() {
+        let store = Arc::new(CachedMaintenanceStore::new(
+            Arc::new(LocalMaintenanceStore::default()),
+            std::time::Duration::from_secs(3600),
+        ));
+        let health_checker = Arc::new(MaintenanceHealthChecker::new(
+            StubHealthChecker::new(Ok(healthy_status())),
+            store.clone(),
+        ));
+        let history = StatusHistory::new(10);
+        history.follow(Arc::downgrade(&health_checker), store.changes());
+        let now = Utc::now();
+        let maintenance =
+            Maintenance::new("Upgrading the database", now, now + Duration::hours(1)).unwrap();
+        history.record(&health_checker.check().await.unwrap());
+
+        store.start(&maintenance).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        store.end().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let overall: Vec<_> = history
+            .transitions()
+            .into_iter()
+            .filter(|transition| transition.dependency().is_none())
+            .map(|transition| transition.to().clone())
+            .collect();
+        assert_eq!(overall, vec![Status::Ok, Status::Maintenance, Status::Ok]);
+    }
+
+    #[test]
+    fn rejects_maintenance_without_reason_or_expiry() {
+        let now = Utc::now();
+
+        assert_matches!(
+            Maintenance::new("  ", now, now + Duration::hours(1)),
+            Err(MaintenanceError::Invalid(_))
+        );
+        assert_matches!(
+            Maintenance::new("Upgrading the database", now, now),
+            Err(MaintenanceError::Invalid(_))
+        );
+    }
+
+    // Counts how many times the current maintenance is read
+    #[derive(Default)]
+    struct CountingMaintenanceStore {
+        store: LocalMaintenanceStore,
+        reads: std::sync::atomic::AtomicUsize,
+    }
+    impl CountingMaintenanceStore {
+        fn reads(&self) -> usize {
+            self.reads.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+    #[async_trait]
+    impl MaintenanceStore for CountingMaintenanceStore {
+        async fn current(&self) -> Result<Option<Maintenance>, MaintenanceError> {
+            self.reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.store.current().await
+        }
+
+        async fn start(&self, maintenance: &Maintenance) -> Result<(), MaintenanceError> {
+            self.store.start(maintenance).await
+        }
+
+        async fn end(&self) -> Result<(), MaintenanceError> {
+            self.store.end().await
+        }
+    }
+
+    fn healthy_status() -> ServiceStatus {
+        let version = StubVersion::new(
+            Environment::new("dev".to_string()),
+            Build::new("feat.branch.108".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        );
+        ServiceStatus::new(
+            version.into(),
+            vec![DependencyStatus::new(database(), Status::Ok)],
+        )
+    }
+}
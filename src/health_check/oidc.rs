@@ -0,0 +1,417 @@
+use super::{
+    service_status::{Dependency, DependencyKind, DependencyStatus, Status},
+    DependencyHealthChecker,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::time::Duration;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// The OpenID Connect provider to check, e.g. an Auth0 tenant.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct OidcCheckConfig {
+    // exactly as advertised by the provider, e.g. `https://rustic.eu.auth0.com/`
+    issuer: String,
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+}
+impl OidcCheckConfig {
+    pub fn new(issuer: &str) -> Self {
+        OidcCheckConfig {
+            issuer: issuer.to_string(),
+            timeout: None,
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        OidcCheckConfig {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+}
+
+/// Checks an OpenID Connect provider through its discovery document and signing keys,
+/// i.e. whether tokens it issues could be verified right now.
+///
+/// The provider is `Down` if it can't be connected to, and `Degraded` if:
+///  - either document can't be fetched or parsed;
+///  - the issuer it advertises differs from the configured one;
+///  - its key set has no usable signing key, e.g. all of them have expired.
+///
+/// Keys expire according to their `exp` claim or the certificate they are published with (`x5c`),
+/// whichever comes first, e.g. Auth0 only publishes certificates.
+pub struct OidcHealthChecker {
+    dependency: Dependency,
+    client: Client,
+    issuer: String,
+    discovery_url: Url,
+}
+impl OidcHealthChecker {
+    pub fn new(name: &str, config: &OidcCheckConfig) -> Result<Self, OidcCheckError> {
+        let discovery_url = Url::parse(&format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        ))
+        .map_err(|source| OidcCheckError::InvalidIssuer {
+            issuer: config.issuer.clone(),
+            source,
+        })?;
+        let mut client = Client::builder();
+        if let Some(timeout) = config.timeout {
+            client = client.timeout(timeout);
+        }
+        let client = client.build().map_err(OidcCheckError::Client)?;
+
+        Ok(OidcHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Oidc),
+            client,
+            issuer: config.issuer.clone(),
+            discovery_url,
+        })
+    }
+
+    async fn probe(&self) -> Result<(), (Status, String)> {
+        let discovery: Discovery = self
+            .fetch(self.discovery_url.clone())
+            .await
+            .map_err(|(status, e)| (status, format!("Invalid discovery document: {e}")))?;
+        let degraded = |reason| (Status::Degraded, reason);
+        if discovery.issuer != self.issuer {
+            return Err(degraded(format!(
+                "Issuer drifted: expected `{}`, advertised `{}`",
+                self.issuer, discovery.issuer
+            )));
+        }
+
+        let jwks_uri = Url::parse(&discovery.jwks_uri)
+            .map_err(|e| degraded(format!("Invalid jwks_uri `{}`: {e}", discovery.jwks_uri)))?;
+        let jwks: Jwks = self
+            .fetch(jwks_uri)
+            .await
+            .map_err(|(status, e)| (status, format!("Invalid key set: {e}")))?;
+
+        let now = Utc::now().timestamp();
+        let signing_keys: Vec<_> = jwks.keys.iter().filter(|k| k.is_signing_key()).collect();
+        if signing_keys.is_empty() {
+            return Err(degraded("No usable signing keys".to_string()));
+        }
+        if signing_keys.iter().all(|k| k.has_expired(now)) {
+            return Err(degraded("All signing keys have expired".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, url: Url) -> Result<T, (Status, String)> {
+        let failed = |e: reqwest::Error| {
+            let status = if e.is_connect() {
+                Status::Down
+            } else {
+                Status::Degraded
+            };
+            (status, e.without_url().to_string())
+        };
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(failed)?
+            .json::<T>()
+            .await
+            .map_err(failed)
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for OidcHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        match self.probe().await {
+            Ok(()) => DependencyStatus::new(self.dependency(), Status::Ok),
+            Err((status, reason)) => {
+                DependencyStatus::new(self.dependency(), status).with_reason(reason)
+            }
+        }
+    }
+}
+
+// The subset of https://openid.net/specs/openid-connect-discovery-1_0.html we rely on
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    jwks_uri: String,
+}
+
+// See https://www.rfc-editor.org/rfc/rfc7517
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(rename = "use")]
+    usage: Option<String>,
+    key_ops: Option<Vec<String>>,
+    n: Option<String>,
+    e: Option<String>,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+    // seconds since the epoch; not part of the RFC, but published by some providers
+    exp: Option<i64>,
+    // base64 encoded DER certificates, the first one holding the key
+    x5c: Option<Vec<String>>,
+}
+impl Jwk {
+    fn is_signing_key(&self) -> bool {
+        let for_signing = self.usage.as_deref().is_none_or(|usage| usage == "sig")
+            && self
+                .key_ops
+                .as_ref()
+                .is_none_or(|ops| ops.iter().any(|op| op == "verify"));
+        let has_material = match self.kty.as_str() {
+            "RSA" => self.n.is_some() && self.e.is_some(),
+            "EC" => self.crv.is_some() && self.x.is_some() && self.y.is_some(),
+            "OKP" => self.crv.is_some() && self.x.is_some(),
+            _ => false,
+        };
+        for_signing && has_material
+    }
+
+    fn has_expired(&self, now: i64) -> bool {
+        self.expires_at()
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+
+    // In seconds since the epoch, unknown when the certificate can't be read
+    fn expires_at(&self) -> Option<i64> {
+        let not_after = self
+            .x5c
+            .as_ref()
+            .and_then(|chain| chain.first())
+            .and_then(|certificate| not_after(certificate));
+        match (self.exp, not_after) {
+            (Some(exp), Some(not_after)) => Some(exp.min(not_after)),
+            (exp, not_after) => exp.or(not_after),
+        }
+    }
+}
+
+fn not_after(certificate: &str) -> Option<i64> {
+    let der = STANDARD.decode(certificate).ok()?;
+    let (_, certificate) = X509Certificate::from_der(&der).ok()?;
+    Some(certificate.validity().not_after.timestamp())
+}
+
+#[derive(Debug, Display, Error)]
+pub enum OidcCheckError {
+    #[display("Invalid issuer `{issuer}`: {source}")]
+    InvalidIssuer {
+        #[error(not(source))]
+        issuer: String,
+        source: url::ParseError,
+    },
+
+    #[display("Failed to build the http client: {_0}")]
+    Client(#[error(source)] reqwest::Error),
+}
+impl OidcCheckError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            OidcCheckError::InvalidIssuer { .. } => "oidc_check_invalid_issuer",
+            OidcCheckError::Client(_) => "oidc_check_client_failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_matches;
+    use std::net::SocketAddr;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn provider_is_ok_with_usable_signing_keys() {
+        let address = stub_issuer();
+
+        let result = check(&issuer(&address, "valid")).await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        assert_eq!(result.dependency().kind(), &DependencyKind::Oidc);
+    }
+
+    #[tokio::test]
+    async fn provider_is_degraded_when_issuer_drifts() {
+        let address = stub_issuer();
+
+        let result = check(&issuer(&address, "drifted")).await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result
+                .diagnostics()
+                .reason()
+                .map(|r| r.starts_with("Issuer drifted")),
+            Some(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn provider_is_degraded_when_signing_keys_have_expired() {
+        let address = stub_issuer();
+
+        let result = check(&issuer(&address, "expired")).await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("All signing keys have expired")
+        );
+    }
+
+    #[tokio::test]
+    async fn provider_is_degraded_when_certificates_of_signing_keys_have_expired() {
+        let address = stub_issuer();
+
+        let result = check(&issuer(&address, "expired-x5c")).await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("All signing keys have expired")
+        );
+    }
+
+    #[tokio::test]
+    async fn provider_is_degraded_without_signing_keys() {
+        let address = stub_issuer();
+
+        let result = check(&issuer(&address, "encryption-only")).await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("No usable signing keys")
+        );
+    }
+
+    #[tokio::test]
+    async fn provider_is_degraded_when_discovery_is_unavailable() {
+        let address = stub_issuer();
+
+        let result = check(&issuer(&address, "unknown")).await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result
+                .diagnostics()
+                .reason()
+                .map(|r| r.starts_with("Invalid discovery document")),
+            Some(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn provider_is_down_when_unreachable() {
+        // nothing listens on port 1 of the loopback interface
+        let result = check("http://127.0.0.1:1/valid").await;
+
+        assert_eq!(*result.status(), Status::Down);
+    }
+
+    #[test]
+    fn keys_expire_with_their_certificate_or_exp_whichever_comes_first() {
+        let jwks: Jwks = serde_json::from_str(&fixture("jwks_expired_x5c.json")).unwrap();
+        let certificate = jwks.keys[0].x5c.clone().unwrap();
+        let key_with_exp = |exp: i64| -> Jwk {
+            serde_json::from_value(serde_json::json!({
+                "kty": "EC", "exp": exp, "x5c": certificate,
+            }))
+            .unwrap()
+        };
+
+        // 2020-01-01, the end of validity of the certificate
+        assert_eq!(jwks.keys[0].expires_at(), Some(1577836800));
+        assert_eq!(key_with_exp(1546300800).expires_at(), Some(1546300800));
+        assert_eq!(key_with_exp(1609459200).expires_at(), Some(1577836800));
+    }
+
+    #[test]
+    fn rejects_invalid_issuer() {
+        let result = OidcHealthChecker::new("auth0", &OidcCheckConfig::new("rustic.auth0.com"));
+
+        assert_matches!(result.err(), Some(OidcCheckError::InvalidIssuer { .. }));
+    }
+
+    async fn check(issuer: &str) -> DependencyStatus {
+        OidcHealthChecker::new("auth0", &OidcCheckConfig::new(issuer))
+            .unwrap()
+            .check()
+            .await
+    }
+
+    fn issuer(address: &SocketAddr, scenario: &str) -> String {
+        format!("http://{address}/{scenario}/")
+    }
+
+    // Serves each scenario under its own path, e.g. `/expired/.well-known/jwks.json`
+    fn stub_issuer() -> SocketAddr {
+        let discovery = warp::path!(String / ".well-known" / "openid-configuration")
+            .and(warp::header::<String>("host"))
+            .map(|scenario: String, host: String| {
+                let issuer = match scenario.as_str() {
+                    "drifted" => "https://drifted.eu.auth0.com/".to_string(),
+                    "valid" | "expired" | "expired-x5c" | "encryption-only" => {
+                        format!("http://{host}/{scenario}/")
+                    }
+                    _ => return json_reply(StatusCode::NOT_FOUND, String::new()),
+                };
+                let body = fixture("openid-configuration.json").replace("{issuer}", &issuer);
+                json_reply(StatusCode::OK, body)
+            });
+        let jwks =
+            warp::path!(String / ".well-known" / "jwks.json").map(
+                |scenario: String| match scenario.as_str() {
+                    "valid" => json_reply(StatusCode::OK, fixture("jwks.json")),
+                    "expired" => json_reply(StatusCode::OK, fixture("jwks_expired.json")),
+                    "expired-x5c" => json_reply(StatusCode::OK, fixture("jwks_expired_x5c.json")),
+                    "encryption-only" => {
+                        json_reply(StatusCode::OK, fixture("jwks_encryption_only.json"))
+                    }
+                    _ => json_reply(StatusCode::NOT_FOUND, String::new()),
+                },
+            );
+
+        let (address, server) = warp::serve(jwks.or(discovery)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        address
+    }
+
+    fn json_reply(status: StatusCode, body: String) -> impl warp::Reply {
+        warp::reply::with_status(
+            warp::reply::with_header(body, "content-type", "application/json"),
+            status,
+        )
+    }
+
+    fn fixture(name: &str) -> String {
+        let path = format!("tests/resources/oidc/{name}");
+        std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Could not read file `{path}`"))
+    }
+}
@@ -0,0 +1,295 @@
+use super::{
+    service_status::{DependencyStatus, ServiceStatus, Status},
+    HealthCheckError, HealthChecker, RusticSketchHealthChecker,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// How often each dependency is polled.
+#[derive(Clone, Debug)]
+pub struct Intervals {
+    default: Duration,
+    // by dependency name, taking precedence over `default`
+    overrides: HashMap<String, Duration>,
+}
+impl Intervals {
+    pub fn new(default: Duration) -> Self {
+        Intervals {
+            default,
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn dependency_override(mut self, name: &str, interval: Duration) -> Self {
+        self.overrides.insert(name.to_string(), interval);
+        self
+    }
+
+    pub fn for_dependency(&self, name: &str) -> Duration {
+        self.overrides.get(name).copied().unwrap_or(self.default)
+    }
+}
+
+// The latest known status of each dependency, by name, signalling when any of them changes
+#[derive(Clone)]
+struct Snapshots {
+    statuses: Arc<RwLock<HashMap<String, DependencyStatus>>>,
+    changes: Arc<watch::Sender<()>>,
+}
+impl Default for Snapshots {
+    fn default() -> Self {
+        Snapshots {
+            statuses: Arc::default(),
+            changes: Arc::new(watch::channel(()).0),
+        }
+    }
+}
+impl Snapshots {
+    fn get(&self, name: &str) -> Option<DependencyStatus> {
+        let statuses = self.statuses.read().unwrap_or_else(PoisonError::into_inner);
+        statuses.get(name).cloned()
+    }
+
+    fn record(&self, status: DependencyStatus) {
+        let mut statuses = self
+            .statuses
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let name = status.dependency().name().to_string();
+        let changed = statuses
+            .get(&name)
+            .is_none_or(|previous| previous.status() != status.status());
+        statuses.insert(name, status);
+        if changed {
+            self.changes.send_replace(());
+        }
+    }
+}
+
+/// Checks every dependency in the background, each one on its own interval,
+/// so health can be reported from the latest snapshots instead of probing dependencies on every request.
+///
+/// Dependencies that haven't been polled yet are reported as Unknown.
+pub struct PollingHealthChecker {
+    health_checker: Arc<RusticSketchHealthChecker>,
+    snapshots: Snapshots,
+    pollers: Vec<JoinHandle<()>>,
+}
+impl PollingHealthChecker {
+    /// Starts polling right away, so it must be called within a Tokio runtime.
+    pub fn start(health_checker: RusticSketchHealthChecker, intervals: &Intervals) -> Self {
+        let health_checker = Arc::new(health_checker);
+        let snapshots = Snapshots::default();
+
+        let pollers = health_checker
+            .dependency_health_checkers()
+            .iter()
+            .map(|checker| {
+                let interval = intervals.for_dependency(checker.dependency().name());
+                let (health_checker, checker, snapshots) =
+                    (health_checker.clone(), checker.clone(), snapshots.clone());
+                tokio::spawn(async move {
+                    let mut ticks = tokio::time::interval(interval);
+                    // a slow check postpones the next one instead of triggering a burst of catch-up checks
+                    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                    loop {
+                        ticks.tick().await;
+                        snapshots.record(health_checker.check_dependency(checker.as_ref()).await);
+                    }
+                })
+            })
+            .collect();
+
+        PollingHealthChecker {
+            health_checker,
+            snapshots,
+            pollers,
+        }
+    }
+
+    /// The latest known status of a dependency; see its diagnostics for when it was checked.
+    pub fn snapshot(&self, name: &str) -> Option<DependencyStatus> {
+        self.snapshots.get(name)
+    }
+
+    /// Signals each time a dependency is first checked or its status changes.
+    pub fn changes(&self) -> watch::Receiver<()> {
+        self.snapshots.changes.subscribe()
+    }
+}
+impl Drop for PollingHealthChecker {
+    fn drop(&mut self) {
+        self.pollers.iter().for_each(JoinHandle::abort);
+    }
+}
+
+#[async_trait]
+impl HealthChecker for PollingHealthChecker {
+    async fn check(&self) -> Result<ServiceStatus, HealthCheckError> {
+        let version = self.health_checker.version().await?;
+
+        let dependencies = self
+            .health_checker
+            .dependency_health_checkers()
+            .iter()
+            .map(|checker| {
+                let dependency = checker.dependency();
+                self.snapshots.get(dependency.name()).unwrap_or_else(|| {
+                    let criticality = self.health_checker.criticality_of(dependency.name());
+                    DependencyStatus::new(dependency, Status::Unknown)
+                        .with_criticality(criticality)
+                        .with_reason("Not checked yet".to_string())
+                })
+            })
+            .collect();
+
+        Ok(ServiceStatus::aggregated(
+            version,
+            dependencies,
+            self.health_checker.aggregation(),
+        ))
+    }
+
+    async fn check_fresh(&self) -> Result<ServiceStatus, HealthCheckError> {
+        let service_status = self.health_checker.check().await?;
+        service_status
+            .dependencies()
+            .iter()
+            .for_each(|status| self.snapshots.record(status.clone()));
+        Ok(service_status)
+    }
+
+    fn started(&self) -> bool {
+        self.health_checker
+            .dependency_health_checkers()
+            .iter()
+            .all(|checker| self.snapshots.get(checker.dependency().name()).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::service_status::test_kit::database;
+    use crate::health_check::test_kit::CountingDependencyHealthChecker;
+    use crate::health_check::version::test_kit::StubVersion;
+    use crate::health_check::version::{Build, Commit, Environment};
+
+    #[tokio::test]
+    async fn serves_cached_status_between_polls() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = polling(&database, Intervals::new(Duration::from_secs(3600)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        for _ in 0..3 {
+            let result = health_checker.check().await.unwrap();
+            assert_eq!(*result.status(), Status::Ok);
+        }
+
+        assert_eq!(database.checks(), 1);
+    }
+
+    #[tokio::test]
+    async fn reports_dependencies_not_polled_yet_as_unknown() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = polling(&database, Intervals::new(Duration::from_secs(3600)));
+
+        let result = health_checker.check().await.unwrap();
+
+        assert_eq!(*result.status(), Status::Unknown);
+        assert_eq!(*result.dependencies()[0].status(), Status::Unknown);
+        assert!(!result.is_ready());
+    }
+
+    #[tokio::test]
+    async fn polls_each_dependency_on_its_own_interval() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let _health_checker = polling(
+            &database,
+            Intervals::new(Duration::from_secs(3600))
+                .dependency_override("database", Duration::from_millis(20)),
+        );
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        assert!(database.checks() >= 3);
+    }
+
+    #[tokio::test]
+    async fn fresh_check_probes_dependencies_and_refreshes_snapshots() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = polling(&database, Intervals::new(Duration::from_secs(3600)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let polled_at = checked_at(&health_checker);
+
+        health_checker.check_fresh().await.unwrap();
+
+        assert_eq!(database.checks(), 2);
+        assert!(checked_at(&health_checker) > polled_at);
+    }
+
+    #[tokio::test]
+    async fn starts_once_every_dependency_has_been_checked() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = polling(&database, Intervals::new(Duration::from_secs(3600)));
+        assert!(!health_checker.started());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(health_checker.started());
+    }
+
+    #[tokio::test]
+    async fn signals_changes_only() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = polling(&database, Intervals::new(Duration::from_millis(10)));
+        let mut changes = health_checker.changes();
+
+        tokio::time::timeout(Duration::from_secs(1), changes.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(database.checks() > 1);
+        assert!(!changes.has_changed().unwrap());
+    }
+
+    #[tokio::test]
+    async fn stops_polling_when_dropped() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = polling(&database, Intervals::new(Duration::from_millis(10)));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        drop(health_checker);
+        let checks = database.checks();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(database.checks(), checks);
+    }
+
+    fn checked_at(health_checker: &PollingHealthChecker) -> chrono::DateTime<chrono::Utc> {
+        let snapshot = health_checker.snapshot("database").unwrap();
+        *snapshot.diagnostics().checked_at().unwrap()
+    }
+
+    fn polling(
+        database: &CountingDependencyHealthChecker,
+        intervals: Intervals,
+    ) -> PollingHealthChecker {
+        let versioned = StubVersion::new(
+            Environment::new("dev".to_string()),
+            Build::new("feat.branch.108".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        );
+        PollingHealthChecker::start(
+            RusticSketchHealthChecker::new(Box::new(versioned), vec![Box::new(database.clone())]),
+            &intervals,
+        )
+    }
+}
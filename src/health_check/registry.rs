@@ -0,0 +1,401 @@
+use super::{
+    command::{CommandCheckConfig, CommandHealthChecker},
+    dns::{DnsCheckConfig, DnsHealthChecker},
+    downstream::{DownstreamCheckConfig, DownstreamStatusHealthChecker},
+    http::{HttpCheckConfig, HttpHealthChecker},
+    oidc::{OidcCheckConfig, OidcHealthChecker},
+    service_status::Criticality,
+    tcp::{TcpCheckConfig, TcpHealthChecker},
+    DependencyHealthChecker,
+};
+use crate::store::postgres::PostgresStore;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// A dependency declared in config, e.g.
+///
+/// ```toml
+/// [[health.dependencies]]
+/// name = "snitch"
+/// kind = "downstream-status"
+/// url = "http://snitch:3030/status"
+/// criticality = "optional"
+/// interval = "30s"
+/// timeout = "1s"
+/// ```
+///
+/// Besides its name and kind, each dependency takes the settings of its kind's checker,
+/// e.g. `url` for `downstream-status`.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct DependencyConfig {
+    // reported in `/status`, and what `health.criticality` and the like refer to
+    name: String,
+    #[serde(flatten)]
+    check: CheckConfig,
+    // override `health.criticality`, `health.poll_intervals` and `health.dependency_timeouts` respectively
+    #[serde(default)]
+    criticality: Option<Criticality>,
+    #[serde(default, with = "humantime_serde")]
+    interval: Option<Duration>,
+    // also bounds the checker's own client, e.g. its http requests
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+}
+impl DependencyConfig {
+    pub fn new(name: &str, check: CheckConfig) -> Self {
+        DependencyConfig {
+            name: name.to_string(),
+            check,
+            criticality: None,
+            interval: None,
+            timeout: None,
+        }
+    }
+
+    pub fn with_criticality(self, criticality: Criticality) -> Self {
+        DependencyConfig {
+            criticality: Some(criticality),
+            ..self
+        }
+    }
+
+    pub fn with_interval(self, interval: Duration) -> Self {
+        DependencyConfig {
+            interval: Some(interval),
+            ..self
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        DependencyConfig {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+}
+
+/// The kind of a declared dependency and the settings of its checker.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum CheckConfig {
+    /// The service's own database, see `[database]`.
+    Postgres,
+    Http(HttpCheckConfig),
+    Oidc(OidcCheckConfig),
+    Tcp(TcpCheckConfig),
+    Dns(DnsCheckConfig),
+    Command(CommandCheckConfig),
+    DownstreamStatus(DownstreamCheckConfig),
+}
+impl CheckConfig {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CheckConfig::Postgres => "postgres",
+            CheckConfig::Http(_) => "http",
+            CheckConfig::Oidc(_) => "oidc",
+            CheckConfig::Tcp(_) => "tcp",
+            CheckConfig::Dns(_) => "dns",
+            CheckConfig::Command(_) => "command",
+            CheckConfig::DownstreamStatus(_) => "downstream-status",
+        }
+    }
+}
+
+/// Builds the checkers of the dependencies declared in config, in the order they are declared.
+///
+/// The database is connected to before the checkers are built, so `postgres` dependencies
+/// need it to be provided with `with_database`.
+pub struct DependencyRegistry {
+    // the name of this service, so downstream services depending on it back are detected
+    origin: String,
+    database: Option<PostgresStore>,
+}
+impl DependencyRegistry {
+    pub fn new(origin: &str) -> Self {
+        DependencyRegistry {
+            origin: origin.to_string(),
+            database: None,
+        }
+    }
+
+    pub fn with_database(self, database: PostgresStore) -> Self {
+        DependencyRegistry {
+            database: Some(database),
+            ..self
+        }
+    }
+
+    /// Rejects the first invalid dependency, leaving out whether a database is provided,
+    /// so dependencies can be validated along with the rest of the config.
+    pub fn validate(&self, dependencies: &[DependencyConfig]) -> Result<(), RegistryError> {
+        validate_declarations(dependencies)?;
+        for dependency in dependencies {
+            self.checker(dependency)?;
+        }
+        Ok(())
+    }
+
+    pub fn build(
+        &self,
+        dependencies: &[DependencyConfig],
+    ) -> Result<Vec<Box<dyn DependencyHealthChecker + Send + Sync>>, RegistryError> {
+        validate_declarations(dependencies)?;
+        dependencies
+            .iter()
+            .map(|dependency| {
+                self.checker(dependency)?
+                    .ok_or_else(|| RegistryError::MissingDatabase {
+                        name: dependency.name.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    // None for a `postgres` dependency while no database is provided
+    fn checker(
+        &self,
+        dependency: &DependencyConfig,
+    ) -> Result<Option<Box<dyn DependencyHealthChecker + Send + Sync>>, RegistryError> {
+        let name = dependency.name.as_str();
+        let timeout = dependency.timeout;
+        let invalid = |reason: String| RegistryError::InvalidCheck {
+            name: name.to_string(),
+            kind: dependency.check.kind(),
+            reason,
+        };
+
+        let checker: Box<dyn DependencyHealthChecker + Send + Sync> = match &dependency.check {
+            CheckConfig::Postgres => match &self.database {
+                Some(database) => Box::new(database.clone().with_dependency_name(name)),
+                None => return Ok(None),
+            },
+            CheckConfig::Http(config) => {
+                let config = with_timeout(config, timeout, HttpCheckConfig::with_timeout);
+                Box::new(HttpHealthChecker::new(name, &config).map_err(|e| invalid(e.to_string()))?)
+            }
+            CheckConfig::Oidc(config) => {
+                let config = with_timeout(config, timeout, OidcCheckConfig::with_timeout);
+                Box::new(OidcHealthChecker::new(name, &config).map_err(|e| invalid(e.to_string()))?)
+            }
+            CheckConfig::Tcp(config) => {
+                let config = with_timeout(config, timeout, TcpCheckConfig::with_timeout);
+                Box::new(TcpHealthChecker::new(name, &config).map_err(|e| invalid(e.to_string()))?)
+            }
+            CheckConfig::Dns(config) => {
+                let config = with_timeout(config, timeout, DnsCheckConfig::with_timeout);
+                Box::new(DnsHealthChecker::new(name, &config).map_err(|e| invalid(e.to_string()))?)
+            }
+            CheckConfig::Command(config) => {
+                let config = with_timeout(config, timeout, CommandCheckConfig::with_timeout);
+                Box::new(
+                    CommandHealthChecker::new(name, &config).map_err(|e| invalid(e.to_string()))?,
+                )
+            }
+            CheckConfig::DownstreamStatus(config) => {
+                let config = with_timeout(config, timeout, DownstreamCheckConfig::with_timeout);
+                Box::new(
+                    DownstreamStatusHealthChecker::new(name, &self.origin, &config)
+                        .map_err(|e| invalid(e.to_string()))?,
+                )
+            }
+        };
+        Ok(Some(checker))
+    }
+}
+
+fn with_timeout<C: Clone>(
+    config: &C,
+    timeout: Option<Duration>,
+    with: impl FnOnce(C, Duration) -> C,
+) -> C {
+    match timeout {
+        Some(timeout) => with(config.clone(), timeout),
+        None => config.clone(),
+    }
+}
+
+// What doesn't depend on the kind of the dependencies
+fn validate_declarations(dependencies: &[DependencyConfig]) -> Result<(), RegistryError> {
+    let mut names = HashSet::new();
+    for (index, dependency) in dependencies.iter().enumerate() {
+        let name = &dependency.name;
+        if name.trim().is_empty() {
+            return Err(RegistryError::MissingName { index });
+        }
+        if !names.insert(name.as_str()) {
+            return Err(RegistryError::DuplicateName { name: name.clone() });
+        }
+        if dependency
+            .interval
+            .is_some_and(|interval| interval.is_zero())
+        {
+            return Err(RegistryError::InvalidInterval { name: name.clone() });
+        }
+        if dependency.timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return Err(RegistryError::InvalidTimeout { name: name.clone() });
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Display, Error)]
+pub enum RegistryError {
+    #[display("Missing name of dependency #{index}: must not be empty")]
+    MissingName { index: usize },
+
+    #[display("Dependency `{name}` is declared more than once")]
+    DuplicateName { name: String },
+
+    #[display("Invalid interval of dependency `{name}`: must be greater than 0")]
+    InvalidInterval { name: String },
+
+    #[display("Invalid timeout of dependency `{name}`: must be greater than 0")]
+    InvalidTimeout { name: String },
+
+    #[display("Invalid {kind} dependency `{name}`: {reason}")]
+    InvalidCheck {
+        name: String,
+        kind: &'static str,
+        reason: String,
+    },
+
+    #[display("Missing database of postgres dependency `{name}`")]
+    MissingDatabase { name: String },
+}
+impl RegistryError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RegistryError::MissingName { .. } => "dependency_registry_missing_name",
+            RegistryError::DuplicateName { .. } => "dependency_registry_duplicate_name",
+            RegistryError::InvalidInterval { .. } => "dependency_registry_invalid_interval",
+            RegistryError::InvalidTimeout { .. } => "dependency_registry_invalid_timeout",
+            RegistryError::InvalidCheck { .. } => "dependency_registry_invalid_check",
+            RegistryError::MissingDatabase { .. } => "dependency_registry_missing_database",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::service_status::DependencyKind;
+    use claims::{assert_matches, assert_ok};
+
+    #[test]
+    fn builds_checkers_in_declared_order() {
+        let dependencies = [
+            DependencyConfig::new(
+                "snitch",
+                CheckConfig::DownstreamStatus(DownstreamCheckConfig::new(
+                    "http://snitch:3030/status",
+                )),
+            ),
+            DependencyConfig::new(
+                "broker",
+                CheckConfig::Tcp(TcpCheckConfig::new("broker:5672")),
+            ),
+            DependencyConfig::new(
+                "backups",
+                CheckConfig::Command(CommandCheckConfig::new(&["check_backups", "-w", "1d"])),
+            ),
+        ];
+
+        let checkers = DependencyRegistry::new("rustic-sketch")
+            .build(&dependencies)
+            .unwrap();
+
+        let declared = checkers
+            .iter()
+            .map(|checker| checker.dependency())
+            .map(|dependency| (dependency.name().to_string(), dependency.kind().clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            declared,
+            vec![
+                ("snitch".to_string(), DependencyKind::DownstreamStatus),
+                ("broker".to_string(), DependencyKind::Tcp),
+                ("backups".to_string(), DependencyKind::Command),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_declarations() {
+        let broker = || CheckConfig::Tcp(TcpCheckConfig::new("broker:5672"));
+        struct TestCase {
+            dependencies: Vec<DependencyConfig>,
+            expected_code: &'static str,
+        }
+        let test_cases = [
+            TestCase {
+                dependencies: vec![DependencyConfig::new(" ", broker())],
+                expected_code: "dependency_registry_missing_name",
+            },
+            TestCase {
+                dependencies: vec![
+                    DependencyConfig::new("broker", broker()),
+                    DependencyConfig::new("broker", broker()),
+                ],
+                expected_code: "dependency_registry_duplicate_name",
+            },
+            TestCase {
+                dependencies: vec![
+                    DependencyConfig::new("broker", broker()).with_interval(Duration::ZERO)
+                ],
+                expected_code: "dependency_registry_invalid_interval",
+            },
+            TestCase {
+                dependencies: vec![
+                    DependencyConfig::new("broker", broker()).with_timeout(Duration::ZERO)
+                ],
+                expected_code: "dependency_registry_invalid_timeout",
+            },
+            TestCase {
+                dependencies: vec![DependencyConfig::new(
+                    "broker",
+                    CheckConfig::Tcp(TcpCheckConfig::new("broker")),
+                )],
+                expected_code: "dependency_registry_invalid_check",
+            },
+        ];
+
+        for case in test_cases {
+            let result = DependencyRegistry::new("rustic-sketch").validate(&case.dependencies);
+
+            assert_eq!(result.unwrap_err().code(), case.expected_code);
+        }
+    }
+
+    #[test]
+    fn reports_which_dependency_is_invalid() {
+        let dependencies = [DependencyConfig::new(
+            "snitch",
+            CheckConfig::DownstreamStatus(DownstreamCheckConfig::new("snitch")),
+        )];
+
+        let result = DependencyRegistry::new("rustic-sketch").validate(&dependencies);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid downstream-status dependency `snitch`: \
+             Invalid url `snitch`: relative URL without a base"
+        );
+    }
+
+    #[test]
+    fn needs_a_database_to_build_postgres_dependencies() {
+        let dependencies = [DependencyConfig::new("database", CheckConfig::Postgres)];
+        let registry = DependencyRegistry::new("rustic-sketch");
+
+        assert_ok!(registry.validate(&dependencies));
+        assert_matches!(
+            registry.build(&dependencies).err(),
+            Some(RegistryError::MissingDatabase { name }) if name == "database"
+        );
+    }
+}
@@ -0,0 +1,700 @@
+use super::{
+    service_status::{Dependency, DependencyKind, DependencyStatus, Status},
+    DependencyHealthChecker,
+};
+use async_trait::async_trait;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use serde::Deserialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/// Which of the service's own resources are checked, each one reported as a dependency
+/// named after its key, e.g. `[health.self_checks.disk]` as `disk`.
+#[derive(Clone, Debug, Default, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct SelfChecksConfig {
+    #[serde(default)]
+    disk: Option<DiskCheckConfig>,
+    #[serde(default)]
+    file_descriptors: Option<FileDescriptorsCheckConfig>,
+    #[serde(default)]
+    memory: Option<MemoryCheckConfig>,
+    #[serde(default)]
+    runtime: Option<RuntimeCheckConfig>,
+}
+impl SelfChecksConfig {
+    pub fn with_disk(self, disk: DiskCheckConfig) -> Self {
+        SelfChecksConfig {
+            disk: Some(disk),
+            ..self
+        }
+    }
+
+    pub fn with_file_descriptors(self, file_descriptors: FileDescriptorsCheckConfig) -> Self {
+        SelfChecksConfig {
+            file_descriptors: Some(file_descriptors),
+            ..self
+        }
+    }
+
+    pub fn with_memory(self, memory: MemoryCheckConfig) -> Self {
+        SelfChecksConfig {
+            memory: Some(memory),
+            ..self
+        }
+    }
+
+    pub fn with_runtime(self, runtime: RuntimeCheckConfig) -> Self {
+        SelfChecksConfig {
+            runtime: Some(runtime),
+            ..self
+        }
+    }
+
+    /// A checker for each resource configured, rejecting thresholds that can't be met.
+    pub fn checkers(
+        &self,
+    ) -> Result<Vec<Box<dyn DependencyHealthChecker + Send + Sync>>, ResourceCheckError> {
+        let mut checkers: Vec<Box<dyn DependencyHealthChecker + Send + Sync>> = Vec::new();
+        if let Some(config) = &self.disk {
+            checkers.push(Box::new(DiskSpaceHealthChecker::new("disk", config)?));
+        }
+        if let Some(config) = &self.file_descriptors {
+            checkers.push(Box::new(FileDescriptorsHealthChecker::new(
+                "file-descriptors",
+                config,
+            )?));
+        }
+        if let Some(config) = &self.memory {
+            checkers.push(Box::new(MemoryHealthChecker::new("memory", config)?));
+        }
+        if let Some(config) = &self.runtime {
+            checkers.push(Box::new(RuntimeHealthChecker::new("runtime", config)?));
+        }
+        Ok(checkers)
+    }
+}
+
+// Thresholds are shares of a resource in use, between 0 and 1
+#[derive(Clone, Copy, Debug)]
+struct Thresholds {
+    degraded_at: f64,
+    down_at: f64,
+}
+impl Thresholds {
+    fn new(name: &str, degraded_at: f64, down_at: f64) -> Result<Self, ResourceCheckError> {
+        if !(degraded_at > 0.0 && degraded_at <= down_at && down_at <= 1.0) {
+            return Err(ResourceCheckError::InvalidThresholds {
+                name: name.to_string(),
+            });
+        }
+        Ok(Thresholds {
+            degraded_at,
+            down_at,
+        })
+    }
+
+    fn status(&self, usage: f64) -> Status {
+        if usage >= self.down_at {
+            Status::Down
+        } else if usage >= self.degraded_at {
+            Status::Degraded
+        } else {
+            Status::Ok
+        }
+    }
+
+    // Explains why `what` isn't Ok, e.g. `Memory budget at 97%`
+    fn assess(&self, dependency: Dependency, usage: f64, what: &str) -> DependencyStatus {
+        let status = DependencyStatus::new(dependency, self.status(usage))
+            .with_detail("usage", format!("{usage:.2}"));
+        if *status.status() == Status::Ok {
+            status
+        } else {
+            status.with_reason(format!("{what} at {:.0}%", usage * 100.0))
+        }
+    }
+}
+
+/// Where the service keeps its data and how full the volume may get.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct DiskCheckConfig {
+    // any path on the volume, e.g. `/var/lib/rustic`
+    path: PathBuf,
+    #[serde(default = "default_disk_degraded_at")]
+    degraded_at: f64,
+    #[serde(default = "default_disk_down_at")]
+    down_at: f64,
+}
+impl DiskCheckConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        DiskCheckConfig {
+            path: path.into(),
+            degraded_at: default_disk_degraded_at(),
+            down_at: default_disk_down_at(),
+        }
+    }
+
+    pub fn with_thresholds(self, degraded_at: f64, down_at: f64) -> Self {
+        DiskCheckConfig {
+            degraded_at,
+            down_at,
+            ..self
+        }
+    }
+}
+
+fn default_disk_degraded_at() -> f64 {
+    0.9
+}
+
+fn default_disk_down_at() -> f64 {
+    0.98
+}
+
+/// Checks how full the volume holding the service's data is.
+///
+/// Only the space available to the service counts as free, not the space reserved for root.
+pub struct DiskSpaceHealthChecker {
+    dependency: Dependency,
+    path: PathBuf,
+    thresholds: Thresholds,
+}
+impl DiskSpaceHealthChecker {
+    pub fn new(name: &str, config: &DiskCheckConfig) -> Result<Self, ResourceCheckError> {
+        Ok(DiskSpaceHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Resource),
+            path: config.path.clone(),
+            thresholds: Thresholds::new(name, config.degraded_at, config.down_at)?,
+        })
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for DiskSpaceHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        let path = self.path.clone();
+        // a hung network volume must not block a runtime worker
+        let space = tokio::task::spawn_blocking(move || disk_space(&path))
+            .await
+            .map_err(io::Error::other)
+            .and_then(|space| space);
+        match space {
+            Ok((_, 0)) => DependencyStatus::new(self.dependency(), Status::Ok),
+            Ok((available, total)) => {
+                let usage = 1.0 - available as f64 / total as f64;
+                self.thresholds
+                    .assess(
+                        self.dependency(),
+                        usage,
+                        &format!("Disk `{}`", self.path.display()),
+                    )
+                    .with_detail("available_bytes", available)
+                    .with_detail("total_bytes", total)
+            }
+            Err(e) => DependencyStatus::new(self.dependency(), Status::Unknown)
+                .with_reason(format!("Failed to inspect `{}`: {e}", self.path.display())),
+        }
+    }
+}
+
+// The bytes available to unprivileged users and the total size of the volume holding `path`
+#[cfg(unix)]
+fn disk_space(path: &Path) -> io::Result<(u64, u64)> {
+    let stats = rustix::fs::statvfs(path)?;
+    Ok((
+        stats.f_bavail.saturating_mul(stats.f_frsize),
+        stats.f_blocks.saturating_mul(stats.f_frsize),
+    ))
+}
+
+#[cfg(not(unix))]
+fn disk_space(_path: &Path) -> io::Result<(u64, u64)> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// How close the service may get to its limit of open files, sockets included.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct FileDescriptorsCheckConfig {
+    #[serde(default = "default_file_descriptors_degraded_at")]
+    degraded_at: f64,
+    #[serde(default = "default_file_descriptors_down_at")]
+    down_at: f64,
+}
+impl Default for FileDescriptorsCheckConfig {
+    fn default() -> Self {
+        FileDescriptorsCheckConfig {
+            degraded_at: default_file_descriptors_degraded_at(),
+            down_at: default_file_descriptors_down_at(),
+        }
+    }
+}
+impl FileDescriptorsCheckConfig {
+    pub fn with_thresholds(self, degraded_at: f64, down_at: f64) -> Self {
+        FileDescriptorsCheckConfig {
+            degraded_at,
+            down_at,
+        }
+    }
+}
+
+fn default_file_descriptors_degraded_at() -> f64 {
+    0.8
+}
+
+fn default_file_descriptors_down_at() -> f64 {
+    0.95
+}
+
+/// Checks how many file descriptors the process has open against its soft limit (`ulimit -n`).
+pub struct FileDescriptorsHealthChecker {
+    dependency: Dependency,
+    thresholds: Thresholds,
+}
+impl FileDescriptorsHealthChecker {
+    pub fn new(
+        name: &str,
+        config: &FileDescriptorsCheckConfig,
+    ) -> Result<Self, ResourceCheckError> {
+        Ok(FileDescriptorsHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Resource),
+            thresholds: Thresholds::new(name, config.degraded_at, config.down_at)?,
+        })
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for FileDescriptorsHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        match (open_file_descriptors(), file_descriptor_limit()) {
+            (Ok(open), Ok(Some(limit))) if limit > 0 => self
+                .thresholds
+                .assess(
+                    self.dependency(),
+                    open as f64 / limit as f64,
+                    "File descriptors",
+                )
+                .with_detail("open", open)
+                .with_detail("limit", limit),
+            // unlimited
+            (Ok(open), Ok(_)) => {
+                DependencyStatus::new(self.dependency(), Status::Ok).with_detail("open", open)
+            }
+            (Err(e), _) | (_, Err(e)) => DependencyStatus::new(self.dependency(), Status::Unknown)
+                .with_reason(format!("Failed to count file descriptors: {e}")),
+        }
+    }
+}
+
+// Including the one used to list them
+fn open_file_descriptors() -> io::Result<u64> {
+    let dir = if Path::new("/proc/self/fd").is_dir() {
+        "/proc/self/fd"
+    } else {
+        "/dev/fd"
+    };
+    Ok(std::fs::read_dir(dir)?.count() as u64)
+}
+
+#[cfg(unix)]
+fn file_descriptor_limit() -> io::Result<Option<u64>> {
+    use rustix::process::{getrlimit, Resource};
+
+    Ok(getrlimit(Resource::Nofile).current)
+}
+
+#[cfg(not(unix))]
+fn file_descriptor_limit() -> io::Result<Option<u64>> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// How much resident memory the service may use, e.g. its container's memory limit.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct MemoryCheckConfig {
+    // e.g. 512 for a container limited to 512MiB
+    budget_mib: u64,
+    #[serde(default = "default_memory_degraded_at")]
+    degraded_at: f64,
+    #[serde(default = "default_memory_down_at")]
+    down_at: f64,
+}
+impl MemoryCheckConfig {
+    pub fn new(budget_mib: u64) -> Self {
+        MemoryCheckConfig {
+            budget_mib,
+            degraded_at: default_memory_degraded_at(),
+            down_at: default_memory_down_at(),
+        }
+    }
+
+    pub fn with_thresholds(self, degraded_at: f64, down_at: f64) -> Self {
+        MemoryCheckConfig {
+            degraded_at,
+            down_at,
+            ..self
+        }
+    }
+}
+
+fn default_memory_degraded_at() -> f64 {
+    0.85
+}
+
+fn default_memory_down_at() -> f64 {
+    0.95
+}
+
+/// Checks the resident set size of the process against its budget, before the OOM killer does.
+pub struct MemoryHealthChecker {
+    dependency: Dependency,
+    budget_bytes: u64,
+    thresholds: Thresholds,
+}
+impl MemoryHealthChecker {
+    pub fn new(name: &str, config: &MemoryCheckConfig) -> Result<Self, ResourceCheckError> {
+        if config.budget_mib == 0 {
+            return Err(ResourceCheckError::InvalidBudget);
+        }
+        Ok(MemoryHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Resource),
+            budget_bytes: config.budget_mib.saturating_mul(1024 * 1024),
+            thresholds: Thresholds::new(name, config.degraded_at, config.down_at)?,
+        })
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for MemoryHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        match resident_set_size() {
+            Ok(resident) => self
+                .thresholds
+                .assess(
+                    self.dependency(),
+                    resident as f64 / self.budget_bytes as f64,
+                    "Memory budget",
+                )
+                .with_detail("resident_bytes", resident)
+                .with_detail("budget_bytes", self.budget_bytes),
+            Err(e) => DependencyStatus::new(self.dependency(), Status::Unknown)
+                .with_reason(format!("Failed to measure resident memory: {e}")),
+        }
+    }
+}
+
+// From `VmRSS` in `/proc/self/status`, so Linux only
+fn resident_set_size() -> io::Result<u64> {
+    let status = std::fs::read_to_string("/proc/self/status")?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kib| kib * 1024)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no VmRSS in /proc/self/status"))
+}
+
+/// How saturated the Tokio runtime may get before requests start queueing up.
+///
+/// The runtime is at worst Degraded: fully busy workers are still serving requests, only slower,
+/// so they must not take the service out of rotation.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct RuntimeCheckConfig {
+    // the share of time workers spent busy since the previous check
+    #[serde(default = "default_runtime_degraded_at")]
+    degraded_at: f64,
+    // tasks waiting in the global queue, from which the runtime is Degraded whatever its workers
+    #[serde(default = "default_max_queue_depth")]
+    max_queue_depth: usize,
+}
+impl Default for RuntimeCheckConfig {
+    fn default() -> Self {
+        RuntimeCheckConfig {
+            degraded_at: default_runtime_degraded_at(),
+            max_queue_depth: default_max_queue_depth(),
+        }
+    }
+}
+impl RuntimeCheckConfig {
+    pub fn with_degraded_at(self, degraded_at: f64) -> Self {
+        RuntimeCheckConfig {
+            degraded_at,
+            ..self
+        }
+    }
+
+    pub fn with_max_queue_depth(self, max_queue_depth: usize) -> Self {
+        RuntimeCheckConfig {
+            max_queue_depth,
+            ..self
+        }
+    }
+}
+
+fn default_runtime_degraded_at() -> f64 {
+    0.9
+}
+
+fn default_max_queue_depth() -> usize {
+    1000
+}
+
+/// Checks how busy the workers of the Tokio runtime running the checks have been since the previous check,
+/// and how many tasks are waiting for one of them.
+///
+/// The first check only looks at the queue, having nothing to compare busy time with.
+pub struct RuntimeHealthChecker {
+    dependency: Dependency,
+    thresholds: Thresholds,
+    max_queue_depth: usize,
+    // when the previous check happened, and how long workers had been busy in total by then
+    previous: Mutex<Option<(Instant, Duration)>>,
+}
+impl RuntimeHealthChecker {
+    pub fn new(name: &str, config: &RuntimeCheckConfig) -> Result<Self, ResourceCheckError> {
+        // never Down, see `RuntimeCheckConfig`
+        let thresholds = Thresholds {
+            down_at: f64::INFINITY,
+            ..Thresholds::new(name, config.degraded_at, 1.0)?
+        };
+        Ok(RuntimeHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Resource),
+            thresholds,
+            max_queue_depth: config.max_queue_depth,
+            previous: Mutex::new(None),
+        })
+    }
+
+    // The share of time workers spent busy since the previous check, if any
+    fn busy_ratio(&self, now: Instant, busy: Duration, workers: usize) -> Option<f64> {
+        let mut previous = self.previous.lock().unwrap_or_else(PoisonError::into_inner);
+        let ratio = previous.and_then(|(at, previously_busy)| {
+            let elapsed = now.duration_since(at).as_secs_f64() * workers as f64;
+            (elapsed > 0.0).then(|| busy.saturating_sub(previously_busy).as_secs_f64() / elapsed)
+        });
+        *previous = Some((now, busy));
+        ratio.map(|ratio| ratio.min(1.0))
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for RuntimeHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        let metrics = Handle::current().metrics();
+        let workers = metrics.num_workers();
+        let queue_depth = metrics.global_queue_depth();
+        let busy = (0..workers)
+            .map(|worker| metrics.worker_total_busy_duration(worker))
+            .sum();
+
+        let status = match self.busy_ratio(Instant::now(), busy, workers) {
+            Some(ratio) => self
+                .thresholds
+                .assess(self.dependency(), ratio, "Runtime workers"),
+            None => DependencyStatus::new(self.dependency(), Status::Ok),
+        };
+        let status = if queue_depth > self.max_queue_depth && *status.status() < Status::Degraded {
+            status
+                .with_status(Status::Degraded)
+                .with_reason(format!("{queue_depth} tasks waiting for a runtime worker"))
+        } else {
+            status
+        };
+        status
+            .with_detail("workers", workers)
+            .with_detail("queue_depth", queue_depth)
+            .with_detail("alive_tasks", metrics.num_alive_tasks())
+    }
+}
+
+#[derive(Debug, Display, Error, PartialEq)]
+pub enum ResourceCheckError {
+    #[display("Invalid thresholds of `{name}`: must be greater than 0, with degraded_at at most down_at, and down_at at most 1")]
+    InvalidThresholds {
+        #[error(not(source))]
+        name: String,
+    },
+
+    #[display("Invalid memory budget: must be at least 1 MiB")]
+    InvalidBudget,
+}
+impl ResourceCheckError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ResourceCheckError::InvalidThresholds { .. } => "resource_check_invalid_thresholds",
+            ResourceCheckError::InvalidBudget => "resource_check_invalid_budget",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_matches;
+
+    #[test]
+    fn degrades_then_goes_down_as_usage_grows() {
+        let thresholds = Thresholds::new("disk", 0.9, 0.98).unwrap();
+
+        assert_eq!(thresholds.status(0.5), Status::Ok);
+        assert_eq!(thresholds.status(0.9), Status::Degraded);
+        assert_eq!(thresholds.status(0.99), Status::Down);
+    }
+
+    #[tokio::test]
+    async fn reports_disk_usage_of_the_volume() {
+        let checker =
+            DiskSpaceHealthChecker::new("disk", &DiskCheckConfig::new(std::env::temp_dir()))
+                .unwrap();
+
+        let result = checker.check().await;
+
+        assert_ne!(*result.status(), Status::Unknown);
+        assert!(result
+            .diagnostics()
+            .details()
+            .contains_key("available_bytes"));
+        assert_eq!(result.dependency().kind(), &DependencyKind::Resource);
+    }
+
+    #[tokio::test]
+    async fn disk_usage_is_unknown_when_the_volume_cant_be_inspected() {
+        let checker = DiskSpaceHealthChecker::new(
+            "disk",
+            &DiskCheckConfig::new("/nonexistent/rustic-sketch"),
+        )
+        .unwrap();
+
+        let result = checker.check().await;
+
+        assert_eq!(*result.status(), Status::Unknown);
+        assert!(result
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("Failed to inspect `/nonexistent/rustic-sketch`"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn reports_open_file_descriptors_against_the_limit() {
+        let checker = FileDescriptorsHealthChecker::new(
+            "file-descriptors",
+            &FileDescriptorsCheckConfig::default(),
+        )
+        .unwrap();
+
+        let result = checker.check().await;
+
+        assert_ne!(*result.status(), Status::Unknown);
+        assert!(result.diagnostics().details().contains_key("open"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn memory_is_down_once_over_budget() {
+        let within = MemoryHealthChecker::new("memory", &MemoryCheckConfig::new(1024 * 1024))
+            .unwrap()
+            .check()
+            .await;
+        let over = MemoryHealthChecker::new("memory", &MemoryCheckConfig::new(1))
+            .unwrap()
+            .check()
+            .await;
+
+        assert_eq!(*within.status(), Status::Ok);
+        assert_eq!(*over.status(), Status::Down);
+        assert!(over
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("Memory budget"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn reports_runtime_workers_and_queue() {
+        let checker = RuntimeHealthChecker::new("runtime", &RuntimeCheckConfig::default()).unwrap();
+
+        checker.check().await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let result = checker.check().await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        assert_eq!(result.diagnostics().details()["workers"], "2");
+        assert!(result.diagnostics().details().contains_key("usage"));
+    }
+
+    #[test]
+    fn fully_busy_runtime_is_at_worst_degraded() {
+        let checker = RuntimeHealthChecker::new("runtime", &RuntimeCheckConfig::default()).unwrap();
+
+        let result = checker
+            .thresholds
+            .assess(checker.dependency(), 1.0, "Runtime workers");
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("Runtime workers at 100%")
+        );
+    }
+
+    #[test]
+    fn busy_ratio_is_the_share_of_worker_time_spent_busy() {
+        let checker = RuntimeHealthChecker::new("runtime", &RuntimeCheckConfig::default()).unwrap();
+        let now = Instant::now();
+
+        let first = checker.busy_ratio(now, Duration::from_secs(1), 2);
+        let second = checker.busy_ratio(now + Duration::from_secs(10), Duration::from_secs(16), 2);
+
+        assert_eq!(first, None);
+        assert_eq!(second, Some(0.75));
+    }
+
+    #[test]
+    fn rejects_thresholds_that_cant_be_met() {
+        let inverted = SelfChecksConfig::default()
+            .with_disk(DiskCheckConfig::new("/var/lib/rustic").with_thresholds(0.9, 0.5))
+            .checkers();
+        let no_budget = SelfChecksConfig::default()
+            .with_memory(MemoryCheckConfig::new(0))
+            .checkers();
+
+        assert_matches!(
+            inverted.err(),
+            Some(ResourceCheckError::InvalidThresholds { name }) if name == "disk"
+        );
+        assert_matches!(no_budget.err(), Some(ResourceCheckError::InvalidBudget));
+    }
+}
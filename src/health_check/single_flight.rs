@@ -0,0 +1,224 @@
+use super::{service_status::ServiceStatus, HealthCheckError, HealthChecker};
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+type Outcome = Result<ServiceStatus, HealthCheckError>;
+type SharedCheck = Shared<BoxFuture<'static, Outcome>>;
+
+/// Shares a single in-flight check among concurrent callers, so a burst of requests to `/status`
+/// results in one round of probes instead of one per request.
+///
+/// Optionally, the outcome of a check is reused for a short window after it completes.
+/// Fresh checks are shared and reused the same way, separately from the other checks,
+/// so `?fresh=true` bypasses cached results without each request probing every dependency.
+pub struct SingleFlightHealthChecker {
+    health_checker: Arc<dyn HealthChecker + Send + Sync>,
+    reuse_window: Option<Duration>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    cached: Flight,
+    fresh: Flight,
+}
+
+#[derive(Default)]
+struct Flight {
+    in_flight: Option<SharedCheck>,
+    last: Option<(Instant, Outcome)>,
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Cached,
+    Fresh,
+}
+impl State {
+    fn flight(&mut self, mode: Mode) -> &mut Flight {
+        match mode {
+            Mode::Cached => &mut self.cached,
+            Mode::Fresh => &mut self.fresh,
+        }
+    }
+}
+
+impl SingleFlightHealthChecker {
+    pub fn new(health_checker: impl HealthChecker + Send + Sync + 'static) -> Self {
+        SingleFlightHealthChecker {
+            health_checker: Arc::new(health_checker),
+            reuse_window: None,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn with_reuse_window(self, reuse_window: Duration) -> Self {
+        SingleFlightHealthChecker {
+            reuse_window: Some(reuse_window),
+            ..self
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Either the outcome of a recent check or the check to wait for, joining the one in flight if any
+    fn join(&self, mode: Mode) -> Result<Outcome, SharedCheck> {
+        let mut state = self.state();
+        let flight = state.flight(mode);
+        if let (Some(window), Some((completed_at, outcome))) = (self.reuse_window, &flight.last) {
+            if completed_at.elapsed() < window {
+                return Ok(outcome.clone());
+            }
+        }
+        let check = flight.in_flight.get_or_insert_with(|| {
+            let health_checker = self.health_checker.clone();
+            match mode {
+                Mode::Cached => async move { health_checker.check().await }.boxed(),
+                Mode::Fresh => async move { health_checker.check_fresh().await }.boxed(),
+            }
+            .shared()
+        });
+        Err(check.clone())
+    }
+
+    fn complete(&self, mode: Mode, check: &SharedCheck, outcome: &Outcome) {
+        let mut state = self.state();
+        let flight = state.flight(mode);
+        // only the first caller to see the check complete records it
+        if flight
+            .in_flight
+            .as_ref()
+            .is_some_and(|in_flight| in_flight.ptr_eq(check))
+        {
+            flight.in_flight = None;
+            flight.last = Some((Instant::now(), outcome.clone()));
+        }
+    }
+
+    async fn check_as(&self, mode: Mode) -> Outcome {
+        match self.join(mode) {
+            Ok(outcome) => outcome,
+            Err(check) => {
+                let outcome = check.clone().await;
+                self.complete(mode, &check, &outcome);
+                outcome
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl HealthChecker for SingleFlightHealthChecker {
+    async fn check(&self) -> Result<ServiceStatus, HealthCheckError> {
+        self.check_as(Mode::Cached).await
+    }
+
+    async fn check_fresh(&self) -> Result<ServiceStatus, HealthCheckError> {
+        self.check_as(Mode::Fresh).await
+    }
+
+    fn started(&self) -> bool {
+        self.health_checker.started()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::version::test_kit::StubVersion;
+    use crate::health_check::version::{Build, Commit, Environment};
+    use futures::future::join_all;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_check() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let health_checker = SingleFlightHealthChecker::new(SlowHealthChecker(checks.clone()));
+
+        let results = join_all((0..10).map(|_| health_checker.check())).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn checks_again_once_the_check_in_flight_completes() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let health_checker = SingleFlightHealthChecker::new(SlowHealthChecker(checks.clone()));
+
+        health_checker.check().await.unwrap();
+        health_checker.check().await.unwrap();
+
+        assert_eq!(checks.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn reuses_outcome_within_window() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let health_checker = SingleFlightHealthChecker::new(SlowHealthChecker(checks.clone()))
+            .with_reuse_window(Duration::from_millis(100));
+
+        health_checker.check().await.unwrap();
+        health_checker.check().await.unwrap();
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        health_checker.check().await.unwrap();
+        assert_eq!(checks.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn fresh_checks_bypass_reused_outcome() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let health_checker = SingleFlightHealthChecker::new(SlowHealthChecker(checks.clone()))
+            .with_reuse_window(Duration::from_secs(3600));
+
+        health_checker.check().await.unwrap();
+        health_checker.check_fresh().await.unwrap();
+
+        assert_eq!(checks.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_fresh_callers_share_one_probe() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let health_checker = SingleFlightHealthChecker::new(SlowHealthChecker(checks.clone()));
+
+        let results = join_all((0..10).map(|_| health_checker.check_fresh())).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn reuses_fresh_outcome_within_window() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let health_checker = SingleFlightHealthChecker::new(SlowHealthChecker(checks.clone()))
+            .with_reuse_window(Duration::from_secs(3600));
+
+        health_checker.check_fresh().await.unwrap();
+        health_checker.check_fresh().await.unwrap();
+
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+    }
+
+    // Counts its checks, each one taking a while
+    struct SlowHealthChecker(Arc<AtomicUsize>);
+    #[async_trait]
+    impl HealthChecker for SlowHealthChecker {
+        async fn check(&self) -> Result<ServiceStatus, HealthCheckError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let version = StubVersion::new(
+                Environment::new("dev".to_string()),
+                Build::new("feat.branch.108".to_string()),
+                Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+            );
+            Ok(ServiceStatus::new(version.into(), Vec::new()))
+        }
+    }
+}
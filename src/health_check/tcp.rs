@@ -0,0 +1,489 @@
+use super::{
+    service_status::{Dependency, DependencyKind, DependencyStatus, Status},
+    DependencyHealthChecker,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, ServerName};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Where to connect to, e.g. a broker or a cache, and whether to also complete a TLS handshake.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct TcpCheckConfig {
+    // e.g. `redis:6379` or `[::1]:443`
+    address: String,
+    // bounds connecting and the handshake altogether
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+    #[serde(default)]
+    tls: Option<TlsCheckConfig>,
+}
+impl TcpCheckConfig {
+    pub fn new(address: &str) -> Self {
+        TcpCheckConfig {
+            address: address.to_string(),
+            timeout: None,
+            tls: None,
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        TcpCheckConfig {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    pub fn with_tls(self, tls: TlsCheckConfig) -> Self {
+        TcpCheckConfig {
+            tls: Some(tls),
+            ..self
+        }
+    }
+}
+
+/// How to verify the certificate presented by the server, and how long before it expires to warn about it.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct TlsCheckConfig {
+    // the host of the address when not set
+    #[serde(default)]
+    server_name: Option<String>,
+    // PEM certificates of a private CA, trusting the Mozilla root CAs when not set
+    #[serde(default)]
+    ca_file: Option<PathBuf>,
+    #[serde(default = "default_expiry_warning", with = "humantime_serde")]
+    expiry_warning: Duration,
+}
+impl Default for TlsCheckConfig {
+    fn default() -> Self {
+        TlsCheckConfig {
+            server_name: None,
+            ca_file: None,
+            expiry_warning: default_expiry_warning(),
+        }
+    }
+}
+impl TlsCheckConfig {
+    pub fn with_server_name(self, server_name: &str) -> Self {
+        TlsCheckConfig {
+            server_name: Some(server_name.to_string()),
+            ..self
+        }
+    }
+
+    pub fn with_ca_file(self, ca_file: impl Into<PathBuf>) -> Self {
+        TlsCheckConfig {
+            ca_file: Some(ca_file.into()),
+            ..self
+        }
+    }
+
+    pub fn with_expiry_warning(self, expiry_warning: Duration) -> Self {
+        TlsCheckConfig {
+            expiry_warning,
+            ..self
+        }
+    }
+}
+
+fn default_expiry_warning() -> Duration {
+    Duration::from_secs(14 * 24 * 60 * 60)
+}
+
+/// Checks that a TCP endpoint accepts connections and, optionally, completes a TLS handshake with it.
+///
+/// The endpoint is Down if it can't be connected to in time or the handshake fails, e.g. because its certificate
+/// isn't trusted or has expired, and Degraded when its certificate expires within the warning period.
+/// How long connecting and the handshake took are reported in the details.
+pub struct TcpHealthChecker {
+    dependency: Dependency,
+    address: String,
+    timeout: Option<Duration>,
+    tls: Option<Tls>,
+}
+struct Tls {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+    expiry_warning: Duration,
+}
+impl TcpHealthChecker {
+    pub fn new(name: &str, config: &TcpCheckConfig) -> Result<Self, TcpCheckError> {
+        let host = host_of(&config.address).ok_or_else(|| TcpCheckError::InvalidAddress {
+            address: config.address.clone(),
+        })?;
+        let tls = config
+            .tls
+            .as_ref()
+            .map(|tls| {
+                let server_name = tls.server_name.as_deref().unwrap_or(host);
+                let server_name = ServerName::try_from(server_name.to_string()).map_err(|_| {
+                    TcpCheckError::InvalidServerName {
+                        server_name: server_name.to_string(),
+                    }
+                })?;
+                Ok(Tls {
+                    connector: TlsConnector::from(Arc::new(client_config(tls.ca_file.as_ref())?)),
+                    server_name,
+                    expiry_warning: tls.expiry_warning,
+                })
+            })
+            .transpose()?;
+
+        Ok(TcpHealthChecker {
+            dependency: Dependency::new(name, DependencyKind::Tcp),
+            address: config.address.clone(),
+            timeout: config.timeout,
+            tls,
+        })
+    }
+
+    async fn probe(&self) -> DependencyStatus {
+        let started = Instant::now();
+        let stream = match TcpStream::connect(&self.address).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                return DependencyStatus::new(self.dependency(), Status::Down)
+                    .with_reason(format!("Failed to connect to `{}`: {e}", self.address))
+            }
+        };
+        let connected = DependencyStatus::new(self.dependency(), Status::Ok)
+            .with_detail("connect_ms", started.elapsed().as_millis());
+        let Some(tls) = &self.tls else {
+            return connected;
+        };
+
+        let started = Instant::now();
+        let stream = match tls.connector.connect(tls.server_name.clone(), stream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                return connected
+                    .with_status(Status::Down)
+                    .with_reason(format!("TLS handshake failed: {e}"))
+            }
+        };
+        let handshaken = connected.with_detail("handshake_ms", started.elapsed().as_millis());
+
+        let (_, session) = stream.get_ref();
+        let not_after = session
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .and_then(|certificate| not_after(certificate));
+        match not_after {
+            Some(not_after) => {
+                let expires_in = (not_after - Utc::now()).to_std().unwrap_or_default();
+                let checked =
+                    handshaken.with_detail("certificate_expires_at", not_after.to_rfc3339());
+                if expires_in < tls.expiry_warning {
+                    checked.with_status(Status::Degraded).with_reason(format!(
+                        "Certificate of `{}` expires in {} day(s)",
+                        tls.server_name.to_str(),
+                        expires_in.as_secs() / (24 * 60 * 60)
+                    ))
+                } else {
+                    checked
+                }
+            }
+            None => handshaken
+                .with_status(Status::Degraded)
+                .with_reason("Failed to read the certificate expiry".to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl DependencyHealthChecker for TcpHealthChecker {
+    fn dependency(&self) -> Dependency {
+        self.dependency.clone()
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.probe())
+                .await
+                .unwrap_or_else(|_| {
+                    DependencyStatus::new(self.dependency(), Status::Down)
+                        .with_reason(format!("Timed out after {timeout:?}"))
+                }),
+            None => self.probe().await,
+        }
+    }
+}
+
+// The host of `host:port`, without the brackets of an IPv6 address
+fn host_of(address: &str) -> Option<&str> {
+    let (host, port) = address.rsplit_once(':')?;
+    port.parse::<u16>().ok()?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    Some(host).filter(|host| !host.is_empty())
+}
+
+fn client_config(ca_file: Option<&PathBuf>) -> Result<ClientConfig, TcpCheckError> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            let invalid = |reason: String| TcpCheckError::InvalidCaFile {
+                path: path.clone(),
+                reason,
+            };
+            let certificates = CertificateDer::pem_file_iter(path)
+                .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| invalid(e.to_string()))?;
+            if certificates.is_empty() {
+                return Err(invalid("no certificate found".to_string()));
+            }
+            for certificate in certificates {
+                roots.add(certificate).map_err(|e| invalid(e.to_string()))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    Ok(ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+// The end of the validity period of a DER certificate
+fn not_after(certificate: &CertificateDer) -> Option<DateTime<Utc>> {
+    let (_, certificate) = X509Certificate::from_der(certificate.as_ref()).ok()?;
+    DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0)
+}
+
+#[derive(Debug, Display, Error)]
+pub enum TcpCheckError {
+    #[display("Invalid address `{address}`: expected `host:port`")]
+    InvalidAddress { address: String },
+
+    #[display("Invalid TLS server name `{server_name}`")]
+    InvalidServerName { server_name: String },
+
+    #[display("Invalid CA file `{}`: {reason}", path.display())]
+    InvalidCaFile { path: PathBuf, reason: String },
+}
+impl TcpCheckError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            TcpCheckError::InvalidAddress { .. } => "tcp_check_invalid_address",
+            TcpCheckError::InvalidServerName { .. } => "tcp_check_invalid_server_name",
+            TcpCheckError::InvalidCaFile { .. } => "tcp_check_invalid_ca_file",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_matches;
+    use rustls_pki_types::PrivateKeyDer;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    const CA: &str = "tests/resources/tls/ca.pem";
+
+    #[tokio::test]
+    async fn is_ok_when_accepting_connections() {
+        let address = stub_listener().await;
+
+        let result = check(TcpCheckConfig::new(&address.to_string())).await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        assert!(result.diagnostics().details().contains_key("connect_ms"));
+    }
+
+    #[tokio::test]
+    async fn is_down_when_unreachable() {
+        // nothing listens on port 1 of the loopback interface
+        let result = check(TcpCheckConfig::new("127.0.0.1:1")).await;
+
+        assert_eq!(*result.status(), Status::Down);
+    }
+
+    #[tokio::test]
+    async fn completes_tls_handshake() {
+        let address = stub_tls_listener().await;
+
+        let result = check(
+            TcpCheckConfig::new(&address.to_string())
+                .with_tls(TlsCheckConfig::default().with_ca_file(CA)),
+        )
+        .await;
+
+        assert_eq!(*result.status(), Status::Ok);
+        let details = result.diagnostics().details();
+        assert!(details.contains_key("handshake_ms"));
+        // see `tests/resources/tls/localhost.pem`
+        assert_eq!(
+            details["certificate_expires_at"],
+            "2126-09-24T06:58:04+00:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn verifies_certificate_against_server_name() {
+        let address = stub_tls_listener().await;
+        let tls = TlsCheckConfig::default().with_ca_file(CA);
+
+        let localhost = check(
+            TcpCheckConfig::new(&address.to_string())
+                .with_tls(tls.clone().with_server_name("localhost")),
+        )
+        .await;
+        let other = check(
+            TcpCheckConfig::new(&address.to_string())
+                .with_tls(tls.with_server_name("snitch.internal")),
+        )
+        .await;
+
+        assert_eq!(*localhost.status(), Status::Ok);
+        assert_eq!(*other.status(), Status::Down);
+    }
+
+    #[tokio::test]
+    async fn is_down_when_certificate_is_not_trusted() {
+        let address = stub_tls_listener().await;
+
+        let result =
+            check(TcpCheckConfig::new(&address.to_string()).with_tls(TlsCheckConfig::default()))
+                .await;
+
+        assert_eq!(*result.status(), Status::Down);
+        assert!(result
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("TLS handshake failed"));
+    }
+
+    #[tokio::test]
+    async fn warns_about_certificate_expiring_soon() {
+        let address = stub_tls_listener().await;
+        // longer than the test certificate is valid for
+        let expiry_warning = Duration::from_secs(200 * 365 * 24 * 60 * 60);
+
+        let result = check(
+            TcpCheckConfig::new(&address.to_string()).with_tls(
+                TlsCheckConfig::default()
+                    .with_ca_file(CA)
+                    .with_expiry_warning(expiry_warning),
+            ),
+        )
+        .await;
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert!(result
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("Certificate of `127.0.0.1` expires in"));
+    }
+
+    #[tokio::test]
+    async fn is_down_when_timing_out() {
+        // accepts connections but never answers the handshake
+        let address = stub_listener().await;
+
+        let result = check(
+            TcpCheckConfig::new(&address.to_string())
+                .with_timeout(Duration::from_millis(100))
+                .with_tls(TlsCheckConfig::default().with_ca_file(CA)),
+        )
+        .await;
+
+        assert_eq!(*result.status(), Status::Down);
+        assert!(result
+            .diagnostics()
+            .reason()
+            .unwrap()
+            .starts_with("Timed out"));
+    }
+
+    #[test]
+    fn reads_certificate_expiry() {
+        let ca = CertificateDer::from_pem_file(CA).unwrap();
+
+        assert_eq!(
+            not_after(&ca).map(|not_after| not_after.to_rfc3339()),
+            Some("2126-09-24T06:58:04+00:00".to_string())
+        );
+        assert_eq!(not_after(&CertificateDer::from(vec![0x30, 0x82])), None);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let without_port = TcpHealthChecker::new("broker", &TcpCheckConfig::new("broker"));
+        let missing_ca = TcpHealthChecker::new(
+            "broker",
+            &TcpCheckConfig::new("broker:443")
+                .with_tls(TlsCheckConfig::default().with_ca_file("tests/resources/tls/none.pem")),
+        );
+
+        assert_matches!(
+            without_port.err(),
+            Some(TcpCheckError::InvalidAddress { .. })
+        );
+        assert_matches!(missing_ca.err(), Some(TcpCheckError::InvalidCaFile { .. }));
+    }
+
+    async fn check(config: TcpCheckConfig) -> DependencyStatus {
+        TcpHealthChecker::new("broker", &config)
+            .unwrap()
+            .check()
+            .await
+    }
+
+    // Accepts connections, holding on to them without a word
+    async fn stub_listener() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        address
+    }
+
+    // Completes TLS handshakes as `localhost` and `127.0.0.1`, with a certificate issued by the test CA
+    async fn stub_tls_listener() -> SocketAddr {
+        let certificates = CertificateDer::pem_file_iter("tests/resources/tls/localhost.pem")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_file("tests/resources/tls/localhost.key").unwrap();
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let _ = acceptor.accept(stream).await;
+                });
+            }
+        });
+        address
+    }
+}
@@ -0,0 +1,101 @@
+use super::{service_status::DependencyStatus, DependencyHealthChecker};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How long dependency checks are allowed to take before being reported as timed out.
+///
+/// Dependencies are checked concurrently, so the overall timeout bounds how long a health check
+/// waits for all of them, along with anything else it does, while the per dependency timeouts bound each check individually.
+#[derive(Clone, Debug, Default)]
+pub struct Timeouts {
+    overall: Option<Duration>,
+    dependency: Option<Duration>,
+    // by dependency name, taking precedence over `dependency`
+    overrides: HashMap<String, Duration>,
+}
+impl Timeouts {
+    pub fn overall(self, timeout: Duration) -> Self {
+        Timeouts {
+            overall: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Applies to every dependency without an override.
+    pub fn dependency(self, timeout: Duration) -> Self {
+        Timeouts {
+            dependency: Some(timeout),
+            ..self
+        }
+    }
+
+    pub fn dependency_override(mut self, name: &str, timeout: Duration) -> Self {
+        self.overrides.insert(name.to_string(), timeout);
+        self
+    }
+
+    /// How long a whole health check may take, loading the version included, if limited.
+    pub fn for_health_check(&self) -> Option<Duration> {
+        self.overall
+    }
+
+    /// The effective timeout for checking `name`, if any.
+    pub fn for_dependency(&self, name: &str) -> Option<Duration> {
+        let dependency = self.overrides.get(name).copied().or(self.dependency);
+        match (dependency, self.overall) {
+            (Some(dependency), Some(overall)) => Some(dependency.min(overall)),
+            (dependency, overall) => dependency.or(overall),
+        }
+    }
+}
+
+/// Checks a dependency, giving up after `timeout`.
+pub async fn check_within(
+    checker: &(dyn DependencyHealthChecker + Send + Sync),
+    timeout: Option<Duration>,
+) -> DependencyStatus {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, checker.check())
+            .await
+            .unwrap_or_else(|_| DependencyStatus::timed_out(checker.dependency(), timeout)),
+        None => checker.check().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_take_precedence_over_dependency_timeout() {
+        let timeouts = Timeouts::default()
+            .dependency(Duration::from_secs(2))
+            .dependency_override("database", Duration::from_secs(1));
+
+        assert_eq!(
+            timeouts.for_dependency("database"),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            timeouts.for_dependency("snitch"),
+            Some(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn overall_timeout_caps_dependency_timeouts() {
+        let timeouts = Timeouts::default()
+            .overall(Duration::from_secs(3))
+            .dependency_override("database", Duration::from_secs(10));
+
+        assert_eq!(
+            timeouts.for_dependency("database"),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            timeouts.for_dependency("snitch"),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(Timeouts::default().for_dependency("snitch"), None);
+    }
+}
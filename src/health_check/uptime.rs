@@ -0,0 +1,369 @@
+use super::{service_status::ServiceStatus, HealthChecker};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use log::warn;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// How often the status of the service is sampled, and how long samples are kept.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct UptimeConfig {
+    // samples are only taken, and the table keeping them created, once enabled
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default = "default_sample_interval", with = "humantime_serde")]
+    sample_interval: Duration,
+    // older samples are deleted; shorter than 30 days truncates the widest window
+    #[serde(default = "default_retention", with = "humantime_serde")]
+    retention: Duration,
+    // older samples are merged into hourly ones
+    #[serde(default = "default_downsample_after", with = "humantime_serde")]
+    downsample_after: Duration,
+    // how often expired samples are deleted and old ones downsampled
+    #[serde(default = "default_compact_interval", with = "humantime_serde")]
+    compact_interval: Duration,
+}
+impl UptimeConfig {
+    pub fn with_sample_interval(self, sample_interval: Duration) -> Self {
+        UptimeConfig {
+            sample_interval,
+            ..self
+        }
+    }
+}
+impl Default for UptimeConfig {
+    fn default() -> Self {
+        UptimeConfig {
+            enabled: false,
+            sample_interval: default_sample_interval(),
+            retention: default_retention(),
+            downsample_after: default_downsample_after(),
+            compact_interval: default_compact_interval(),
+        }
+    }
+}
+
+fn default_sample_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_retention() -> Duration {
+    Duration::from_secs(30 * 24 * 3600)
+}
+
+fn default_downsample_after() -> Duration {
+    Duration::from_secs(24 * 3600)
+}
+
+fn default_compact_interval() -> Duration {
+    Duration::from_secs(3600)
+}
+
+/// The time windows availability is reported over, up to now.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Window {
+    #[display("1h")]
+    Hour,
+    #[display("24h")]
+    Day,
+    #[display("7d")]
+    Week,
+    #[display("30d")]
+    Month,
+}
+impl Window {
+    pub const ALL: [Window; 4] = [Window::Hour, Window::Day, Window::Week, Window::Month];
+
+    pub fn duration(&self) -> Duration {
+        let hours = match self {
+            Window::Hour => 1,
+            Window::Day => 24,
+            Window::Week => 7 * 24,
+            Window::Month => 30 * 24,
+        };
+        Duration::from_secs(hours * 3600)
+    }
+}
+
+/// How many of the samples taken over a window were Ok.
+#[derive(Clone, Debug, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct Availability {
+    // none for the overall status of the service
+    dependency: Option<String>,
+    ok: u64,
+    total: u64,
+}
+impl Availability {
+    pub fn new(dependency: Option<String>, ok: u64, total: u64) -> Self {
+        Availability {
+            dependency,
+            ok,
+            total,
+        }
+    }
+
+    /// Between 0 and 1, none without samples.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.total > 0).then(|| self.ok as f64 / self.total as f64)
+    }
+}
+
+/// Availability by window, for the service and each of its dependencies.
+pub type UptimeReport = BTreeMap<Window, Vec<Availability>>;
+
+/// Where samples of the status of the service are kept, e.g. `PostgresStore`.
+#[async_trait]
+pub trait UptimeStore {
+    /// Records one sample for the overall status and one for each dependency.
+    async fn record(
+        &self,
+        at: DateTime<Utc>,
+        service_status: &ServiceStatus,
+    ) -> Result<(), UptimeError>;
+
+    /// Sums up the samples taken since `since`, by dependency.
+    async fn availability(&self, since: DateTime<Utc>) -> Result<Vec<Availability>, UptimeError>;
+
+    /// Deletes samples older than the retention period and merges the ones older than `downsample_after`.
+    async fn compact(&self, now: DateTime<Utc>, config: &UptimeConfig) -> Result<(), UptimeError>;
+}
+
+/// Reports availability over every window.
+pub async fn report(
+    store: &(dyn UptimeStore + Send + Sync),
+    now: DateTime<Utc>,
+) -> Result<UptimeReport, UptimeError> {
+    let mut report = UptimeReport::new();
+    for window in Window::ALL {
+        let since = now - chrono::Duration::from_std(window.duration()).unwrap_or_default();
+        report.insert(window, store.availability(since).await?);
+    }
+    Ok(report)
+}
+
+/// Samples the status of the service on an interval, compacting older samples along the way,
+/// until the health checker is dropped.
+///
+/// Sampling at a fixed interval makes availability the share of time the service was Ok.
+/// Holds on to the health checker weakly, so sampling it doesn't keep it alive.
+pub fn start_sampling<H>(
+    store: Arc<dyn UptimeStore + Send + Sync>,
+    health_checker: Weak<H>,
+    config: UptimeConfig,
+) -> JoinHandle<()>
+where
+    H: HealthChecker + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(config.sample_interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut compacted_at: Option<DateTime<Utc>> = None;
+        loop {
+            ticks.tick().await;
+            let Some(health_checker) = health_checker.upgrade() else {
+                break;
+            };
+            let now = Utc::now();
+            // dependencies not checked yet would count as unavailable
+            if !health_checker.started() {
+                continue;
+            }
+            match health_checker.check().await {
+                Ok(service_status) => {
+                    if let Err(e) = store.record(now, &service_status).await {
+                        warn!("{e}");
+                    }
+                }
+                Err(e) => warn!("Failed to sample the status of the service: {e}"),
+            }
+
+            let compaction_due = compacted_at.is_none_or(|at| {
+                (now - at).to_std().unwrap_or_default() >= config.compact_interval
+            });
+            if compaction_due {
+                match store.compact(now, &config).await {
+                    Ok(()) => compacted_at = Some(now),
+                    Err(e) => warn!("{e}"),
+                }
+            }
+        }
+    })
+}
+
+#[derive(Debug, Display, Error)]
+pub enum UptimeError {
+    #[display("Uptime isn't recorded by this instance")]
+    NotRecorded,
+
+    #[display("Failed to access uptime samples: {_0}")]
+    Store(#[error(not(source))] String),
+}
+impl UptimeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            UptimeError::NotRecorded => "uptime_not_recorded",
+            UptimeError::Store(_) => "uptime_unavailable",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_kit::InMemoryUptimeStore;
+    use super::*;
+    use crate::health_check::service_status::test_kit::database;
+    use crate::health_check::service_status::{DependencyStatus, Status};
+    use crate::health_check::test_kit::StubHealthChecker;
+    use crate::health_check::version::test_kit::StubVersion;
+    use crate::health_check::version::{Build, Commit, Environment};
+
+    #[test]
+    fn availability_is_the_share_of_ok_samples() {
+        assert_eq!(Availability::new(None, 3, 4).ratio(), Some(0.75));
+        assert_eq!(Availability::new(None, 0, 0).ratio(), None);
+    }
+
+    #[tokio::test]
+    async fn reports_availability_over_every_window() {
+        let store = InMemoryUptimeStore::default();
+        let now = Utc::now();
+        store
+            .record(
+                now - chrono::Duration::days(2),
+                &status_of(Status::Degraded),
+            )
+            .await
+            .unwrap();
+        store.record(now, &status_of(Status::Ok)).await.unwrap();
+
+        let report = report(&store, now).await.unwrap();
+
+        assert_eq!(
+            report.keys().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["1h", "24h", "7d", "30d"]
+        );
+        let overall = |window| {
+            report[&window]
+                .iter()
+                .find(|a| a.dependency().is_none())
+                .and_then(Availability::ratio)
+        };
+        assert_eq!(overall(Window::Day), Some(1.0));
+        assert_eq!(overall(Window::Week), Some(0.5));
+    }
+
+    #[tokio::test]
+    async fn samples_until_health_checker_is_dropped() {
+        let store = Arc::new(InMemoryUptimeStore::default());
+        let health_checker = Arc::new(StubHealthChecker::new(Ok(status_of(Status::Ok))));
+        let sampling = start_sampling(
+            store.clone(),
+            Arc::downgrade(&health_checker),
+            UptimeConfig::default().with_sample_interval(Duration::from_millis(10)),
+        );
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(health_checker);
+        tokio::time::timeout(Duration::from_secs(1), sampling)
+            .await
+            .expect("sampling should stop once the health checker is dropped")
+            .unwrap();
+
+        assert!(store.samples() >= 2);
+        assert!(store.compactions() >= 1);
+    }
+
+    fn status_of(database_status: Status) -> ServiceStatus {
+        let version = StubVersion::new(
+            Environment::new("dev".to_string()),
+            Build::new("feat.branch.108".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        );
+        ServiceStatus::new(
+            version.into(),
+            vec![DependencyStatus::new(database(), database_status)],
+        )
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_kit {
+    use super::*;
+    use crate::health_check::service_status::Status;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // when, which dependency (none for overall), whether Ok
+    type Sample = (DateTime<Utc>, Option<String>, bool);
+
+    /// Keeps samples in memory, never downsampling them.
+    #[derive(Default)]
+    pub struct InMemoryUptimeStore {
+        samples: Mutex<Vec<Sample>>,
+        compactions: AtomicUsize,
+    }
+    impl InMemoryUptimeStore {
+        pub fn samples(&self) -> usize {
+            self.samples.lock().unwrap().len()
+        }
+
+        pub fn compactions(&self) -> usize {
+            self.compactions.load(Ordering::SeqCst)
+        }
+    }
+    #[async_trait]
+    impl UptimeStore for InMemoryUptimeStore {
+        async fn record(
+            &self,
+            at: DateTime<Utc>,
+            service_status: &ServiceStatus,
+        ) -> Result<(), UptimeError> {
+            let mut samples = self.samples.lock().unwrap();
+            samples.push((at, None, *service_status.status() == Status::Ok));
+            for dependency in service_status.dependencies() {
+                samples.push((
+                    at,
+                    Some(dependency.dependency().name().to_string()),
+                    *dependency.status() == Status::Ok,
+                ));
+            }
+            Ok(())
+        }
+
+        async fn availability(
+            &self,
+            since: DateTime<Utc>,
+        ) -> Result<Vec<Availability>, UptimeError> {
+            let mut by_dependency: BTreeMap<Option<String>, (u64, u64)> = BTreeMap::new();
+            for (at, dependency, ok) in self.samples.lock().unwrap().iter() {
+                if *at >= since {
+                    let (oks, total) = by_dependency.entry(dependency.clone()).or_default();
+                    *oks += u64::from(*ok);
+                    *total += 1;
+                }
+            }
+            Ok(by_dependency
+                .into_iter()
+                .map(|(dependency, (ok, total))| Availability::new(dependency, ok, total))
+                .collect())
+        }
+
+        async fn compact(
+            &self,
+            _now: DateTime<Utc>,
+            _config: &UptimeConfig,
+        ) -> Result<(), UptimeError> {
+            self.compactions.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+}
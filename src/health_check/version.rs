@@ -2,15 +2,16 @@ use async_trait::async_trait;
 use derive_more::Constructor;
 use derive_more::Display;
 use derive_more::Error;
-use getset::Getters;
 use std::fs;
+use std::io;
+use std::sync::Arc;
 
 #[async_trait]
 pub trait Versioned {
     async fn version(&self) -> Result<Version, VersionLoadError>;
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Constructor, Debug, Eq, Hash, PartialEq)]
 pub struct Version {
     env: Environment,
     build: Build,
@@ -43,15 +44,16 @@ pub struct VersionFromFile {
 #[async_trait]
 impl Versioned for VersionFromFile {
     async fn version(&self) -> Result<Version, VersionLoadError> {
-        let content = fs::read_to_string(&self.path).map_err(|e| VersionLoadError {
-            message: e.to_string(),
+        let content = fs::read_to_string(&self.path).map_err(|e| VersionLoadError::Unreadable {
+            path: self.path.clone(),
+            source: Arc::new(e),
         })?;
         let mut lines = content.lines();
-        let build = lines.next().ok_or(VersionLoadError {
-            message: "No build number specified in 'rustic.version'".to_string(),
+        let build = lines.next().ok_or(VersionLoadError::MissingBuild {
+            path: self.path.clone(),
         })?;
-        let commit = lines.next().ok_or(VersionLoadError {
-            message: "No commit hash specified in 'rustic.version'".to_string(),
+        let commit = lines.next().ok_or(VersionLoadError::MissingCommit {
+            path: self.path.clone(),
         })?;
         let version = Version {
             env: self.env.to_owned(),
@@ -62,23 +64,47 @@ impl Versioned for VersionFromFile {
     }
 }
 
-// TODO use anyhow or thiserror to deal with errors?
-#[derive(Debug, Display, Error, Getters)]
-pub struct VersionLoadError {
-    #[getset(get = "pub")]
-    message: String,
+// Sources are wrapped in `Arc` so errors can be cloned, e.g. when a result is shared by several callers
+#[derive(Clone, Debug, Display, Error)]
+pub enum VersionLoadError {
+    #[display("Failed to read version file `{path}`: {source}")]
+    Unreadable {
+        path: String,
+        source: Arc<io::Error>,
+    },
+
+    #[display("No build number specified in `{path}`")]
+    MissingBuild {
+        #[error(not(source))]
+        path: String,
+    },
+
+    #[display("No commit hash specified in `{path}`")]
+    MissingCommit {
+        #[error(not(source))]
+        path: String,
+    },
+}
+impl VersionLoadError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            VersionLoadError::Unreadable { .. } => "version_file_unreadable",
+            VersionLoadError::MissingBuild { .. } => "version_build_missing",
+            VersionLoadError::MissingCommit { .. } => "version_commit_missing",
+        }
+    }
 }
 
-#[derive(Clone, Constructor, Debug, Display, PartialEq)]
+#[derive(Clone, Constructor, Debug, Display, Eq, Hash, PartialEq)]
 // The newtype pattern.
 // Use owned String instead of slice &str: each instance of this struct own its own data,
 // always valid for as long the entire struct is valid.
 pub struct Environment(String);
 
-#[derive(Clone, Constructor, Debug, Display, PartialEq)]
+#[derive(Clone, Constructor, Debug, Display, Eq, Hash, PartialEq)]
 pub struct Build(String);
 
-#[derive(Clone, Constructor, Debug, Display, PartialEq)]
+#[derive(Clone, Constructor, Debug, Display, Eq, Hash, PartialEq)]
 pub struct Commit(String);
 
 #[cfg(test)] // conditional compilation attr: item included only during tests.
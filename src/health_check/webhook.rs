@@ -0,0 +1,651 @@
+use super::{
+    history::{StatusChange, Transition},
+    service_status::{ServiceStatus, Status},
+};
+use crate::routes::health_status::model::StatusEventPayload;
+use chrono::Utc;
+use derive_more::Display;
+use derive_more::Error;
+use getset::Getters;
+use hmac::{Hmac, Mac};
+use log::warn;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, StatusCode, Url};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+pub const EVENT_ID_HEADER: &str = "x-rustic-event-id";
+pub const TIMESTAMP_HEADER: &str = "x-rustic-timestamp";
+// `sha256=` followed by the hex encoded HMAC-SHA256 of `{timestamp}.{body}`, keyed with the shared secret
+pub const SIGNATURE_HEADER: &str = "x-rustic-signature";
+
+// how many transitions of undelivered events are kept for the next event
+const MAX_REQUEUED_TRANSITIONS: usize = 1000;
+
+/// Where to send status change events and how hard to try.
+#[derive(Clone, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct WebhookConfig {
+    url: String,
+    // shared with the receiver, to verify events come from us
+    #[getset(skip)]
+    secret: String,
+    // including the first one
+    #[serde(default = "default_max_attempts")]
+    max_attempts: u32,
+    // before the first retry, doubling after each one
+    #[serde(default = "default_backoff", with = "humantime_serde")]
+    backoff: Duration,
+    // at most one event per interval; changes in between are coalesced into the next event
+    #[serde(default = "default_min_interval", with = "humantime_serde")]
+    min_interval: Duration,
+    // of each delivery attempt, so a hanging receiver doesn't hold back the next events
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    timeout: Duration,
+}
+impl WebhookConfig {
+    pub fn new(url: &str, secret: &str) -> Self {
+        WebhookConfig {
+            url: url.to_string(),
+            secret: secret.to_string(),
+            max_attempts: default_max_attempts(),
+            backoff: default_backoff(),
+            min_interval: default_min_interval(),
+            timeout: default_timeout(),
+        }
+    }
+
+    pub fn with_retries(self, max_attempts: u32, backoff: Duration) -> Self {
+        WebhookConfig {
+            max_attempts,
+            backoff,
+            ..self
+        }
+    }
+
+    pub fn with_min_interval(self, min_interval: Duration) -> Self {
+        WebhookConfig {
+            min_interval,
+            ..self
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        WebhookConfig { timeout, ..self }
+    }
+}
+
+// Not derived, so the secret doesn't end up in logs
+impl std::fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookConfig")
+            .field("url", &self.url)
+            .field("secret", &"***")
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff", &self.backoff)
+            .field("min_interval", &self.min_interval)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_backoff() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_min_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+// The overall status and the status of each dependency, to tell whether anything changed since the last event
+type Fingerprint = (Status, Vec<(String, Status)>);
+
+fn fingerprint(service_status: &ServiceStatus) -> Fingerprint {
+    (
+        service_status.status().clone(),
+        service_status
+            .dependencies()
+            .iter()
+            .map(|d| (d.dependency().name().to_string(), d.status().clone()))
+            .collect(),
+    )
+}
+
+// The latest status and the transitions leading to it since the last event
+type Event = (ServiceStatus, Vec<Transition>);
+
+struct Delivery {
+    id: String,
+    event: Event,
+    outcome: Result<(), String>,
+}
+
+// The transitions of an event that couldn't be delivered come before the ones received since,
+// keeping only the most recent ones so a receiver that is down for long doesn't grow them without bounds
+fn requeue(failed: Event, received: Option<Event>) -> Event {
+    let (service_status, mut transitions) = failed;
+    let service_status = match received {
+        Some((latest, received)) => {
+            transitions.extend(received);
+            latest
+        }
+        None => service_status,
+    };
+    if transitions.len() > MAX_REQUEUED_TRANSITIONS {
+        let dropped = transitions.len() - MAX_REQUEUED_TRANSITIONS;
+        warn!("Dropping the {dropped} oldest transition(s) of undelivered events");
+        transitions.drain(..dropped);
+    }
+    (service_status, transitions)
+}
+
+/// POSTs a signed JSON event to a webhook each time the status of the service or of its dependencies changes.
+///
+/// Deliveries are retried with exponential backoff on network errors, 5xx and 429 responses,
+/// and events that still aren't delivered are sent again after `min_interval`, along with the changes since.
+/// Each target receives at most one event per `min_interval`: changes in between are coalesced,
+/// and dropped altogether when they lead back to the state of the last event, e.g. a dependency flapping.
+/// The first status observed is the baseline, so receivers aren't notified on every deployment.
+pub struct WebhookNotifier {
+    client: Client,
+    url: Url,
+    secret: Vec<u8>,
+    max_attempts: u32,
+    backoff: Duration,
+    min_interval: Duration,
+}
+impl WebhookNotifier {
+    pub fn new(config: &WebhookConfig) -> Result<Self, WebhookError> {
+        let url = Url::parse(&config.url).map_err(|source| WebhookError::InvalidUrl {
+            url: config.url.clone(),
+            source,
+        })?;
+        if config.secret.is_empty() {
+            return Err(WebhookError::MissingSecret {
+                url: config.url.clone(),
+            });
+        }
+        if config.max_attempts == 0 {
+            return Err(WebhookError::InvalidMaxAttempts);
+        }
+        if config.timeout.is_zero() {
+            return Err(WebhookError::InvalidTimeout);
+        }
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(WebhookError::Client)?;
+
+        Ok(WebhookNotifier {
+            client,
+            url,
+            secret: config.secret.as_bytes().to_vec(),
+            max_attempts: config.max_attempts,
+            backoff: config.backoff,
+            min_interval: config.min_interval,
+        })
+    }
+
+    /// Notifies the webhook of the changes received, until their sender is dropped.
+    ///
+    /// Events are delivered in a task of their own, so changes keep being received while retrying.
+    pub fn start(self, mut changes: broadcast::Receiver<StatusChange>) -> JoinHandle<()> {
+        let notifier = Arc::new(self);
+        tokio::spawn(async move {
+            // what the webhook was last told about
+            let mut notified: Option<Fingerprint> = None;
+            let mut pending: Option<Event> = None;
+            let mut delivering: Option<JoinHandle<Delivery>> = None;
+            let mut next_delivery = Instant::now();
+            loop {
+                tokio::select! {
+                    change = changes.recv() => match change {
+                        Ok(change) if notified.is_none() => {
+                            notified = Some(fingerprint(change.service_status()));
+                        }
+                        Ok(change) => {
+                            let mut transitions = pending.take().map(|(_, t)| t).unwrap_or_default();
+                            transitions.extend(change.transitions().iter().cloned());
+                            pending = Some((change.service_status().clone(), transitions));
+                        }
+                        // the next change carries the latest status anyway
+                        Err(RecvError::Lagged(missed)) => {
+                            warn!("Missed {missed} status change(s) to notify {}", notifier.url)
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    delivery = async { delivering.as_mut().expect("Delivering").await }, if delivering.is_some() => {
+                        delivering = None;
+                        match delivery {
+                            Ok(Delivery { event: (service_status, _), outcome: Ok(()), .. }) => {
+                                notified = Some(fingerprint(&service_status));
+                            }
+                            Ok(Delivery { id, event, outcome: Err(reason) }) => {
+                                warn!(
+                                    "Gave up notifying {} of event {id}, sending it again with the next event: {reason}",
+                                    notifier.url
+                                );
+                                pending = Some(requeue(event, pending.take()));
+                            }
+                            Err(e) => warn!("Failed to notify {}: {e}", notifier.url),
+                        }
+                        next_delivery = Instant::now() + notifier.min_interval;
+                    }
+                    _ = tokio::time::sleep_until(next_delivery), if pending.is_some() && delivering.is_none() => {
+                        let Some((service_status, transitions)) = pending.take() else {
+                            continue;
+                        };
+                        if notified.as_ref() == Some(&fingerprint(&service_status)) {
+                            continue;
+                        }
+                        let notifier = notifier.clone();
+                        delivering = Some(tokio::spawn(async move {
+                            let id = Uuid::new_v4().to_string();
+                            let outcome = notifier
+                                .deliver(&id, &service_status, transitions.clone())
+                                .await;
+                            Delivery {
+                                id,
+                                event: (service_status, transitions),
+                                outcome,
+                            }
+                        }));
+                    }
+                }
+            }
+        })
+    }
+
+    async fn deliver(
+        &self,
+        id: &str,
+        service_status: &ServiceStatus,
+        transitions: Vec<Transition>,
+    ) -> Result<(), String> {
+        let occurred_at = transitions.last().map(|t| *t.at()).unwrap_or_else(Utc::now);
+        let event =
+            StatusEventPayload::new(id.to_string(), occurred_at, service_status, transitions);
+        let body = serde_json::to_vec(&event).map_err(|e| e.to_string())?;
+
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match self.post(id, &body).await {
+                Ok(()) => return Ok(()),
+                Err((reason, true)) if attempt < self.max_attempts => {
+                    warn!(
+                        "Failed to notify {} ({reason}), retrying in {backoff:?}",
+                        self.url
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err((reason, _)) => return Err(format!("{reason} after {attempt} attempt(s)")),
+            }
+        }
+    }
+
+    // Fails with the reason and whether it's worth retrying
+    async fn post(&self, id: &str, body: &[u8]) -> Result<(), (String, bool)> {
+        let timestamp = Utc::now().timestamp();
+        let response = self
+            .client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_ID_HEADER, id)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(&self.secret, timestamp, body))
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| (e.without_url().to_string(), true))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            let retryable = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+            Err((format!("Unexpected status {status}"), retryable))
+        }
+    }
+}
+
+/// The value of the signature header for an event sent at `timestamp`, in seconds since the epoch.
+pub fn sign(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[derive(Debug, Display, Error)]
+pub enum WebhookError {
+    #[display("Invalid webhook url `{url}`: {source}")]
+    InvalidUrl {
+        #[error(not(source))]
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[display("Missing secret for webhook `{url}`")]
+    MissingSecret {
+        #[error(not(source))]
+        url: String,
+    },
+
+    #[display("Invalid max attempts: must be at least 1")]
+    InvalidMaxAttempts,
+
+    #[display("Invalid timeout: must be greater than zero")]
+    InvalidTimeout,
+
+    #[display("Failed to build the http client: {_0}")]
+    Client(#[error(source)] reqwest::Error),
+}
+impl WebhookError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            WebhookError::InvalidUrl { .. } => "webhook_invalid_url",
+            WebhookError::MissingSecret { .. } => "webhook_missing_secret",
+            WebhookError::InvalidMaxAttempts => "webhook_invalid_max_attempts",
+            WebhookError::InvalidTimeout => "webhook_invalid_timeout",
+            WebhookError::Client(_) => "webhook_client_failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::history::StatusHistory;
+    use crate::health_check::service_status::test_kit::database;
+    use crate::health_check::service_status::DependencyStatus;
+    use crate::health_check::version::test_kit::StubVersion;
+    use crate::health_check::version::{Build, Commit, Environment};
+    use claims::assert_matches;
+    use serde_json::Value;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use warp::http::HeaderMap;
+    use warp::hyper::body::Bytes;
+    use warp::Filter;
+
+    const SECRET: &str = "whsec_c0ffee";
+
+    #[tokio::test]
+    async fn posts_signed_event_on_change() {
+        let receiver = Receiver::start(0);
+        let history = StatusHistory::new(10);
+        notifier(WebhookConfig::new(&receiver.url(), SECRET)).start(history.subscribe());
+
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let events = receiver.events();
+        assert_eq!(events.len(), 1);
+        let (headers, body) = &events[0];
+        let timestamp: i64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+        assert_eq!(
+            headers[SIGNATURE_HEADER].to_str().unwrap(),
+            sign(SECRET.as_bytes(), timestamp, body)
+        );
+        let event: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(event["id"], headers[EVENT_ID_HEADER].to_str().unwrap());
+        assert_eq!(event["status"], "Degraded");
+        assert_eq!(event["transitions"][0]["dependency"], "database");
+        assert_eq!(event["transitions"][0]["from"], "Ok");
+        assert_eq!(event["transitions"][0]["to"], "Degraded");
+    }
+
+    #[tokio::test]
+    async fn retries_with_backoff_until_delivered() {
+        let receiver = Receiver::start(2);
+        let history = StatusHistory::new(10);
+        notifier(
+            WebhookConfig::new(&receiver.url(), SECRET).with_retries(3, Duration::from_millis(10)),
+        )
+        .start(history.subscribe());
+
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let events = receiver.events();
+        assert_eq!(receiver.requests(), 3);
+        assert_eq!(events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let receiver = Receiver::start(usize::MAX);
+        let history = StatusHistory::new(10);
+        notifier(
+            WebhookConfig::new(&receiver.url(), SECRET).with_retries(2, Duration::from_millis(10)),
+        )
+        .start(history.subscribe());
+
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(receiver.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn sends_undelivered_event_again() {
+        let receiver = Receiver::start(2);
+        let history = StatusHistory::new(10);
+        notifier(
+            WebhookConfig::new(&receiver.url(), SECRET)
+                .with_retries(2, Duration::from_millis(10))
+                .with_min_interval(Duration::from_millis(100)),
+        )
+        .start(history.subscribe());
+
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let events = receiver.events();
+        assert_eq!(receiver.requests(), 3);
+        assert_eq!(events.len(), 1);
+        let event: Value = serde_json::from_slice(&events[0].1).unwrap();
+        assert_eq!(event["status"], "Degraded");
+        assert_eq!(event["transitions"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_receiving_changes_while_retrying() {
+        let receiver = Receiver::start(2);
+        let history = StatusHistory::new(100);
+        notifier(
+            WebhookConfig::new(&receiver.url(), SECRET)
+                .with_retries(3, Duration::from_millis(100))
+                .with_min_interval(Duration::from_millis(50)),
+        )
+        .start(history.subscribe());
+
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // more changes than a subscriber may fall behind, while the first event is being retried
+        for status in [Status::Ok, Status::Degraded].iter().cycle().take(21) {
+            history.record(&status_of(status.clone()));
+            tokio::task::yield_now().await;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let events = receiver.events();
+        assert_eq!(events.len(), 2);
+        let event: Value = serde_json::from_slice(&events[1].1).unwrap();
+        assert_eq!(event["status"], "Ok");
+        assert_eq!(event["transitions"].as_array().unwrap().len(), 42);
+    }
+
+    #[test]
+    fn debug_redacts_secret() {
+        let config = WebhookConfig::new("http://hooks/status", SECRET);
+
+        let debug = format!("{config:?}");
+
+        assert!(!debug.contains(SECRET));
+        assert!(debug.contains("http://hooks/status"));
+    }
+
+    #[tokio::test]
+    async fn drops_changes_leading_back_to_the_last_event() {
+        let receiver = Receiver::start(0);
+        let history = StatusHistory::new(10);
+        notifier(
+            WebhookConfig::new(&receiver.url(), SECRET)
+                .with_min_interval(Duration::from_millis(200)),
+        )
+        .start(history.subscribe());
+
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // flapping within the interval, ending up as last notified
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert_eq!(receiver.events().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn coalesces_changes_within_min_interval() {
+        let receiver = Receiver::start(0);
+        let history = StatusHistory::new(10);
+        notifier(
+            WebhookConfig::new(&receiver.url(), SECRET)
+                .with_min_interval(Duration::from_millis(200)),
+        )
+        .start(history.subscribe());
+
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        history.record(&status_of(Status::Ok));
+        history.record(&status_of(Status::Degraded));
+        history.record(&status_of(Status::Ok));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(receiver.events().len(), 1);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let events = receiver.events();
+        assert_eq!(events.len(), 2);
+        let event: Value = serde_json::from_slice(&events[1].1).unwrap();
+        assert_eq!(event["status"], "Ok");
+        assert_eq!(event["transitions"].as_array().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let invalid_url = WebhookNotifier::new(&WebhookConfig::new("hooks", SECRET));
+        let missing_secret = WebhookNotifier::new(&WebhookConfig::new("http://hooks/status", ""));
+        let invalid_attempts = WebhookNotifier::new(
+            &WebhookConfig::new("http://hooks/status", SECRET).with_retries(0, Duration::ZERO),
+        );
+        let zero_timeout = WebhookNotifier::new(
+            &WebhookConfig::new("http://hooks/status", SECRET).with_timeout(Duration::ZERO),
+        );
+
+        assert_matches!(invalid_url.err(), Some(WebhookError::InvalidUrl { .. }));
+        assert_matches!(
+            missing_secret.err(),
+            Some(WebhookError::MissingSecret { .. })
+        );
+        assert_matches!(
+            invalid_attempts.err(),
+            Some(WebhookError::InvalidMaxAttempts)
+        );
+        assert_matches!(zero_timeout.err(), Some(WebhookError::InvalidTimeout));
+    }
+
+    #[test]
+    fn times_deliveries_out_by_default() {
+        let config = WebhookConfig::new("http://hooks/status", SECRET);
+
+        assert_eq!(*config.timeout(), Duration::from_secs(5));
+    }
+
+    fn notifier(config: WebhookConfig) -> WebhookNotifier {
+        WebhookNotifier::new(&config.with_timeout(Duration::from_secs(1))).unwrap()
+    }
+
+    fn status_of(database_status: Status) -> ServiceStatus {
+        let version = StubVersion::new(
+            Environment::new("dev".to_string()),
+            Build::new("feat.branch.108".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        );
+        ServiceStatus::new(
+            version.into(),
+            vec![DependencyStatus::new(database(), database_status)],
+        )
+    }
+
+    // A local webhook receiver, failing the given number of requests before accepting events
+    #[derive(Clone)]
+    struct Receiver {
+        address: SocketAddr,
+        events: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+        requests: Arc<AtomicUsize>,
+    }
+    impl Receiver {
+        fn start(failures: usize) -> Self {
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let requests = Arc::new(AtomicUsize::new(0));
+            let (received, counted) = (events.clone(), requests.clone());
+            let hook = warp::post()
+                .and(warp::path("hooks"))
+                .and(warp::header::headers_cloned())
+                .and(warp::body::bytes())
+                .map(move |headers: HeaderMap, body: Bytes| {
+                    if counted.fetch_add(1, Ordering::SeqCst) < failures {
+                        return warp::http::StatusCode::SERVICE_UNAVAILABLE;
+                    }
+                    received.lock().unwrap().push((headers, body));
+                    warp::http::StatusCode::NO_CONTENT
+                });
+            let (address, server) = warp::serve(hook).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+            Receiver {
+                address,
+                events,
+                requests,
+            }
+        }
+
+        fn url(&self) -> String {
+            format!("http://{}/hooks", self.address)
+        }
+
+        fn events(&self) -> Vec<(HeaderMap, Bytes)> {
+            self.events.lock().unwrap().clone()
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+}
@@ -1,5 +1,21 @@
-#[tokio::main]
+use clap::Parser;
+use rustic_sketch::app::App;
+use rustic_sketch::config::{CliArgs, Config};
+use std::process;
 
+#[tokio::main]
 async fn main() {
-    rustic_sketch::run().await
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let config = Config::load(&CliArgs::parse()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1)
+    });
+    let app = App::from_config(config).await.unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1)
+    });
+    let server = app.build().start().expect("Failed to start server");
+    server.listen_for_signals();
+    server.stopped().await;
 }
@@ -0,0 +1,228 @@
+use crate::health_check::maintenance::{Maintenance, MaintenanceError, MaintenanceStore};
+use crate::routes::health_status::model::MaintenancePayload;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use derive_more::Error;
+use serde::Deserialize;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::reject::{self, Rejection};
+use warp::reply::Reply;
+use warp::Filter;
+
+/// Lets operators take the service out of rotation, e.g. `PUT /admin/maintenance`.
+///
+/// Requests must carry the configured token as `Authorization: Bearer <token>`.
+/// Without a token, the admin routes aren't served at all.
+pub fn routes(
+    token: Option<String>,
+    maintenance_store: Arc<dyn MaintenanceStore + Send + Sync>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    // before matching methods, so every route rejects the same way without a valid token
+    let maintenance = warp::path!("admin" / "maintenance").and(authorized(token));
+    current_maintenance(maintenance.clone(), maintenance_store.clone())
+        .or(start_maintenance(
+            maintenance.clone(),
+            maintenance_store.clone(),
+        ))
+        .or(end_maintenance(maintenance, maintenance_store))
+}
+
+fn authorized(token: Option<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let token = token.clone();
+            async move {
+                let Some(token) = token else {
+                    return Err(reject::not_found());
+                };
+                let bearer = authorization
+                    .as_deref()
+                    .and_then(|value| value.strip_prefix("Bearer "));
+                match bearer {
+                    Some(bearer) if same(bearer.as_bytes(), token.as_bytes()) => Ok(()),
+                    _ => Err(reject::custom(AdminError::Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Takes as long whatever the first difference, so tokens can't be guessed byte by byte
+fn same(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len() && left.iter().zip(right).fold(0, |acc, (l, r)| acc | (l ^ r)) == 0
+}
+
+/// The maintenance in progress, if any.
+fn current_maintenance(
+    maintenance: impl Filter<Extract = (), Error = Rejection> + Clone,
+    maintenance_store: Arc<dyn MaintenanceStore + Send + Sync>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    maintenance.and(warp::get()).and_then(move || {
+        let maintenance_store = maintenance_store.clone();
+        async move {
+            match maintenance_store.current().await {
+                Ok(Some(maintenance)) if maintenance.is_active(Utc::now()) => {
+                    Ok(warp::reply::json(&MaintenancePayload::from(maintenance)).into_response())
+                }
+                Ok(_) => Ok(StatusCode::NO_CONTENT.into_response()),
+                Err(e) => Err(reject::custom(e)),
+            }
+        }
+    })
+}
+
+/// Starts maintenance now, replacing the one in progress if any.
+fn start_maintenance(
+    maintenance: impl Filter<Extract = (), Error = Rejection> + Clone,
+    maintenance_store: Arc<dyn MaintenanceStore + Send + Sync>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    maintenance
+        .and(warp::put())
+        .and(warp::body::json())
+        .and_then(move |request: MaintenanceRequest| {
+            let maintenance_store = maintenance_store.clone();
+            async move {
+                let maintenance = Maintenance::new(&request.reason, Utc::now(), request.until)
+                    .map_err(reject::custom)?;
+                match maintenance_store.start(&maintenance).await {
+                    Ok(()) => Ok(warp::reply::json(&MaintenancePayload::from(maintenance))),
+                    Err(e) => Err(reject::custom(e)),
+                }
+            }
+        })
+}
+
+/// Puts the service back into rotation, as far as its dependencies allow.
+fn end_maintenance(
+    maintenance: impl Filter<Extract = (), Error = Rejection> + Clone,
+    maintenance_store: Arc<dyn MaintenanceStore + Send + Sync>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    maintenance.and(warp::delete()).and_then(move || {
+        let maintenance_store = maintenance_store.clone();
+        async move {
+            match maintenance_store.end().await {
+                Ok(()) => Ok(StatusCode::NO_CONTENT),
+                Err(e) => Err(reject::custom(e)),
+            }
+        }
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceRequest {
+    reason: String,
+    until: DateTime<Utc>,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum AdminError {
+    #[display("Missing or invalid admin token")]
+    Unauthorized,
+}
+impl AdminError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AdminError::Unauthorized => "unauthorized",
+        }
+    }
+}
+
+// Turned into JSON error payloads by `routes::error::with_json_errors`
+impl warp::reject::Reject for AdminError {}
+impl warp::reject::Reject for MaintenanceError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::maintenance::LocalMaintenanceStore;
+    use crate::routes::error::{model::ErrorPayload, with_json_errors};
+    use chrono::Duration;
+    use serde_json::{json, Value};
+
+    const TOKEN: &str = "s3cr3t";
+
+    #[tokio::test]
+    async fn starts_and_ends_maintenance() {
+        let store = Arc::new(LocalMaintenanceStore::default());
+        let filter = routes(Some(TOKEN.to_string()), store.clone());
+        let until = Utc::now() + Duration::hours(1);
+
+        let started = warp::test::request()
+            .method("PUT")
+            .path("/admin/maintenance")
+            .header("authorization", format!("Bearer {TOKEN}"))
+            .json(&json!({ "reason": "Upgrading the database", "until": until }))
+            .reply(&filter)
+            .await;
+        let current = warp::test::request()
+            .path("/admin/maintenance")
+            .header("authorization", format!("Bearer {TOKEN}"))
+            .reply(&filter)
+            .await;
+        let ended = warp::test::request()
+            .method("DELETE")
+            .path("/admin/maintenance")
+            .header("authorization", format!("Bearer {TOKEN}"))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(started.status(), 200);
+        assert_eq!(current.status(), 200);
+        let current: Value = serde_json::from_slice(current.body()).unwrap();
+        assert_eq!(current["reason"], "Upgrading the database");
+        assert_eq!(ended.status(), 204);
+        assert_eq!(store.current().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_the_token() {
+        let filter = with_json_errors(routes(
+            Some(TOKEN.to_string()),
+            Arc::new(LocalMaintenanceStore::default()),
+        ));
+
+        let result = warp::test::request()
+            .method("DELETE")
+            .path("/admin/maintenance")
+            .header("authorization", "Bearer guess")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 401);
+        let obtained: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(obtained.code(), "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn rejects_maintenance_already_expired() {
+        let filter = with_json_errors(routes(
+            Some(TOKEN.to_string()),
+            Arc::new(LocalMaintenanceStore::default()),
+        ));
+
+        let result = warp::test::request()
+            .method("PUT")
+            .path("/admin/maintenance")
+            .header("authorization", format!("Bearer {TOKEN}"))
+            .json(&json!({ "reason": "Upgrading the database", "until": Utc::now() }))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 400);
+        let obtained: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(obtained.code(), "maintenance_invalid");
+    }
+
+    #[tokio::test]
+    async fn not_found_without_a_token_configured() {
+        let filter = with_json_errors(routes(None, Arc::new(LocalMaintenanceStore::default())));
+
+        let result = warp::test::request()
+            .path("/admin/maintenance")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 404);
+    }
+}
@@ -0,0 +1,254 @@
+use self::model::ErrorPayload;
+use crate::health_check::{maintenance::MaintenanceError, uptime::UptimeError, HealthCheckError};
+use crate::routes::admin::AdminError;
+use log::error;
+use uuid::Uuid;
+use warp::body::BodyDeserializeError;
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
+use warp::reject::{
+    InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader, PayloadTooLarge,
+    Rejection, UnsupportedMediaType,
+};
+use warp::reply::{Reply, Response};
+use warp::Filter;
+
+pub mod model;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Turns every rejection of `routes` into a JSON [`ErrorPayload`].
+///
+/// Every response carries a request id in the `x-request-id` header: the one sent by the client,
+/// or a new one otherwise.
+pub fn with_json_errors<F, R>(
+    routes: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let routes = routes
+        .map(|reply: R| Ok(reply.into_response()))
+        .or_else(|rejection| async move { Ok::<_, Rejection>((Err(rejection),)) });
+
+    request_id()
+        .and(routes)
+        .map(|request_id: String, result: Result<Response, Rejection>| {
+            let mut response = match result {
+                Ok(response) => response,
+                Err(rejection) => error_response(&rejection, &request_id),
+            };
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                response.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            response
+        })
+}
+
+fn request_id() -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| {
+        headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= 128)
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string())
+    })
+}
+
+fn error_response(rejection: &Rejection, request_id: &str) -> Response {
+    let (status, code, message) = if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "not_found", "Not found".to_string())
+    } else if let Some(err) = rejection.find::<HealthCheckError>() {
+        let status = match err {
+            HealthCheckError::Version(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            HealthCheckError::TimedOut(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, err.code(), err.to_string())
+    } else if let Some(err) = rejection.find::<UptimeError>() {
+        let status = match err {
+            UptimeError::NotRecorded => StatusCode::NOT_FOUND,
+            UptimeError::Store(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, err.code(), err.to_string())
+    } else if let Some(err) = rejection.find::<AdminError>() {
+        (StatusCode::UNAUTHORIZED, err.code(), err.to_string())
+    } else if let Some(err) = rejection.find::<MaintenanceError>() {
+        let status = match err {
+            MaintenanceError::Invalid(_) => StatusCode::BAD_REQUEST,
+            MaintenanceError::Store(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, err.code(), err.to_string())
+    } else if let Some(err) = rejection.find::<MethodNotAllowed>() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            err.to_string(),
+        )
+    } else if let Some(err) = rejection.find::<InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "invalid_query", err.to_string())
+    } else if let Some(err) = rejection.find::<BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, "invalid_body", err.to_string())
+    } else if let Some(err) = rejection.find::<UnsupportedMediaType>() {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            err.to_string(),
+        )
+    } else if let Some(err) = rejection.find::<PayloadTooLarge>() {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            err.to_string(),
+        )
+    } else if let Some(err) = rejection.find::<LengthRequired>() {
+        (
+            StatusCode::LENGTH_REQUIRED,
+            "length_required",
+            err.to_string(),
+        )
+    } else if let Some(err) = rejection.find::<MissingHeader>() {
+        (StatusCode::BAD_REQUEST, "missing_header", err.to_string())
+    } else if let Some(err) = rejection.find::<InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, "invalid_header", err.to_string())
+    } else {
+        // the rejection may tell about the internals of the service, so it's only logged
+        error!("Request {request_id} failed with an unhandled rejection: {rejection:?}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "Internal server error".to_string(),
+        )
+    };
+    if status.is_server_error() {
+        error!("Request {request_id} failed with `{code}`: {message}");
+    }
+
+    let payload = ErrorPayload::new(code.to_string(), message, request_id.to_string());
+    warp::reply::with_status(warp::reply::json(&payload), status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unknown_route_returns_json_not_found() {
+        let filter = with_json_errors(warp::path("ping").map(|| "pong"));
+
+        let result = warp::test::request()
+            .path("/unknown")
+            .header(REQUEST_ID_HEADER, "c0ffee")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 404);
+        let payload: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(
+            payload,
+            ErrorPayload::new(
+                "not_found".to_string(),
+                "Not found".to_string(),
+                "c0ffee".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_method_returns_json_method_not_allowed() {
+        let filter = with_json_errors(warp::get().and(warp::path("ping")).map(|| "pong"));
+
+        let result = warp::test::request()
+            .method("POST")
+            .path("/ping")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 405);
+        let payload: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(payload.code(), "method_not_allowed");
+    }
+
+    #[tokio::test]
+    async fn maps_rejections_of_warp_filters() {
+        struct TestCase {
+            filter: warp::filters::BoxedFilter<(&'static str,)>,
+            request: warp::test::RequestBuilder,
+            expected_status: u16,
+            expected_code: &'static str,
+        }
+        let test_cases = [
+            TestCase {
+                filter: warp::body::json::<serde_json::Value>()
+                    .map(|_| "ok")
+                    .boxed(),
+                request: warp::test::request()
+                    .method("POST")
+                    .header("content-type", "text/plain")
+                    .body("{}"),
+                expected_status: 415,
+                expected_code: "unsupported_media_type",
+            },
+            TestCase {
+                filter: warp::body::content_length_limit(4).map(|| "ok").boxed(),
+                request: warp::test::request().method("POST").body("too large"),
+                expected_status: 413,
+                expected_code: "payload_too_large",
+            },
+            TestCase {
+                filter: warp::body::content_length_limit(4).map(|| "ok").boxed(),
+                request: warp::test::request().method("POST"),
+                expected_status: 411,
+                expected_code: "length_required",
+            },
+            TestCase {
+                filter: warp::header::<String>("x-snitch").map(|_| "ok").boxed(),
+                request: warp::test::request(),
+                expected_status: 400,
+                expected_code: "missing_header",
+            },
+            TestCase {
+                filter: warp::header::<u32>("x-snitch").map(|_| "ok").boxed(),
+                request: warp::test::request().header("x-snitch", "many"),
+                expected_status: 400,
+                expected_code: "invalid_header",
+            },
+        ];
+
+        for case in test_cases {
+            let result = case.request.reply(&with_json_errors(case.filter)).await;
+
+            assert_eq!(result.status(), case.expected_status);
+            let payload: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+            assert_eq!(payload.code(), case.expected_code);
+        }
+    }
+
+    #[tokio::test]
+    async fn unhandled_rejection_does_not_leak_details() {
+        #[derive(Debug)]
+        struct Secret;
+        impl warp::reject::Reject for Secret {}
+        let filter = with_json_errors(
+            warp::any().and_then(|| async { Err::<&str, _>(warp::reject::custom(Secret)) }),
+        );
+
+        let result = warp::test::request().reply(&filter).await;
+
+        assert_eq!(result.status(), 500);
+        let payload: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(payload.code(), "internal_error");
+        assert_eq!(payload.message(), "Internal server error");
+    }
+
+    #[tokio::test]
+    async fn generates_request_id_when_client_does_not_send_one() {
+        let filter = with_json_errors(warp::path("ping").map(|| "pong"));
+
+        let result = warp::test::request().path("/ping").reply(&filter).await;
+
+        assert_eq!(result.status(), 200);
+        let request_id = result.headers().get(REQUEST_ID_HEADER).unwrap();
+        assert!(Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,24 @@
+use derive_more::Constructor;
+use serde::{Deserialize, Serialize};
+
+/// The body of every error response.
+#[derive(Constructor, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ErrorPayload {
+    // machine-readable, e.g. `version_unavailable`
+    code: String,
+    message: String,
+    request_id: String,
+}
+impl ErrorPayload {
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+}
@@ -1,6 +1,19 @@
-use self::model::ServiceStatusPayload;
-use crate::health_check::{HealthCheckError, HealthChecker};
+use self::model::{
+    ContractVersion, ServiceStatusPayload, StatusHistoryPayload, UptimePayload,
+    VerboseServiceStatusPayload,
+};
+use crate::health_check::{
+    history::StatusHistory,
+    uptime::{self, UptimeError, UptimeStore},
+    HealthCheckError, HealthChecker,
+};
+use crate::shutdown::{Readiness, ShutdownHandle};
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use serde::Deserialize;
 use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use warp::http::StatusCode;
 use warp::reject::{self, Rejection};
 use warp::reply::Reply;
 use warp::Filter;
@@ -9,44 +22,197 @@ pub mod model;
 
 pub fn routes(
     health_checker: Arc<dyn HealthChecker + Send + Sync>,
+    history: StatusHistory,
+    uptime_store: Option<Arc<dyn UptimeStore + Send + Sync>>,
+    readiness: Readiness,
+    shutdown: ShutdownHandle,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    ping().or(check_health(health_checker))
+    ping()
+        .or(live())
+        .or(startup(health_checker.clone()))
+        .or(ready(health_checker.clone(), readiness))
+        .or(check_health(health_checker))
+        .or(status_history(history.clone()))
+        .or(status_stream(history, shutdown))
+        .or(status_uptime(uptime_store))
 }
 
 fn ping() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     warp::path("ping").map(|| warp::reply::json(&"pong"))
 }
 
+/// Whether the process is up. Never depends on dependencies, otherwise their outages would get instances restarted.
+fn live() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("health" / "live").map(|| probe_reply(true))
+}
+
+/// Whether the initial dependency checks have completed.
+fn startup(
+    health_checker: Arc<dyn HealthChecker + Send + Sync>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("health" / "startup").map(move || probe_reply(health_checker.started()))
+}
+
+/// Whether the instance should receive traffic: it isn't shutting down and its critical dependencies are healthy.
+fn ready(
+    health_checker: Arc<dyn HealthChecker + Send + Sync>,
+    readiness: Readiness,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("health" / "ready").and_then(move || {
+        let (health_checker, readiness) = (health_checker.clone(), readiness.clone());
+        async move {
+            if !readiness.is_ready() {
+                return Ok(probe_reply(false));
+            }
+            match health_checker.check().await {
+                Ok(service_status) => Ok(probe_reply(service_status.is_ready())),
+                Err(e) => Err(reject::custom(e)),
+            }
+        }
+    })
+}
+
+fn probe_reply(up: bool) -> impl Reply {
+    if up {
+        warp::reply::with_status(warp::reply::json(&"up"), StatusCode::OK)
+    } else {
+        warp::reply::with_status(warp::reply::json(&"down"), StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
 fn check_health(
     health_checker: Arc<dyn HealthChecker + Send + Sync>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    warp::path("status").and_then(move || {
-        let fnn = health_checker.clone();
+    warp::path("status")
+        .and(warp::path::end())
+        .and(warp::query::<StatusQuery>())
+        .and_then(move |query: StatusQuery| {
+            let fnn = health_checker.clone();
+            async move {
+                let result = if query.fresh {
+                    fnn.check_fresh().await
+                } else {
+                    fnn.check().await
+                };
+                match result {
+                    Ok(service_status) if query.verbose => Ok(warp::reply::json(
+                        &VerboseServiceStatusPayload::from(service_status)
+                            .for_contract(query.contract),
+                    )),
+                    Ok(service_status) => Ok(warp::reply::json(
+                        &ServiceStatusPayload::from(service_status).for_contract(query.contract),
+                    )),
+                    Err(e) => Err(reject::custom(e)),
+                }
+            }
+        })
+}
+
+/// The latest status transitions of the service and its dependencies, oldest first.
+fn status_history(
+    history: StatusHistory,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("status" / "history")
+        .map(move || warp::reply::json(&StatusHistoryPayload::from(history.transitions())))
+}
+
+/// Pushes the status of the service as Server-Sent Events: the latest one first,
+/// then a new one each time the overall status or the status of a dependency changes.
+///
+/// Streams end once shutdown is triggered, so they don't hold up draining in-flight requests.
+fn status_stream(
+    history: StatusHistory,
+    shutdown: ShutdownHandle,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("status" / "stream")
+        .and(warp::query::<StreamQuery>())
+        .map(move |query: StreamQuery| {
+            // subscribing first, so no change is missed in between
+            let updates = history.subscribe();
+            let changes = stream::unfold(updates, |mut updates| async move {
+                loop {
+                    match updates.recv().await {
+                        Ok(change) => return Some((change.service_status().clone(), updates)),
+                        // a slow client only cares about the latest status anyway
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            });
+            let (shutdown, contract) = (shutdown.clone(), query.contract);
+            let events = stream::iter(history.latest())
+                .chain(changes)
+                .map(move |service_status| {
+                    warp::sse::Event::default().event("status").json_data(
+                        ServiceStatusPayload::from(service_status).for_contract(contract),
+                    )
+                })
+                .take_until(async move { shutdown.triggered().await });
+            warp::sse::reply(warp::sse::keep_alive().stream(events))
+        })
+}
+
+/// How available the service and its dependencies were over the last hour, day, week and month.
+fn status_uptime(
+    uptime_store: Option<Arc<dyn UptimeStore + Send + Sync>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("status" / "uptime").and_then(move || {
+        let uptime_store = uptime_store.clone();
         async move {
-            match fnn.check().await {
-                Ok(service_status) => Ok(warp::reply::json(&Into::<ServiceStatusPayload>::into(
-                    service_status,
-                ))),
+            let Some(uptime_store) = uptime_store else {
+                return Err(reject::custom(UptimeError::NotRecorded));
+            };
+            match uptime::report(uptime_store.as_ref(), Utc::now()).await {
+                Ok(report) => Ok(warp::reply::json(&UptimePayload::from(report))),
                 Err(e) => Err(reject::custom(e)),
             }
         }
     })
 }
 
-// TODO Wrap a json payload?
-//struct CustomJsonRejection(reply::Json);
+#[derive(Debug, Default, Deserialize)]
+struct StatusQuery {
+    // skips cached results, e.g. `/status?fresh=true`
+    #[serde(default)]
+    fresh: bool,
+    // includes the diagnostics of each dependency, e.g. `/status?verbose=true`
+    #[serde(default)]
+    verbose: bool,
+    // which statuses to report, e.g. `/status?contract=2` to tell Down and Unknown dependencies apart
+    #[serde(default)]
+    contract: ContractVersion,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StreamQuery {
+    // e.g. `/status/stream?contract=2`, see `StatusQuery`
+    #[serde(default)]
+    contract: ContractVersion,
+}
 
+// Turned into JSON error payloads by `routes::error::with_json_errors`
 impl warp::reject::Reject for HealthCheckError {}
+impl warp::reject::Reject for UptimeError {}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::health_check::service_status::ServiceStatus;
-    use crate::health_check::test_kit::StubHealthChecker;
+    use crate::health_check::maintenance::Maintenance;
+    use crate::health_check::polling::{Intervals, PollingHealthChecker};
+    use crate::health_check::service_status::test_kit::{database, snitch};
+    use crate::health_check::service_status::{
+        Criticality, DependencyStatus, ServiceStatus, Status,
+    };
+    use crate::health_check::test_kit::{CountingDependencyHealthChecker, StubHealthChecker};
+    use crate::health_check::uptime::test_kit::InMemoryUptimeStore;
     use crate::health_check::version::test_kit::StubVersion;
-    use crate::health_check::version::{Build, Commit, Environment};
-    use crate::health_status::model::ServiceStatusPayload;
+    use crate::health_check::version::{Build, Commit, Environment, VersionLoadError};
+    use crate::health_check::RusticSketchHealthChecker;
+    use crate::routes::error::{model::ErrorPayload, with_json_errors, REQUEST_ID_HEADER};
+    use crate::routes::health_status::model::ServiceStatusPayload;
+    use crate::shutdown::Shutdown;
     use serde_json::Value;
+    use std::time::Duration;
 
     // See https://docs.rs/warp/latest/warp/test/index.html
     #[tokio::test]
@@ -66,6 +232,135 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn live_is_up_regardless_of_dependencies() {
+        let filter = live();
+
+        let result = warp::test::request()
+            .path("/health/live")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn startup_waits_for_initial_checks() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = Arc::new(PollingHealthChecker::start(
+            RusticSketchHealthChecker::new(Box::new(stub_version()), vec![Box::new(database)]),
+            &Intervals::new(Duration::from_secs(3600)),
+        ));
+        let filter = startup(health_checker);
+
+        let before = warp::test::request()
+            .path("/health/startup")
+            .reply(&filter)
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let after = warp::test::request()
+            .path("/health/startup")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(before.status(), 503);
+        assert_eq!(after.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn ready_ignores_unhealthy_optional_dependencies() {
+        let filter = ready(
+            health_checker_with(vec![
+                DependencyStatus::new(database(), Status::Ok),
+                DependencyStatus::new(snitch(), Status::Degraded)
+                    .with_criticality(Criticality::Optional),
+            ]),
+            Readiness::default(),
+        );
+
+        let result = warp::test::request()
+            .path("/health/ready")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn ready_fails_when_a_critical_dependency_is_unhealthy() {
+        let filter = ready(
+            health_checker_with(vec![
+                DependencyStatus::new(database(), Status::Degraded),
+                DependencyStatus::new(snitch(), Status::Ok).with_criticality(Criticality::Optional),
+            ]),
+            Readiness::default(),
+        );
+
+        let result = warp::test::request()
+            .path("/health/ready")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 503);
+    }
+
+    #[tokio::test]
+    async fn ready_fails_once_shutdown_is_triggered() {
+        let shutdown = Shutdown::new(Duration::from_secs(1));
+        let filter = ready(health_checker_with(Vec::new()), shutdown.readiness());
+
+        let before = warp::test::request()
+            .path("/health/ready")
+            .reply(&filter)
+            .await;
+        shutdown.handle().trigger();
+        let after = warp::test::request()
+            .path("/health/ready")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(before.status(), 200);
+        assert_eq!(after.status(), 503);
+    }
+
+    #[tokio::test]
+    async fn ready_fails_during_maintenance() {
+        let now = Utc::now();
+        let maintenance = Maintenance::new(
+            "Upgrading the database",
+            now,
+            now + chrono::Duration::hours(1),
+        )
+        .unwrap();
+        let service_status = ServiceStatus::new(
+            stub_version().into(),
+            vec![DependencyStatus::new(database(), Status::Ok)],
+        )
+        .with_maintenance(maintenance);
+        let health_checker: Arc<dyn HealthChecker + Send + Sync> =
+            Arc::new(StubHealthChecker::new(Ok(service_status)));
+        let filter = ready(health_checker.clone(), Readiness::default());
+        let status = check_health(health_checker);
+
+        let ready = warp::test::request()
+            .path("/health/ready")
+            .reply(&filter)
+            .await;
+        let status = warp::test::request()
+            .path("/status?contract=2")
+            .reply(&status)
+            .await;
+
+        assert_eq!(ready.status(), 503);
+        let status: Value = serde_json::from_slice(status.body()).unwrap();
+        assert_eq!(status["status"], "Maintenance");
+        assert_eq!(status["maintenance"]["reason"], "Upgrading the database");
+        assert_eq!(
+            status["dependencies"][0],
+            serde_json::json!({ "database": "Ok" })
+        );
+    }
+
     #[tokio::test]
     async fn status_checks_service_health() {
         let version = StubVersion::new(
@@ -89,20 +384,285 @@ mod tests {
         assert_eq!(obtained, service_status.into());
     }
 
+    #[tokio::test]
+    async fn status_reports_down_dependencies_from_contract_version_2() {
+        let service_status = ServiceStatus::new(
+            stub_version().into(),
+            vec![
+                DependencyStatus::new(database(), Status::Down),
+                DependencyStatus::new(snitch(), Status::Unknown),
+            ],
+        );
+        let status = check_health(Arc::new(StubHealthChecker::new(Ok(service_status))));
+
+        let v1 = warp::test::request().path("/status").reply(&status).await;
+        let v2 = warp::test::request()
+            .path("/status?contract=2")
+            .reply(&status)
+            .await;
+        let invalid = warp::test::request()
+            .path("/status?contract=3")
+            .reply(&status)
+            .await;
+
+        let v1: Value = serde_json::from_slice(v1.body()).unwrap();
+        assert_eq!(v1["status"], "Degraded");
+        assert_eq!(
+            v1["dependencies"],
+            serde_json::json!([{ "database": "Degraded" }, { "snitch": "Degraded" }])
+        );
+        let v2: Value = serde_json::from_slice(v2.body()).unwrap();
+        assert_eq!(v2["status"], "Down");
+        assert_eq!(
+            v2["dependencies"],
+            serde_json::json!([{ "database": "Down" }, { "snitch": "Unknown" }])
+        );
+        assert_eq!(invalid.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn fresh_status_bypasses_cached_results() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = Arc::new(PollingHealthChecker::start(
+            RusticSketchHealthChecker::new(
+                Box::new(stub_version()),
+                vec![Box::new(database.clone())],
+            ),
+            &Intervals::new(Duration::from_secs(3600)),
+        ));
+        let status = check_health(health_checker);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        warp::test::request().path("/status").reply(&status).await;
+        warp::test::request().path("/status").reply(&status).await;
+        assert_eq!(database.checks(), 1);
+
+        let result = warp::test::request()
+            .path("/status?fresh=true")
+            .reply(&status)
+            .await;
+        assert_eq!(result.status(), 200);
+        assert_eq!(database.checks(), 2);
+    }
+
+    #[tokio::test]
+    async fn verbose_status_includes_dependency_diagnostics() {
+        let database = CountingDependencyHealthChecker::new(database(), Status::Ok);
+        let health_checker = Arc::new(RusticSketchHealthChecker::new(
+            Box::new(stub_version()),
+            vec![Box::new(database)],
+        ));
+        let status = check_health(health_checker);
+
+        let compact = warp::test::request().path("/status").reply(&status).await;
+        let verbose = warp::test::request()
+            .path("/status?verbose=true")
+            .reply(&status)
+            .await;
+
+        let compact: Value = serde_json::from_slice(compact.body()).unwrap();
+        assert_eq!(
+            compact["dependencies"][0],
+            serde_json::json!({ "database": "Ok" })
+        );
+        let verbose: Value = serde_json::from_slice(verbose.body()).unwrap();
+        let dependency = &verbose["dependencies"][0];
+        assert_eq!(dependency["dependency"], "database");
+        assert_eq!(dependency["criticality"], "critical");
+        assert_eq!(dependency["consecutive_failures"], 0);
+        assert!(dependency["latency_ms"].is_u64());
+        assert!(dependency["checked_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn status_rejects_invalid_query() {
+        let health_checker = Arc::new(StubHealthChecker::new(Ok(ServiceStatus::new(
+            stub_version().into(),
+            Vec::new(),
+        ))));
+
+        let status = with_json_errors(check_health(health_checker));
+        let result = warp::test::request()
+            .path("/status?fresh=maybe")
+            .reply(&status)
+            .await;
+
+        assert_eq!(result.status(), 400);
+        let obtained: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(obtained.code(), "invalid_query");
+    }
+
     #[tokio::test]
     async fn status_fails_with_error() {
-        let health_checker = Arc::new(StubHealthChecker::new(Err(HealthCheckError::new(
-            "something went wrong".to_string(),
+        let health_checker = Arc::new(StubHealthChecker::new(Err(HealthCheckError::Version(
+            VersionLoadError::MissingBuild {
+                path: "rustic.version".to_string(),
+            },
         ))));
 
-        let status = check_health(health_checker);
+        let status = with_json_errors(check_health(health_checker));
         let result = warp::test::request()
             .method("GET")
             .path("/status")
+            .header(REQUEST_ID_HEADER, "c0ffee")
             .reply(&status)
             .await;
 
         assert_eq!(result.status(), 500);
-        // TODO Error payload
+        let obtained: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(
+            obtained,
+            ErrorPayload::new(
+                "version_unavailable".to_string(),
+                "Failed to load version: No build number specified in `rustic.version`".to_string(),
+                "c0ffee".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn status_history_lists_transitions() {
+        let history = StatusHistory::new(10);
+        history.record(&ServiceStatus::new(
+            stub_version().into(),
+            vec![DependencyStatus::new(database(), Status::Ok)],
+        ));
+        history.record(&ServiceStatus::new(
+            stub_version().into(),
+            vec![DependencyStatus::new(database(), Status::Degraded)
+                .with_reason("Timed out".to_string())],
+        ));
+        let filter = status_history(history);
+
+        let result = warp::test::request()
+            .path("/status/history")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 200);
+        let transitions = serde_json::from_slice::<Value>(result.body()).unwrap()["transitions"]
+            .as_array()
+            .unwrap()
+            .clone();
+        assert_eq!(transitions.len(), 4);
+        assert_eq!(transitions[2]["dependency"], "database");
+        assert_eq!(transitions[2]["from"], "Ok");
+        assert_eq!(transitions[2]["to"], "Degraded");
+        assert_eq!(transitions[2]["reason"], "Timed out");
+        assert!(transitions[3].get("dependency").is_none());
+    }
+
+    #[tokio::test]
+    async fn status_stream_pushes_changes() {
+        let history = StatusHistory::new(10);
+        history.record(&ServiceStatus::new(
+            stub_version().into(),
+            vec![DependencyStatus::new(database(), Status::Ok)],
+        ));
+        let shutdown = Shutdown::new(Duration::from_secs(1));
+        let (address, server) = warp::serve(status_stream(history.clone(), shutdown.handle()))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let mut response = reqwest::get(format!("http://{address}/status/stream"))
+            .await
+            .unwrap();
+        let latest = next_event(&mut response).await;
+        history.record(&ServiceStatus::new(
+            stub_version().into(),
+            vec![DependencyStatus::new(database(), Status::Degraded)],
+        ));
+        let change = next_event(&mut response).await;
+        shutdown.handle().trigger();
+        let end = tokio::time::timeout(Duration::from_secs(1), response.chunk())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(latest["status"], "Ok");
+        assert_eq!(change["status"], "Degraded");
+        assert_eq!(change["dependencies"][0]["database"], "Degraded");
+        assert_eq!(end, None);
+    }
+
+    #[tokio::test]
+    async fn status_uptime_reports_availability_by_window() {
+        let uptime_store = InMemoryUptimeStore::default();
+        let now = Utc::now();
+        let status_of = |status| {
+            ServiceStatus::new(
+                stub_version().into(),
+                vec![DependencyStatus::new(database(), status)],
+            )
+        };
+        uptime_store
+            .record(
+                now - chrono::Duration::hours(2),
+                &status_of(Status::Degraded),
+            )
+            .await
+            .unwrap();
+        uptime_store
+            .record(now, &status_of(Status::Ok))
+            .await
+            .unwrap();
+        let filter = status_uptime(Some(Arc::new(uptime_store)));
+
+        let result = warp::test::request()
+            .path("/status/uptime")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 200);
+        let uptime: Value = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(uptime["overall"]["1h"], 1.0);
+        assert_eq!(uptime["overall"]["24h"], 0.5);
+        assert_eq!(uptime["dependencies"]["database"]["30d"], 0.5);
+    }
+
+    #[tokio::test]
+    async fn status_uptime_is_not_found_unless_recorded() {
+        let filter = with_json_errors(status_uptime(None));
+
+        let result = warp::test::request()
+            .path("/status/uptime")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(result.status(), 404);
+        let obtained: ErrorPayload = serde_json::from_slice(result.body()).unwrap();
+        assert_eq!(obtained.code(), "uptime_not_recorded");
+    }
+
+    // The JSON data of the next `status` event, skipping keep-alive comments
+    async fn next_event(response: &mut reqwest::Response) -> Value {
+        loop {
+            let chunk = tokio::time::timeout(Duration::from_secs(1), response.chunk())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+            if let Some(data) = chunk.lines().find_map(|line| line.strip_prefix("data:")) {
+                return serde_json::from_str(data).unwrap();
+            }
+        }
+    }
+
+    fn stub_version() -> StubVersion {
+        StubVersion::new(
+            Environment::new("dev".to_string()),
+            Build::new("feat.branch.108".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        )
+    }
+
+    fn health_checker_with(
+        dependencies: Vec<DependencyStatus>,
+    ) -> Arc<dyn HealthChecker + Send + Sync> {
+        Arc::new(StubHealthChecker::new(Ok(ServiceStatus::new(
+            stub_version().into(),
+            dependencies,
+        ))))
     }
 }
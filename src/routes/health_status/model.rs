@@ -1,30 +1,339 @@
 use crate::health_check::{
-    service_status::{Dependency, DependencyStatus, ServiceStatus, Status},
-    version::Version,
+    history::Transition,
+    maintenance::Maintenance,
+    service_status::{
+        Criticality, DependencyKind, DependencyStatus, DownstreamStatus, ServiceStatus, Status,
+    },
+    uptime::UptimeReport,
+    version::{Build, Commit, Environment, Version},
 };
+use chrono::{DateTime, Utc};
 use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Which statuses consumers of `/status` understand, e.g. `/status?contract=2`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ContractVersion {
+    /// Only Ok and Degraded, what consumers have relied on from the start.
+    #[default]
+    V1,
+    /// Also Unknown, Down and Maintenance.
+    V2,
+}
+impl ContractVersion {
+    /// How `status` is reported to consumers of this version of the contract.
+    pub fn status(&self, status: &Status) -> Status {
+        match (self, status) {
+            (ContractVersion::V1, Status::Ok) => Status::Ok,
+            (ContractVersion::V1, _) => Status::Degraded,
+            (ContractVersion::V2, status) => status.clone(),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct ServiceStatusPayload {
     #[serde(flatten)]
     version: VersionPayload,
     status: Status,
+    // omitted unless the service is in maintenance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    maintenance: Option<MaintenancePayload>,
     dependencies: Vec<DependencyStatusPayload>,
+    // by dependency name, what downstream services report about themselves; omitted when there are none
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    downstream: BTreeMap<String, ServiceStatusPayload>,
 }
 impl From<ServiceStatus> for ServiceStatusPayload {
     fn from(value: ServiceStatus) -> ServiceStatusPayload {
         ServiceStatusPayload {
             version: value.version().clone().into(),
             status: value.status().clone(),
+            maintenance: value.maintenance().cloned().map(Into::into),
+            dependencies: value
+                .dependencies()
+                .iter()
+                .map(|d| d.clone().into())
+                .collect(),
+            downstream: downstream_of(value.dependencies()),
+        }
+    }
+}
+impl ServiceStatusPayload {
+    /// Reports statuses, down to those of downstream services, as `contract` consumers understand them.
+    pub fn for_contract(self, contract: ContractVersion) -> Self {
+        ServiceStatusPayload {
+            status: contract.status(&self.status),
+            dependencies: self
+                .dependencies
+                .into_iter()
+                .map(|d| DependencyStatusPayload {
+                    status: contract.status(&d.status),
+                    ..d
+                })
+                .collect(),
+            downstream: for_contract(self.downstream, contract),
+            ..self
+        }
+    }
+}
+impl From<DownstreamStatus> for ServiceStatusPayload {
+    fn from(value: DownstreamStatus) -> ServiceStatusPayload {
+        ServiceStatusPayload {
+            version: value.version().clone().into(),
+            status: value.status().clone(),
+            maintenance: None,
+            dependencies: value
+                .dependencies()
+                .iter()
+                .map(|(dependency, status)| DependencyStatusPayload {
+                    dependency: dependency.clone(),
+                    status: status.clone(),
+                })
+                .collect(),
+            downstream: value
+                .downstream()
+                .iter()
+                .map(|(name, downstream)| (name.clone(), downstream.clone().into()))
+                .collect(),
+        }
+    }
+}
+impl From<ServiceStatusPayload> for DownstreamStatus {
+    fn from(value: ServiceStatusPayload) -> DownstreamStatus {
+        DownstreamStatus::new(
+            value.version.into(),
+            value.status,
+            value
+                .dependencies
+                .into_iter()
+                .map(|d| (d.dependency, d.status))
+                .collect(),
+            value
+                .downstream
+                .into_iter()
+                .map(|(name, downstream)| (name, downstream.into()))
+                .collect(),
+        )
+    }
+}
+
+fn for_contract(
+    downstream: BTreeMap<String, ServiceStatusPayload>,
+    contract: ContractVersion,
+) -> BTreeMap<String, ServiceStatusPayload> {
+    downstream
+        .into_iter()
+        .map(|(name, payload)| (name, payload.for_contract(contract)))
+        .collect()
+}
+
+fn downstream_of(dependencies: &[DependencyStatus]) -> BTreeMap<String, ServiceStatusPayload> {
+    dependencies
+        .iter()
+        .filter_map(|d| {
+            d.downstream()
+                .map(|downstream| (d.dependency().name().to_string(), downstream.clone().into()))
+        })
+        .collect()
+}
+
+/// Like `ServiceStatusPayload`, but with the diagnostics of each dependency, e.g. `/status?verbose=true`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct VerboseServiceStatusPayload {
+    #[serde(flatten)]
+    version: VersionPayload,
+    status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    maintenance: Option<MaintenancePayload>,
+    dependencies: Vec<DependencyDiagnosticsPayload>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    downstream: BTreeMap<String, ServiceStatusPayload>,
+}
+impl From<ServiceStatus> for VerboseServiceStatusPayload {
+    fn from(value: ServiceStatus) -> VerboseServiceStatusPayload {
+        VerboseServiceStatusPayload {
+            version: value.version().clone().into(),
+            status: value.status().clone(),
+            maintenance: value.maintenance().cloned().map(Into::into),
             dependencies: value
                 .dependencies()
                 .iter()
                 .map(|d| d.clone().into())
                 .collect(),
+            downstream: downstream_of(value.dependencies()),
+        }
+    }
+}
+impl VerboseServiceStatusPayload {
+    /// Reports statuses, down to those of downstream services, as `contract` consumers understand them.
+    pub fn for_contract(self, contract: ContractVersion) -> Self {
+        VerboseServiceStatusPayload {
+            status: contract.status(&self.status),
+            dependencies: self
+                .dependencies
+                .into_iter()
+                .map(|d| DependencyDiagnosticsPayload {
+                    status: contract.status(&d.status),
+                    ..d
+                })
+                .collect(),
+            downstream: for_contract(self.downstream, contract),
+            ..self
+        }
+    }
+}
+
+/// Why and until when the service is out of rotation, e.g. `/admin/maintenance`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaintenancePayload {
+    reason: String,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+}
+impl From<Maintenance> for MaintenancePayload {
+    fn from(value: Maintenance) -> MaintenancePayload {
+        MaintenancePayload {
+            reason: value.reason().clone(),
+            since: *value.since(),
+            until: *value.until(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DependencyDiagnosticsPayload {
+    dependency: String,
+    kind: DependencyKind,
+    status: Status,
+    criticality: Criticality,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checked_at: Option<DateTime<Utc>>,
+    consecutive_failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    details: BTreeMap<String, String>,
+}
+impl From<DependencyStatus> for DependencyDiagnosticsPayload {
+    fn from(value: DependencyStatus) -> DependencyDiagnosticsPayload {
+        let diagnostics = value.diagnostics();
+        DependencyDiagnosticsPayload {
+            dependency: value.dependency().name().to_string(),
+            kind: value.dependency().kind().clone(),
+            status: value.status().clone(),
+            criticality: *value.criticality(),
+            latency_ms: diagnostics
+                .latency()
+                .map(|latency| latency.as_millis().try_into().unwrap_or(u64::MAX)),
+            checked_at: diagnostics.checked_at().copied(),
+            consecutive_failures: diagnostics.consecutive_failures(),
+            reason: diagnostics.reason().map(str::to_string),
+            details: diagnostics.details().clone(),
+        }
+    }
+}
+
+/// The latest status transitions, oldest first, e.g. `/status/history`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatusHistoryPayload {
+    transitions: Vec<TransitionPayload>,
+}
+impl From<Vec<Transition>> for StatusHistoryPayload {
+    fn from(value: Vec<Transition>) -> StatusHistoryPayload {
+        StatusHistoryPayload {
+            transitions: value.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransitionPayload {
+    at: DateTime<Utc>,
+    // omitted for the overall status of the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dependency: Option<String>,
+    // omitted when first observed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from: Option<Status>,
+    to: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+impl From<Transition> for TransitionPayload {
+    fn from(value: Transition) -> TransitionPayload {
+        TransitionPayload {
+            at: *value.at(),
+            dependency: value.dependency().clone(),
+            from: value.from().clone(),
+            to: value.to().clone(),
+            reason: value.reason().clone(),
+        }
+    }
+}
+
+/// Sent to webhooks when the status of the service or of its dependencies changes,
+/// with every transition since the previous event.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatusEventPayload {
+    // the same across delivery attempts, so receivers can tell retries apart
+    id: String,
+    occurred_at: DateTime<Utc>,
+    #[serde(flatten)]
+    version: VersionPayload,
+    status: Status,
+    transitions: Vec<TransitionPayload>,
+}
+impl StatusEventPayload {
+    pub fn new(
+        id: String,
+        occurred_at: DateTime<Utc>,
+        service_status: &ServiceStatus,
+        transitions: Vec<Transition>,
+    ) -> Self {
+        StatusEventPayload {
+            id,
+            occurred_at,
+            version: service_status.version().clone().into(),
+            status: service_status.status().clone(),
+            transitions: transitions.into_iter().map(Into::into).collect(),
         }
     }
 }
 
+/// How available the service and each of its dependencies were, e.g. `/status/uptime`.
+///
+/// Availability is between 0 and 1, by window, e.g. `{ "1h": 1.0, "24h": 0.9986 }`.
+/// Windows without samples are omitted.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct UptimePayload {
+    overall: BTreeMap<String, f64>,
+    dependencies: BTreeMap<String, BTreeMap<String, f64>>,
+}
+impl From<UptimeReport> for UptimePayload {
+    fn from(value: UptimeReport) -> UptimePayload {
+        let mut payload = UptimePayload {
+            overall: BTreeMap::new(),
+            dependencies: BTreeMap::new(),
+        };
+        for (window, availabilities) in value {
+            for availability in availabilities {
+                let Some(ratio) = availability.ratio() else {
+                    continue;
+                };
+                let windows = match availability.dependency() {
+                    Some(dependency) => payload.dependencies.entry(dependency.clone()).or_default(),
+                    None => &mut payload.overall,
+                };
+                windows.insert(window.to_string(), ratio);
+            }
+        }
+        payload
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct VersionPayload {
     env: String,
@@ -40,6 +349,15 @@ impl From<Version> for VersionPayload {
         }
     }
 }
+impl From<VersionPayload> for Version {
+    fn from(value: VersionPayload) -> Version {
+        Version::new(
+            Environment::new(value.env),
+            Build::new(value.build),
+            Commit::new(value.commit),
+        )
+    }
+}
 
 impl Serialize for Status {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -48,20 +366,41 @@ impl Serialize for Status {
     {
         serializer.serialize_str(match self {
             Status::Ok => "Ok",
+            Status::Unknown => "Unknown",
             Status::Degraded => "Degraded",
+            Status::Down => "Down",
+            Status::Maintenance => "Maintenance",
+        })
+    }
+}
+
+impl Serialize for Criticality {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Criticality::Critical => "critical",
+            Criticality::Optional => "optional",
         })
     }
 }
 
-impl Serialize for Dependency {
+impl Serialize for DependencyKind {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         serializer.serialize_str(match self {
-            Dependency::Auth0 => "auth0",
-            Dependency::Database => "database",
-            Dependency::Snitch => "snitch",
+            DependencyKind::Postgres => "postgres",
+            DependencyKind::Http => "http",
+            DependencyKind::Oidc => "oidc",
+            DependencyKind::DownstreamStatus => "downstream-status",
+            DependencyKind::Resource => "resource",
+            DependencyKind::Tcp => "tcp",
+            DependencyKind::Dns => "dns",
+            DependencyKind::Command => "command",
+            DependencyKind::Custom(kind) => kind,
         })
     }
 }
@@ -76,7 +415,10 @@ impl<'de> Deserialize<'de> for Status {
         let s = String::deserialize(deserializer)?;
         match s.as_str() {
             "Ok" => Ok(Status::Ok),
+            "Unknown" => Ok(Status::Unknown),
             "Degraded" => Ok(Status::Degraded),
+            "Down" => Ok(Status::Down),
+            "Maintenance" => Ok(Status::Maintenance),
             unknown => Err(serde::de::Error::custom(format!(
                 "Invalid Status: `{}`",
                 unknown
@@ -85,31 +427,71 @@ impl<'de> Deserialize<'de> for Status {
     }
 }
 
-impl<'de> Deserialize<'de> for Dependency {
+impl<'de> Deserialize<'de> for ContractVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "1" => Ok(ContractVersion::V1),
+            "2" => Ok(ContractVersion::V2),
+            unknown => Err(serde::de::Error::custom(format!(
+                "Invalid ContractVersion: `{}`",
+                unknown
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Criticality {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
         match s.as_str() {
-            "database" => Ok(Dependency::Database),
+            "critical" => Ok(Criticality::Critical),
+            "optional" => Ok(Criticality::Optional),
             unknown => Err(serde::de::Error::custom(format!(
-                "Invalid Dependency: `{}`",
+                "Invalid Criticality: `{}`",
                 unknown
             ))),
         }
     }
 }
 
+impl<'de> Deserialize<'de> for DependencyKind {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "postgres" => Ok(DependencyKind::Postgres),
+            "http" => Ok(DependencyKind::Http),
+            "oidc" => Ok(DependencyKind::Oidc),
+            "downstream-status" => Ok(DependencyKind::DownstreamStatus),
+            "resource" => Ok(DependencyKind::Resource),
+            "tcp" => Ok(DependencyKind::Tcp),
+            "dns" => Ok(DependencyKind::Dns),
+            "command" => Ok(DependencyKind::Command),
+            "" => Err(serde::de::Error::custom("Invalid DependencyKind: ``")),
+            custom => Ok(DependencyKind::Custom(custom.to_string())),
+        }
+    }
+}
+
+// `{"<dependency name>": "<status>"}`
 #[derive(Debug, PartialEq)]
 pub struct DependencyStatusPayload {
-    dependency: Dependency,
+    dependency: String,
     status: Status,
 }
 impl From<DependencyStatus> for DependencyStatusPayload {
     fn from(value: DependencyStatus) -> DependencyStatusPayload {
         DependencyStatusPayload {
-            dependency: value.dependency().clone(),
+            dependency: value.dependency().name().to_string(),
             status: value.status().clone(),
         }
     }
@@ -158,3 +540,28 @@ impl<'de> Deserialize<'de> for DependencyStatusPayload {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_check::service_status::test_kit::arb_dependency_kind;
+    use crate::health_check::service_status::Dependency;
+    use proptest::proptest;
+
+    proptest! {
+        #[test]
+        fn dependencies_of_every_kind_roundtrip(
+            name in "[a-z][a-z0-9_-]{0,15}",
+            kind in arb_dependency_kind(),
+        ) {
+            let status = DependencyStatus::new(Dependency::new(name, kind), Status::Ok);
+            let payload = DependencyDiagnosticsPayload::from(status.clone());
+            let compact = DependencyStatusPayload::from(status);
+
+            let json = serde_json::to_string(&payload).unwrap();
+            assert_eq!(serde_json::from_str::<DependencyDiagnosticsPayload>(&json).unwrap(), payload);
+            let json = serde_json::to_string(&compact).unwrap();
+            assert_eq!(serde_json::from_str::<DependencyStatusPayload>(&json).unwrap(), compact);
+       }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::store::Store;
+use derive_more::Display;
+use log::{info, warn};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Coordinates the graceful shutdown of the service.
+///
+/// Once triggered, either by SIGTERM/SIGINT or through a [`ShutdownHandle`]:
+///  1. readiness starts failing;
+///  2. the server stops accepting new connections;
+///  3. in-flight requests are given up to `deadline` to complete;
+///  4. the store is closed.
+pub struct Shutdown {
+    handle: ShutdownHandle,
+    deadline: Duration,
+}
+impl Shutdown {
+    pub fn new(deadline: Duration) -> Self {
+        let (trigger, _) = watch::channel(None);
+        Shutdown {
+            handle: ShutdownHandle {
+                trigger: Arc::new(trigger),
+                readiness: Readiness::default(),
+            },
+            deadline,
+        }
+    }
+
+    pub fn handle(&self) -> ShutdownHandle {
+        self.handle.clone()
+    }
+
+    pub fn readiness(&self) -> Readiness {
+        self.handle.readiness.clone()
+    }
+
+    /// Resolves once shutdown is triggered. Meant to be handed over to the server.
+    pub fn signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        let handle = self.handle();
+        async move {
+            handle.triggered().await;
+        }
+    }
+
+    /// Waits for shutdown to be triggered, then drains `server` and closes `store`.
+    ///
+    /// `server` must stop accepting connections once [`Shutdown::signal`] resolves.
+    pub async fn drain<S>(
+        self,
+        server: S,
+        store: Option<Arc<dyn Store + Send + Sync>>,
+    ) -> ShutdownSummary
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        let mut server = tokio::spawn(server);
+        let reason = self.handle.triggered().await;
+        let started = Instant::now();
+        info!(
+            "Shutting down ({reason}): draining in-flight requests for up to {:?}",
+            self.deadline
+        );
+
+        let drained = match tokio::time::timeout(self.deadline, &mut server).await {
+            Ok(_) => true,
+            Err(_) => {
+                // remaining connections are dropped along with the runtime
+                server.abort();
+                false
+            }
+        };
+        let store_closed = match store {
+            Some(store) => {
+                store.close().await;
+                true
+            }
+            None => false,
+        };
+
+        let summary = ShutdownSummary {
+            reason,
+            drained,
+            store_closed,
+            elapsed: started.elapsed(),
+        };
+        if drained {
+            info!("{summary}");
+        } else {
+            warn!("{summary}");
+        }
+        summary
+    }
+}
+
+/// Triggers the shutdown of a running service.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    trigger: Arc<watch::Sender<Option<ShutdownReason>>>,
+    readiness: Readiness,
+}
+impl ShutdownHandle {
+    pub fn trigger(&self) {
+        self.shutdown(ShutdownReason::Requested)
+    }
+
+    /// Triggers shutdown when the process receives SIGTERM or SIGINT.
+    pub fn listen_for_signals(&self) {
+        let handle = self.clone();
+        tokio::spawn(async move {
+            let reason = wait_for_signal().await;
+            handle.shutdown(reason);
+        });
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.trigger.borrow().is_some()
+    }
+
+    /// Resolves once shutdown is triggered, with the reason it was triggered for.
+    pub async fn triggered(&self) -> ShutdownReason {
+        let mut receiver = self.trigger.subscribe();
+        let reason = receiver
+            .wait_for(Option::is_some)
+            .await
+            // the sender lives as long as `self`, so the channel can't be closed while we wait
+            .map(|reason| *reason)
+            .expect("shutdown channel closed unexpectedly");
+        reason.expect("shutdown triggered without a reason")
+    }
+
+    fn shutdown(&self, reason: ShutdownReason) {
+        // readiness fails first so load balancers stop routing to this instance asap
+        self.readiness.set_not_ready();
+        // only the first trigger counts
+        self.trigger.send_if_modified(|current| {
+            if current.is_none() {
+                *current = Some(reason);
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+/// Whether the instance is able to serve traffic.
+#[derive(Clone, Debug)]
+pub struct Readiness(Arc<AtomicBool>);
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn set_not_ready(&self) {
+        self.0.store(false, Ordering::SeqCst)
+    }
+}
+impl Default for Readiness {
+    fn default() -> Self {
+        Readiness(Arc::new(AtomicBool::new(true)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, PartialEq)]
+pub enum ShutdownReason {
+    #[display("shutdown requested")]
+    Requested,
+    #[display("SIGINT")]
+    Interrupt,
+    #[display("SIGTERM")]
+    Terminate,
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(
+    "Shutdown ({reason}) completed in {elapsed:?}: requests drained: {drained}, store closed: {store_closed}"
+)]
+pub struct ShutdownSummary {
+    reason: ShutdownReason,
+    drained: bool,
+    store_closed: bool,
+    elapsed: Duration,
+}
+impl ShutdownSummary {
+    pub fn reason(&self) -> &ShutdownReason {
+        &self.reason
+    }
+
+    /// `false` if in-flight requests didn't complete within the deadline.
+    pub fn drained(&self) -> bool {
+        self.drained
+    }
+
+    pub fn store_closed(&self) -> bool {
+        self.store_closed
+    }
+
+    pub fn elapsed(&self) -> &Duration {
+        &self.elapsed
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> ShutdownReason {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen to SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => ShutdownReason::Terminate,
+        _ = tokio::signal::ctrl_c() => ShutdownReason::Interrupt,
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> ShutdownReason {
+    let _ = tokio::signal::ctrl_c().await;
+    ShutdownReason::Interrupt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_kit::StubStore;
+    use std::net::SocketAddr;
+    use tokio::task::JoinHandle;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn drains_in_flight_requests_before_stopping() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let handle = shutdown.handle();
+        let (address, drain) = serve_slow_route(shutdown, Duration::from_millis(200), None);
+
+        let request = tokio::spawn(reqwest::get(format!("http://{address}/slow")));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.trigger();
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), 200);
+        let summary = drain.await.unwrap();
+        assert_eq!(*summary.reason(), ShutdownReason::Requested);
+        assert!(summary.drained());
+    }
+
+    #[tokio::test]
+    async fn stops_accepting_connections_once_triggered() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let handle = shutdown.handle();
+        let (address, drain) = serve_slow_route(shutdown, Duration::ZERO, None);
+
+        handle.trigger();
+        drain.await.unwrap();
+
+        assert!(reqwest::get(format!("http://{address}/slow"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn gives_up_draining_after_deadline() {
+        let shutdown = Shutdown::new(Duration::from_millis(100));
+        let handle = shutdown.handle();
+        let (address, drain) = serve_slow_route(shutdown, Duration::from_secs(10), None);
+
+        let _request = tokio::spawn(reqwest::get(format!("http://{address}/slow")));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.trigger();
+
+        let summary = drain.await.unwrap();
+        assert!(!summary.drained());
+        assert!(*summary.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn fails_readiness_and_closes_store() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let handle = shutdown.handle();
+        let readiness = shutdown.readiness();
+        let store = StubStore::default();
+        let (_, drain) = serve_slow_route(shutdown, Duration::ZERO, Some(Arc::new(store.clone())));
+        assert!(readiness.is_ready());
+
+        handle.trigger();
+
+        assert!(!readiness.is_ready());
+        let summary = drain.await.unwrap();
+        assert!(summary.store_closed());
+        assert!(store.is_closed());
+    }
+
+    fn serve_slow_route(
+        shutdown: Shutdown,
+        delay: Duration,
+        store: Option<Arc<dyn Store + Send + Sync>>,
+    ) -> (SocketAddr, JoinHandle<ShutdownSummary>) {
+        let slow = warp::path("slow").then(move || async move {
+            tokio::time::sleep(delay).await;
+            "done"
+        });
+        let (address, server) =
+            warp::serve(slow).bind_with_graceful_shutdown(([127, 0, 0, 1], 0), shutdown.signal());
+        (address, tokio::spawn(shutdown.drain(server, store)))
+    }
+}
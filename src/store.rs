@@ -1 +1,34 @@
+use async_trait::async_trait;
+
 pub mod postgres;
+
+#[async_trait]
+pub trait Store {
+    /// Releases the underlying resources, e.g. a connection pool.
+    async fn close(&self);
+}
+
+#[cfg(test)]
+pub(crate) mod test_kit {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /* Stubs */
+
+    #[derive(Clone, Default)]
+    pub struct StubStore {
+        closed: Arc<AtomicBool>,
+    }
+    impl StubStore {
+        pub fn is_closed(&self) -> bool {
+            self.closed.load(Ordering::SeqCst)
+        }
+    }
+    #[async_trait]
+    impl Store for StubStore {
+        async fn close(&self) {
+            self.closed.store(true, Ordering::SeqCst)
+        }
+    }
+}
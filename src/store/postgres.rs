@@ -1,27 +1,112 @@
+use super::Store;
 use crate::health_check::{
-    service_status::{Dependency, DependencyStatus, Status},
+    maintenance::{Maintenance, MaintenanceError, MaintenanceStore},
+    service_status::{Dependency, DependencyKind, DependencyStatus, ServiceStatus, Status},
+    uptime::{Availability, UptimeConfig, UptimeError, UptimeStore},
     DependencyHealthChecker,
 };
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::time::Duration;
 
 use derive_more::Constructor;
 use derive_more::Display;
 use derive_more::Error;
 use getset::Getters;
+use serde::Deserialize;
 
-#[derive(Clone, Constructor, Debug)]
+#[derive(Clone, Constructor, Deserialize, Getters)]
+#[getset(get = "pub")]
 pub struct DatabaseConfig {
     host: String,
     port: u16,
     name: String,
     user: String,
+    #[getset(skip)]
     password: String, // Secret type?
     db_pool_threads: u32,
 }
+// Not derived, so the password doesn't end up in logs
+impl std::fmt::Debug for DatabaseConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatabaseConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("name", &self.name)
+            .field("user", &self.user)
+            .field("password", &"***")
+            .field("db_pool_threads", &self.db_pool_threads)
+            .finish()
+    }
+}
+
+/// Thresholds of the deep database check, which looks into more than whether the database responds.
+#[derive(Clone, Debug, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct DeepCheckConfig {
+    // whether being connected to a replica (in recovery) is expected
+    #[serde(default)]
+    allow_replica: bool,
+    #[serde(default = "default_max_replication_lag", with = "humantime_serde")]
+    max_replication_lag: Duration,
+    // the share of `db_pool_threads` in use above which the pool is considered saturated, e.g. 0.9
+    #[serde(default = "default_max_pool_saturation")]
+    max_pool_saturation: f64,
+    // the latest migration the service expects in `_sqlx_migrations`, if any
+    #[serde(default)]
+    expected_migration: Option<i64>,
+    #[serde(default = "default_max_transaction_age", with = "humantime_serde")]
+    max_transaction_age: Duration,
+}
+impl DeepCheckConfig {
+    pub fn allowing_replica(self) -> Self {
+        DeepCheckConfig {
+            allow_replica: true,
+            ..self
+        }
+    }
+
+    pub fn expecting_migration(self, version: i64) -> Self {
+        DeepCheckConfig {
+            expected_migration: Some(version),
+            ..self
+        }
+    }
+}
+impl Default for DeepCheckConfig {
+    fn default() -> Self {
+        DeepCheckConfig {
+            allow_replica: false,
+            max_replication_lag: default_max_replication_lag(),
+            max_pool_saturation: default_max_pool_saturation(),
+            expected_migration: None,
+            max_transaction_age: default_max_transaction_age(),
+        }
+    }
+}
 
+fn default_max_replication_lag() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_pool_saturation() -> f64 {
+    0.9
+}
+
+fn default_max_transaction_age() -> Duration {
+    Duration::from_secs(300)
+}
+
+// Cheap to clone: clones share the same connection pool
+#[derive(Clone)]
 pub struct PostgresStore {
     pool: PgPool,
+    max_connections: u32,
+    // `SELECT 42` unless set
+    deep_check: Option<DeepCheckConfig>,
+    // as reported in `/status`
+    dependency_name: String,
 }
 impl PostgresStore {
     pub async fn new(config: DatabaseConfig) -> Result<Self, PostgresStoreError> {
@@ -34,28 +119,627 @@ impl PostgresStore {
             .max_connections(config.db_pool_threads)
             .connect(&connection_string)
             .await
-            .map_err(|e| PostgresStoreError {
-                message: e.to_string(),
-            })?;
+            .map_err(PostgresStoreError::Connection)?;
+
+        Ok(PostgresStore {
+            pool,
+            max_connections: config.db_pool_threads,
+            deep_check: None,
+            dependency_name: "database".to_string(),
+        })
+    }
+
+    pub fn with_deep_check(self, deep_check: DeepCheckConfig) -> Self {
+        PostgresStore {
+            deep_check: Some(deep_check),
+            ..self
+        }
+    }
+
+    /// Defaults to `database`.
+    pub fn with_dependency_name(self, name: &str) -> Self {
+        PostgresStore {
+            dependency_name: name.to_string(),
+            ..self
+        }
+    }
+
+    /// Creates the table uptime samples are recorded in, unless it exists already.
+    pub async fn prepare_uptime(&self) -> Result<(), PostgresStoreError> {
+        // one row per sample, or per hour of samples once downsampled
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS health_samples ( \
+                 recorded_at timestamptz NOT NULL, \
+                 dependency text, \
+                 ok integer NOT NULL, \
+                 total integer NOT NULL, \
+                 downsampled boolean NOT NULL DEFAULT false \
+             )",
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(PostgresStoreError::Schema)?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS health_samples_recorded_at ON health_samples (recorded_at)",
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(PostgresStoreError::Schema)?;
+        Ok(())
+    }
+
+    /// Creates the table maintenance is kept in, unless it exists already.
+    pub async fn prepare_maintenance(&self) -> Result<(), PostgresStoreError> {
+        // at most one row, shared by every instance
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS health_maintenance ( \
+                 id boolean PRIMARY KEY DEFAULT true CHECK (id), \
+                 reason text NOT NULL, \
+                 since timestamptz NOT NULL, \
+                 until timestamptz NOT NULL \
+             )",
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(PostgresStoreError::Schema)
+    }
+
+    async fn observe(&self, config: &DeepCheckConfig) -> Result<Observations, sqlx::Error> {
+        // before querying, so the connection used by the check itself isn't counted
+        let pool_in_use = self.pool.size() - self.pool.num_idle() as u32;
+
+        let (in_recovery, read_only, replication_lag): (bool, bool, Option<f64>) = sqlx::query_as(
+            "SELECT pg_is_in_recovery(), \
+                        current_setting('transaction_read_only') = 'on', \
+                        EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp())::float8",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let (long_running_transactions, longest_transaction): (i64, Option<f64>) = sqlx::query_as(
+            "SELECT count(*), EXTRACT(EPOCH FROM max(now() - xact_start))::float8 \
+                 FROM pg_stat_activity \
+                 WHERE datname = current_database() \
+                   AND state <> 'idle' \
+                   AND xact_start < now() - make_interval(secs => $1)",
+        )
+        .bind(config.max_transaction_age.as_secs_f64())
+        .fetch_one(&self.pool)
+        .await?;
+
+        let migration = match config.expected_migration {
+            Some(_) => applied_migration(
+                sqlx::query_scalar("SELECT max(version) FROM _sqlx_migrations WHERE success")
+                    .fetch_one(&self.pool)
+                    .await,
+            )?,
+            None => None,
+        };
+
+        Ok(Observations {
+            in_recovery,
+            read_only,
+            replication_lag: replication_lag.and_then(seconds),
+            pool_in_use,
+            pool_max: self.max_connections,
+            migration,
+            long_running_transactions,
+            longest_transaction: longest_transaction.and_then(seconds),
+        })
+    }
+}
+
+// SQLSTATE of a missing table
+const UNDEFINED_TABLE: &str = "42P01";
+
+// A missing migrations table means no migration has been applied,
+// while any other failure, e.g. lacking privileges, fails the check
+fn applied_migration(
+    queried: Result<Option<i64>, sqlx::Error>,
+) -> Result<Option<i64>, sqlx::Error> {
+    match queried {
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNDEFINED_TABLE) => Ok(None),
+        queried => queried,
+    }
+}
+
+fn seconds(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs.max(0.0))
+        .ok()
+        .map(|d| Duration::from_millis(d.as_millis() as u64))
+}
+
+// What the deep check found out about the database
+#[derive(Debug, Default)]
+struct Observations {
+    in_recovery: bool,
+    read_only: bool,
+    replication_lag: Option<Duration>,
+    pool_in_use: u32,
+    pool_max: u32,
+    migration: Option<i64>,
+    long_running_transactions: i64,
+    longest_transaction: Option<Duration>,
+}
+impl Observations {
+    // Each finding degrades the database
+    fn findings(&self, config: &DeepCheckConfig) -> Vec<String> {
+        let mut findings = Vec::new();
+        if self.in_recovery && !config.allow_replica {
+            findings.push("Database is in recovery mode".to_string());
+        }
+        if !self.in_recovery && self.read_only {
+            findings.push("Database is read-only".to_string());
+        }
+        if let Some(lag) = self.replication_lag.filter(|lag| {
+            self.in_recovery && config.allow_replica && *lag > config.max_replication_lag
+        }) {
+            findings.push(format!(
+                "Replication lag of {lag:?} exceeds {:?}",
+                config.max_replication_lag
+            ));
+        }
+        if self.pool_max > 0
+            && f64::from(self.pool_in_use) / f64::from(self.pool_max) > config.max_pool_saturation
+        {
+            findings.push(format!(
+                "Pool saturated: {} of {} connections in use",
+                self.pool_in_use, self.pool_max
+            ));
+        }
+        if let Some(expected) = config.expected_migration {
+            match self.migration {
+                Some(current) if current >= expected => {}
+                Some(current) => findings.push(format!(
+                    "Schema migrations behind: at {current}, expected {expected}"
+                )),
+                None => findings.push(format!("No schema migrations applied, expected {expected}")),
+            }
+        }
+        if self.long_running_transactions > 0 {
+            findings.push(format!(
+                "{} transaction(s) running for more than {:?}, the longest for {:?}",
+                self.long_running_transactions,
+                config.max_transaction_age,
+                self.longest_transaction.unwrap_or_default()
+            ));
+        }
+        findings
+    }
+
+    fn details(&self) -> Vec<(&'static str, String)> {
+        let mut details = vec![
+            ("in_recovery", self.in_recovery.to_string()),
+            ("pool_in_use", self.pool_in_use.to_string()),
+            ("pool_max", self.pool_max.to_string()),
+        ];
+        if let Some(lag) = self.replication_lag.filter(|_| self.in_recovery) {
+            details.push(("replication_lag_ms", lag.as_millis().to_string()));
+        }
+        if let Some(migration) = self.migration {
+            details.push(("migration", migration.to_string()));
+        }
+        if self.long_running_transactions > 0 {
+            details.push((
+                "long_running_transactions",
+                self.long_running_transactions.to_string(),
+            ));
+        }
+        details
+    }
+}
+
+#[async_trait]
+impl Store for PostgresStore {
+    async fn close(&self) {
+        self.pool.close().await
+    }
+}
+
+#[async_trait]
+impl UptimeStore for PostgresStore {
+    async fn record(
+        &self,
+        at: DateTime<Utc>,
+        service_status: &ServiceStatus,
+    ) -> Result<(), UptimeError> {
+        // the overall status is recorded without a dependency
+        let (dependencies, oks): (Vec<Option<String>>, Vec<i32>) =
+            std::iter::once((None, service_status.status()))
+                .chain(
+                    service_status
+                        .dependencies()
+                        .iter()
+                        .map(|d| (Some(d.dependency().name().to_string()), d.status())),
+                )
+                .map(|(dependency, status)| (dependency, i32::from(*status == Status::Ok)))
+                .unzip();
+        sqlx::query(
+            "INSERT INTO health_samples (recorded_at, dependency, ok, total) \
+             SELECT $1, dependency, ok, 1 FROM UNNEST($2::text[], $3::int4[]) AS s(dependency, ok)",
+        )
+        .bind(at)
+        .bind(dependencies)
+        .bind(oks)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(uptime_error)
+    }
+
+    async fn availability(&self, since: DateTime<Utc>) -> Result<Vec<Availability>, UptimeError> {
+        let rows: Vec<(Option<String>, i64, i64)> = sqlx::query_as(
+            "SELECT dependency, sum(ok)::int8, sum(total)::int8 \
+             FROM health_samples \
+             WHERE recorded_at >= $1 \
+             GROUP BY dependency \
+             ORDER BY dependency NULLS FIRST",
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(uptime_error)?;
+        Ok(rows
+            .into_iter()
+            .map(|(dependency, ok, total)| {
+                Availability::new(dependency, ok.max(0) as u64, total.max(0) as u64)
+            })
+            .collect())
+    }
+
+    async fn compact(&self, now: DateTime<Utc>, config: &UptimeConfig) -> Result<(), UptimeError> {
+        let before = |age: Duration| now - chrono::Duration::from_std(age).unwrap_or_default();
+        sqlx::query("DELETE FROM health_samples WHERE recorded_at < $1")
+            .bind(before(*config.retention()))
+            .execute(&self.pool)
+            .await
+            .map_err(uptime_error)?;
+        // one statement, so samples can't be lost or counted twice
+        sqlx::query(
+            "WITH raw AS ( \
+                 DELETE FROM health_samples \
+                 WHERE NOT downsampled AND recorded_at < $1 \
+                 RETURNING recorded_at, dependency, ok, total \
+             ) \
+             INSERT INTO health_samples (recorded_at, dependency, ok, total, downsampled) \
+             SELECT date_trunc('hour', recorded_at), dependency, sum(ok), sum(total), true \
+             FROM raw \
+             GROUP BY 1, 2",
+        )
+        .bind(before(*config.downsample_after()))
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(uptime_error)
+    }
+}
+
+fn uptime_error(e: sqlx::Error) -> UptimeError {
+    UptimeError::Store(e.to_string())
+}
+
+#[async_trait]
+impl MaintenanceStore for PostgresStore {
+    async fn current(&self) -> Result<Option<Maintenance>, MaintenanceError> {
+        let row: Option<(String, DateTime<Utc>, DateTime<Utc>)> =
+            sqlx::query_as("SELECT reason, since, until FROM health_maintenance")
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(maintenance_error)?;
+        // rows were validated before being written
+        Ok(row.and_then(|(reason, since, until)| Maintenance::new(&reason, since, until).ok()))
+    }
+
+    async fn start(&self, maintenance: &Maintenance) -> Result<(), MaintenanceError> {
+        sqlx::query(
+            "INSERT INTO health_maintenance (reason, since, until) VALUES ($1, $2, $3) \
+             ON CONFLICT (id) DO UPDATE \
+             SET reason = excluded.reason, since = excluded.since, until = excluded.until",
+        )
+        .bind(maintenance.reason())
+        .bind(maintenance.since())
+        .bind(maintenance.until())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(maintenance_error)
+    }
 
-        Ok(PostgresStore { pool })
+    async fn end(&self) -> Result<(), MaintenanceError> {
+        sqlx::query("DELETE FROM health_maintenance")
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(maintenance_error)
     }
 }
 
-// TODO Think about errors
-#[derive(Debug, Display, Error, Getters)]
-pub struct PostgresStoreError {
-    #[getset(get)]
-    message: String,
+fn maintenance_error(e: sqlx::Error) -> MaintenanceError {
+    MaintenanceError::Store(e.to_string())
+}
+
+#[derive(Debug, Display, Error)]
+pub enum PostgresStoreError {
+    #[display("Failed to connect to the database: {_0}")]
+    Connection(#[error(source)] sqlx::Error),
+
+    #[display("Failed to prepare the database schema: {_0}")]
+    Schema(#[error(source)] sqlx::Error),
+}
+impl PostgresStoreError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PostgresStoreError::Connection(_) => "database_connection_failed",
+            PostgresStoreError::Schema(_) => "database_schema_failed",
+        }
+    }
 }
 
 #[async_trait]
 impl DependencyHealthChecker for PostgresStore {
+    fn dependency(&self) -> Dependency {
+        Dependency::new(&self.dependency_name, DependencyKind::Postgres)
+    }
+
     async fn check(&self) -> DependencyStatus {
-        let status = match sqlx::query("SELECT 42").fetch_one(&self.pool).await {
-            Ok(_) => Status::Ok,
-            Err(_) => Status::Degraded,
+        let Some(config) = &self.deep_check else {
+            return match sqlx::query("SELECT 42").fetch_one(&self.pool).await {
+                Ok(_) => DependencyStatus::new(self.dependency(), Status::Ok),
+                Err(e) => {
+                    DependencyStatus::new(self.dependency(), failed(&e)).with_reason(e.to_string())
+                }
+            };
         };
-        DependencyStatus::new(Dependency::Database, status)
+
+        match self.observe(config).await {
+            Ok(observations) => assess(self.dependency(), &observations, config),
+            Err(e) => {
+                DependencyStatus::new(self.dependency(), failed(&e)).with_reason(e.to_string())
+            }
+        }
+    }
+}
+
+// Down when the database can't be reached at all, Degraded when it can but queries fail
+fn failed(e: &sqlx::Error) -> Status {
+    match e {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed => Status::Down,
+        _ => Status::Degraded,
+    }
+}
+
+fn assess(
+    dependency: Dependency,
+    observations: &Observations,
+    config: &DeepCheckConfig,
+) -> DependencyStatus {
+    let findings = observations.findings(config);
+    let status = if findings.is_empty() {
+        DependencyStatus::new(dependency, Status::Ok)
+    } else {
+        DependencyStatus::new(dependency, Status::Degraded).with_reason(findings.join("; "))
+    };
+    observations
+        .details()
+        .into_iter()
+        .fold(status, |status, (key, value)| {
+            status.with_detail(key, value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_matches, assert_ok_eq};
+    use sqlx::error::{DatabaseError, ErrorKind};
+    use std::borrow::Cow;
+
+    #[test]
+    fn debug_redacts_password() {
+        let config = DatabaseConfig::new(
+            "localhost".to_string(),
+            5432,
+            "rustic".to_string(),
+            "rustic".to_string(),
+            "s3cr3t".to_string(),
+            4,
+        );
+
+        let debug = format!("{config:?}");
+
+        assert!(!debug.contains("s3cr3t"));
+        assert!(debug.contains("localhost"));
+    }
+
+    #[test]
+    fn healthy_primary_is_ok() {
+        let result = assess_with(Observations::default(), DeepCheckConfig::default());
+
+        assert_eq!(*result.status(), Status::Ok);
+        assert_eq!(result.diagnostics().details()["in_recovery"], "false");
+    }
+
+    #[test]
+    fn flags_recovery_mode_unless_replicas_are_allowed() {
+        let replica = || Observations {
+            in_recovery: true,
+            read_only: true,
+            replication_lag: Some(Duration::from_secs(1)),
+            ..Observations::default()
+        };
+
+        let primary_expected = assess_with(replica(), DeepCheckConfig::default());
+        let replica_allowed = assess_with(replica(), DeepCheckConfig::default().allowing_replica());
+
+        assert_eq!(
+            primary_expected.diagnostics().reason(),
+            Some("Database is in recovery mode")
+        );
+        assert_eq!(*replica_allowed.status(), Status::Ok);
+        assert_eq!(
+            replica_allowed.diagnostics().details()["replication_lag_ms"],
+            "1000"
+        );
+    }
+
+    #[test]
+    fn flags_read_only_primary() {
+        let observations = Observations {
+            read_only: true,
+            ..Observations::default()
+        };
+
+        let result = assess_with(observations, DeepCheckConfig::default());
+
+        assert_eq!(result.diagnostics().reason(), Some("Database is read-only"));
+    }
+
+    #[test]
+    fn flags_replication_lag_above_threshold() {
+        let observations = Observations {
+            in_recovery: true,
+            read_only: true,
+            replication_lag: Some(Duration::from_secs(45)),
+            ..Observations::default()
+        };
+
+        let result = assess_with(observations, DeepCheckConfig::default().allowing_replica());
+
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("Replication lag of 45s exceeds 30s")
+        );
+    }
+
+    #[test]
+    fn flags_pool_saturation() {
+        let observations = Observations {
+            pool_in_use: 5,
+            pool_max: 5,
+            ..Observations::default()
+        };
+
+        let result = assess_with(observations, DeepCheckConfig::default());
+
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("Pool saturated: 5 of 5 connections in use")
+        );
+    }
+
+    #[test]
+    fn flags_pending_migrations() {
+        let behind = Observations {
+            migration: Some(3),
+            ..Observations::default()
+        };
+
+        let result = assess_with(behind, DeepCheckConfig::default().expecting_migration(4));
+        let none_applied = assess_with(
+            Observations::default(),
+            DeepCheckConfig::default().expecting_migration(4),
+        );
+
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("Schema migrations behind: at 3, expected 4")
+        );
+        assert_eq!(
+            none_applied.diagnostics().reason(),
+            Some("No schema migrations applied, expected 4")
+        );
+    }
+
+    #[test]
+    fn reports_every_finding() {
+        let observations = Observations {
+            read_only: true,
+            long_running_transactions: 2,
+            longest_transaction: Some(Duration::from_secs(512)),
+            ..Observations::default()
+        };
+
+        let result = assess_with(observations, DeepCheckConfig::default());
+
+        assert_eq!(*result.status(), Status::Degraded);
+        assert_eq!(
+            result.diagnostics().reason(),
+            Some("Database is read-only; 2 transaction(s) running for more than 300s, the longest for 512s")
+        );
+        assert_eq!(
+            result.diagnostics().details()["long_running_transactions"],
+            "2"
+        );
+    }
+
+    fn assess_with(observations: Observations, config: DeepCheckConfig) -> DependencyStatus {
+        assess(
+            Dependency::new("database", DependencyKind::Postgres),
+            &observations,
+            &config,
+        )
+    }
+
+    #[test]
+    fn no_migration_applied_when_migrations_table_is_missing() {
+        let missing_table = Err(StubDatabaseError::with_code(UNDEFINED_TABLE));
+
+        assert_ok_eq!(applied_migration(missing_table), None);
+    }
+
+    #[test]
+    fn fails_when_migrations_cannot_be_read_otherwise() {
+        // insufficient privilege
+        let forbidden = Err(StubDatabaseError::with_code("42501"));
+
+        assert_matches!(
+            applied_migration(forbidden),
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("42501")
+        );
+        assert_matches!(
+            applied_migration(Err(sqlx::Error::PoolTimedOut)),
+            Err(sqlx::Error::PoolTimedOut)
+        );
+    }
+
+    #[derive(Debug, Display, Error)]
+    #[display("Stub database error {code}")]
+    struct StubDatabaseError {
+        #[error(not(source))]
+        code: &'static str,
+    }
+    impl StubDatabaseError {
+        fn with_code(code: &'static str) -> sqlx::Error {
+            sqlx::Error::Database(Box::new(StubDatabaseError { code }))
+        }
+    }
+    impl DatabaseError for StubDatabaseError {
+        fn message(&self) -> &str {
+            "Stub database error"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.code))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
     }
 }
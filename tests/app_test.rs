@@ -0,0 +1,158 @@
+use async_trait::async_trait;
+use rustic_sketch::app::App;
+use rustic_sketch::config::{CliArgs, Config};
+use rustic_sketch::health_check::service_status::{
+    Dependency, DependencyKind, DependencyStatus, Status,
+};
+use rustic_sketch::health_check::version::{
+    Build, Commit, Environment, Version, VersionLoadError, Versioned,
+};
+use rustic_sketch::health_check::DependencyHealthChecker;
+use rustic_sketch::store::Store;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn serves_status_on_an_ephemeral_port() {
+    let server = App::builder(config_with_ephemeral_port())
+        .versioned(StubVersion)
+        .dependency_health_checker(StubDependencyHealthChecker(Status::Degraded))
+        .build()
+        .start()
+        .unwrap();
+
+    let response = reqwest::get(format!("http://{}/status", server.local_addr()))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.json::<Value>().await.unwrap(),
+        json!({
+            "env": "dev",
+            "build": "snapshot",
+            "commit": "c11e2d041c9b4ca66e241f8429e9a2876a8e0b18",
+            "status": "Degraded",
+            "dependencies": [{ "database": "Degraded" }]
+        })
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn shutdown_stops_the_server_and_closes_the_store() {
+    let store = StubStore::default();
+    let server = App::builder(config_with_ephemeral_port())
+        .versioned(StubVersion)
+        .store(store.clone())
+        .build()
+        .start()
+        .unwrap();
+    let address = server.local_addr();
+
+    let summary = server.shutdown().await;
+
+    assert!(summary.drained());
+    assert!(store.closed.load(Ordering::SeqCst));
+    assert!(reqwest::get(format!("http://{address}/ping"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn records_maintenance_in_status_history() {
+    let config_file = std::env::temp_dir().join("rustic-sketch.app-test-maintenance.toml");
+    std::fs::write(&config_file, "[admin]\ntoken = \"s3cr3t\"\n").unwrap();
+    let config = Config::load(&CliArgs {
+        config: Some(config_file),
+        host: Some([127, 0, 0, 1].into()),
+        port: Some(0),
+        ..CliArgs::default()
+    })
+    .unwrap();
+    let server = App::builder(config)
+        .versioned(StubVersion)
+        .dependency_health_checker(StubDependencyHealthChecker(Status::Ok))
+        .build()
+        .start()
+        .unwrap();
+    let client = reqwest::Client::new();
+    let until = chrono::Utc::now() + chrono::Duration::hours(1);
+
+    let response = client
+        .put(format!("http://{}/admin/maintenance", server.local_addr()))
+        .bearer_auth("s3cr3t")
+        .json(&json!({ "reason": "Upgrading the database", "until": until }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let mut overall = Vec::new();
+    for _ in 0..50 {
+        let history = reqwest::get(format!("http://{}/status/history", server.local_addr()))
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap();
+        overall = history["transitions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|transition| transition.get("dependency").is_none())
+            .map(|transition| transition["to"].clone())
+            .collect();
+        if overall.contains(&json!("Maintenance")) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(overall.last(), Some(&json!("Maintenance")));
+    server.shutdown().await;
+}
+
+fn config_with_ephemeral_port() -> Config {
+    Config::load(&CliArgs {
+        host: Some([127, 0, 0, 1].into()),
+        port: Some(0),
+        ..CliArgs::default()
+    })
+    .unwrap()
+}
+
+struct StubVersion;
+#[async_trait]
+impl Versioned for StubVersion {
+    async fn version(&self) -> Result<Version, VersionLoadError> {
+        Ok(Version::new(
+            Environment::new("dev".to_string()),
+            Build::new("snapshot".to_string()),
+            Commit::new("c11e2d041c9b4ca66e241f8429e9a2876a8e0b18".to_string()),
+        ))
+    }
+}
+
+struct StubDependencyHealthChecker(Status);
+#[async_trait]
+impl DependencyHealthChecker for StubDependencyHealthChecker {
+    fn dependency(&self) -> Dependency {
+        Dependency::new("database", DependencyKind::Postgres)
+    }
+
+    async fn check(&self) -> DependencyStatus {
+        DependencyStatus::new(self.dependency(), self.0.clone())
+    }
+}
+
+#[derive(Clone, Default)]
+struct StubStore {
+    closed: Arc<AtomicBool>,
+}
+#[async_trait]
+impl Store for StubStore {
+    async fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst)
+    }
+}
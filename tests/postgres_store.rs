@@ -1,9 +1,19 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 
+use chrono::{Duration, Utc};
 use getset::Getters;
+use rustic_sketch::app::App;
+use rustic_sketch::config::{CliArgs, Config};
+use rustic_sketch::health_check::maintenance::{Maintenance, MaintenanceStore};
+use rustic_sketch::health_check::service_status::{
+    Dependency, DependencyKind, DependencyStatus, ServiceStatus,
+};
+use rustic_sketch::health_check::uptime::{Availability, UptimeConfig, UptimeStore};
+use rustic_sketch::health_check::version::{Build, Commit, Environment, Version};
 use rustic_sketch::health_check::{service_status::Status, DependencyHealthChecker};
 use rustic_sketch::store::postgres;
+use serde_json::{json, Value};
 use testcontainers::core::{Image, WaitFor};
 use testcontainers::runners::AsyncRunner;
 
@@ -31,7 +41,34 @@ async fn dependency_status_is_ok_when_database_is_available() {
 }
 
 #[tokio::test]
-async fn dependency_status_is_degraded_when_database_is_not_available() {
+async fn deep_check_reports_primary_diagnostics() {
+    let postgres = Postgres::default();
+    let container = postgres.clone().start().await.unwrap();
+    let exposed_port = container
+        .get_host_port_ipv4(*postgres.port())
+        .await
+        .unwrap();
+    let config = postgres::DatabaseConfig::new(
+        "127.0.0.1".to_string(),
+        exposed_port,
+        postgres.name().to_string(),
+        postgres.user().to_string(),
+        postgres.password().to_string(),
+        5,
+    );
+
+    let store = postgres::PostgresStore::new(config)
+        .await
+        .unwrap()
+        .with_deep_check(postgres::DeepCheckConfig::default());
+    let result = store.check().await;
+
+    assert_eq!(*result.status(), Status::Ok);
+    assert_eq!(result.diagnostics().details()["in_recovery"], "false");
+}
+
+#[tokio::test]
+async fn dependency_status_is_down_when_database_is_not_available() {
     let postgres = Postgres::default();
     let container = postgres.clone().start().await.unwrap();
     let exposed_port = container
@@ -52,7 +89,161 @@ async fn dependency_status_is_degraded_when_database_is_not_available() {
 
     let result = store.check().await;
 
-    assert_eq!(*result.status(), Status::Degraded);
+    assert_eq!(*result.status(), Status::Down);
+    assert!(result.diagnostics().reason().is_some());
+}
+
+#[tokio::test]
+async fn records_uptime_samples_and_downsamples_old_ones() {
+    let postgres = Postgres::default();
+    let container = postgres.clone().start().await.unwrap();
+    let exposed_port = container
+        .get_host_port_ipv4(*postgres.port())
+        .await
+        .unwrap();
+    let config = postgres::DatabaseConfig::new(
+        "127.0.0.1".to_string(),
+        exposed_port,
+        postgres.name().to_string(),
+        postgres.user().to_string(),
+        postgres.password().to_string(),
+        5,
+    );
+    let store = postgres::PostgresStore::new(config).await.unwrap();
+    store.prepare_uptime().await.unwrap();
+    // preparing is idempotent, as every instance does it on startup
+    store.prepare_uptime().await.unwrap();
+
+    let now = Utc::now();
+    for (age, status) in [
+        (Duration::days(3), Status::Degraded),
+        (Duration::days(2), Status::Ok),
+        (Duration::zero(), Status::Ok),
+    ] {
+        store.record(now - age, &status_of(status)).await.unwrap();
+    }
+    store.compact(now, &UptimeConfig::default()).await.unwrap();
+
+    let last_week = store.availability(now - Duration::days(7)).await.unwrap();
+    let last_hour = store.availability(now - Duration::hours(1)).await.unwrap();
+
+    assert_eq!(
+        last_week,
+        vec![
+            Availability::new(None, 2, 3),
+            Availability::new(Some("database".to_string()), 2, 3),
+        ]
+    );
+    assert_eq!(last_hour[0], Availability::new(None, 1, 1));
+}
+
+#[tokio::test]
+async fn shares_maintenance_across_instances() {
+    let postgres = Postgres::default();
+    let container = postgres.clone().start().await.unwrap();
+    let exposed_port = container
+        .get_host_port_ipv4(*postgres.port())
+        .await
+        .unwrap();
+    let config = postgres::DatabaseConfig::new(
+        "127.0.0.1".to_string(),
+        exposed_port,
+        postgres.name().to_string(),
+        postgres.user().to_string(),
+        postgres.password().to_string(),
+        5,
+    );
+    let instance = postgres::PostgresStore::new(config.clone()).await.unwrap();
+    let other_instance = postgres::PostgresStore::new(config).await.unwrap();
+    instance.prepare_maintenance().await.unwrap();
+    other_instance.prepare_maintenance().await.unwrap();
+
+    let now = Utc::now();
+    let first = Maintenance::new("Upgrading the database", now, now + Duration::hours(1)).unwrap();
+    let second = Maintenance::new("Rotating credentials", now, now + Duration::hours(2)).unwrap();
+    instance.start(&first).await.unwrap();
+    instance.start(&second).await.unwrap();
+    let during = other_instance.current().await.unwrap();
+    other_instance.end().await.unwrap();
+    let after = instance.current().await.unwrap();
+
+    assert_eq!(
+        during.map(|m| m.reason().clone()),
+        Some("Rotating credentials".to_string())
+    );
+    assert_eq!(after, None);
+}
+
+#[tokio::test]
+async fn serves_status_of_app_built_from_config() {
+    let postgres = Postgres::default();
+    let container = postgres.clone().start().await.unwrap();
+    let exposed_port = container
+        .get_host_port_ipv4(*postgres.port())
+        .await
+        .unwrap();
+    let config_file = std::env::temp_dir().join(format!(
+        "rustic-sketch.from-config-{}.toml",
+        std::process::id()
+    ));
+    std::fs::write(
+        &config_file,
+        format!(
+            r#"
+            [database]
+            host = "127.0.0.1"
+            port = {exposed_port}
+            name = "{}"
+            user = "{}"
+            password = "{}"
+
+            [health.uptime]
+            enabled = true
+
+            [admin]
+            token = "s3cr3t"
+            persist_maintenance = true
+            "#,
+            postgres.name(),
+            postgres.user(),
+            postgres.password()
+        ),
+    )
+    .unwrap();
+    let config = Config::load(&CliArgs {
+        config: Some(config_file.clone()),
+        host: Some([127, 0, 0, 1].into()),
+        port: Some(0),
+        ..CliArgs::default()
+    });
+    let _ = std::fs::remove_file(&config_file);
+
+    let server = App::from_config(config.unwrap())
+        .await
+        .unwrap()
+        .build()
+        .start()
+        .unwrap();
+    let status: Value = reqwest::get(format!("http://{}/status", server.local_addr()))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    server.shutdown().await;
+
+    assert_eq!(status["status"], "Ok");
+    assert_eq!(status["dependencies"], json!([{ "database": "Ok" }]));
+}
+
+fn status_of(status: Status) -> ServiceStatus {
+    let version = Version::new(
+        Environment::new("dev".to_string()),
+        Build::new("snapshot".to_string()),
+        Commit::new("66bf883e145315e5a304f6a1b69c4aaa22ae9305".to_string()),
+    );
+    let database = Dependency::new("database", DependencyKind::Postgres);
+    ServiceStatus::new(version, vec![DependencyStatus::new(database, status)])
 }
 
 #[derive(Clone, Getters)]
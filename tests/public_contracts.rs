@@ -4,7 +4,11 @@ mod test_kit;
 
 use std::fs;
 
-use rustic_sketch::routes::health_status::model::ServiceStatusPayload;
+use rustic_sketch::routes::error::model::ErrorPayload;
+use rustic_sketch::routes::health_status::model::{
+    ContractVersion, ServiceStatusPayload, StatusEventPayload, StatusHistoryPayload, UptimePayload,
+    VerboseServiceStatusPayload,
+};
 
 use test_kit::assert_bijective_relationship_between_encoder_and_decoder;
 use test_kit::TestResult;
@@ -14,20 +18,94 @@ fn status_ok_contract() -> TestResult {
     struct TestCase {
         sample: &'static str,
     }
-    let test_cases = vec![
+    let test_cases = [
         TestCase { sample: "ok" },
         //TestCase { sample: "bum" }, // Try it to see the error
         TestCase { sample: "degraded" },
+        TestCase {
+            sample: "all_dependencies",
+        },
+        TestCase {
+            sample: "downstream",
+        },
+        TestCase { sample: "v1_down" },
+        TestCase { sample: "v2_down" },
+        TestCase {
+            sample: "v2_maintenance",
+        },
     ];
     // note that `try_for_each` will interrupt the tests on the first error
     test_cases.iter().try_for_each(|case| {
-        let path_to_contract = format!(
-            "tests/resources/contracts/health_check/status_{}.json",
-            case.sample
-        );
-        let json = fs::read_to_string(&path_to_contract)
-            .expect(&format!("Could not read file `{}`", &path_to_contract));
+        let json = read_contract(&format!("status_{}.json", case.sample));
 
         assert_bijective_relationship_between_encoder_and_decoder::<ServiceStatusPayload>(&json)
     })
 }
+
+#[test]
+fn status_v1_contract_is_kept() -> TestResult {
+    // consumers of the first version of the contract only ever see Ok and Degraded
+    let samples = [
+        "ok",
+        "degraded",
+        "all_dependencies",
+        "downstream",
+        "v1_down",
+    ];
+    for sample in samples {
+        let json = read_contract(&format!("status_{sample}.json"));
+        let payload = serde_json::from_str::<ServiceStatusPayload>(&json)?;
+        let v1 = serde_json::from_str::<ServiceStatusPayload>(&json)?;
+
+        assert_eq!(v1.for_contract(ContractVersion::V1), payload);
+    }
+
+    let v2 = serde_json::from_str::<ServiceStatusPayload>(&read_contract("status_v2_down.json"))?;
+    let v1 = serde_json::from_str::<ServiceStatusPayload>(&read_contract("status_v1_down.json"))?;
+    assert_eq!(v2.for_contract(ContractVersion::V1), v1);
+    Ok(())
+}
+
+#[test]
+fn status_verbose_contract() -> TestResult {
+    let json = read_contract("status_verbose.json");
+
+    assert_bijective_relationship_between_encoder_and_decoder::<VerboseServiceStatusPayload>(&json)
+}
+
+#[test]
+fn status_history_contract() -> TestResult {
+    let json = read_contract("status_history.json");
+
+    assert_bijective_relationship_between_encoder_and_decoder::<StatusHistoryPayload>(&json)
+}
+
+#[test]
+fn status_uptime_contract() -> TestResult {
+    let json = read_contract("status_uptime.json");
+
+    assert_bijective_relationship_between_encoder_and_decoder::<UptimePayload>(&json)
+}
+
+#[test]
+fn status_event_contract() -> TestResult {
+    let json = read_contract("status_event.json");
+
+    assert_bijective_relationship_between_encoder_and_decoder::<StatusEventPayload>(&json)
+}
+
+#[test]
+fn error_contract() -> TestResult {
+    let samples = ["version_unavailable", "not_found"];
+    samples.iter().try_for_each(|sample| {
+        let json = read_contract(&format!("error_{}.json", sample));
+
+        assert_bijective_relationship_between_encoder_and_decoder::<ErrorPayload>(&json)
+    })
+}
+
+fn read_contract(file_name: &str) -> String {
+    let path_to_contract = format!("tests/resources/contracts/health_check/{file_name}");
+    fs::read_to_string(&path_to_contract)
+        .unwrap_or_else(|_| panic!("Could not read file `{}`", &path_to_contract))
+}
@@ -22,11 +22,11 @@ pub fn assert_bijective_relationship_between_encoder_and_decoder<'a, A>(
 where
     A: Serialize + Deserialize<'a> + PartialEq + std::fmt::Debug,
 {
-    let decoded = serde_json::from_str::<A>(&original)?;
+    let decoded = serde_json::from_str::<A>(original)?;
     dbg!(&decoded);
     let roundtrip = serde_json::to_string(&decoded)?;
 
-    Ok(assert_eq!(
+    assert_eq!(
         original
             .chars()
             .filter(|c| !c.is_whitespace())
@@ -35,5 +35,6 @@ where
             .chars()
             .filter(|c| !c.is_whitespace())
             .collect::<String>()
-    ))
+    );
+    Ok(())
 }
@@ -1,6 +1,6 @@
-use claims::assert_err;
+use claims::assert_matches;
 use rustic_sketch::health_check::version::{
-    Build, Commit, Environment, VersionFromFile, Versioned,
+    Build, Commit, Environment, VersionFromFile, VersionLoadError, Versioned,
 };
 use std::error::Error;
 use std::fs;
@@ -29,8 +29,7 @@ async fn version_returns_error_when_there_is_no_version_file() {
     let versioned = VersionFromFile::new(env.clone(), version_file_path);
     let result = versioned.version().await;
 
-    // TODO Check actual error
-    assert_err!(result);
+    assert_matches!(result, Err(VersionLoadError::Unreadable { .. }));
 }
 
 #[tokio::test]
@@ -41,7 +40,7 @@ async fn version_returns_error_when_version_file_is_empty() {
     let versioned = VersionFromFile::new(env.clone(), version_file_path.clone());
     let result = versioned.version().await;
 
-    assert_err!(result);
+    assert_matches!(result, Err(VersionLoadError::MissingBuild { .. }));
     fs::remove_file(&version_file_path).unwrap()
 }
 